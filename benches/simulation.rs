@@ -0,0 +1,181 @@
+use std::f32::consts::TAU;
+
+use barnes_hut::{quadtree::NodeOrder, utils, Body, Settings, Simulation};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use ultraviolet::Vec2;
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+const THETAS: [f32; 3] = [0.5, 1.0, 1.5];
+
+/// Restricted two-body problem of a light body circling a pinned mass.
+const CENTRAL_MASS: f32 = 1e4;
+const ORBIT_RADIUS: f32 = 100.0;
+const ORBIT_STEPS: usize = 2000;
+/// Largest accepted distance from the start after one period, relative to
+/// the radius, and relative change of the orbit's energy.
+const MAX_CLOSURE_ERROR: f32 = 1e-2;
+const MAX_ENERGY_DRIFT: f32 = 1e-2;
+
+fn simulation(n: usize, theta: f32) -> Simulation {
+    let settings = Settings {
+        body_count: n,
+        seed: 0,
+        theta,
+        ..Default::default()
+    };
+    Simulation::new(&settings)
+}
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    for n in SIZES {
+        let mut simulation = simulation(n, 1.0);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            b.iter(|| simulation.build())
+        });
+    }
+    group.finish();
+}
+
+fn step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+    group.sample_size(10);
+    for n in SIZES {
+        for theta in THETAS {
+            let mut simulation = simulation(n, theta);
+            let id = BenchmarkId::new(format!("theta={}", theta), n);
+            group.bench_with_input(id, &n, |b, _| b.iter(|| simulation.step()));
+        }
+    }
+    group.finish();
+}
+
+/// Steps the same disc in the generated order, which is sorted by radius,
+/// and shuffled, to show what the memory layout of the bodies is worth.
+fn order(c: &mut Criterion) {
+    let mut group = c.benchmark_group("order");
+    group.sample_size(10);
+    let n = SIZES[SIZES.len() - 1];
+
+    let mut generated = simulation(n, 1.0);
+    group.bench_function(BenchmarkId::new("generated", n), |b| {
+        b.iter(|| generated.step())
+    });
+
+    let mut shuffled = simulation(n, 1.0);
+    let mut bodies = shuffled.bodies().to_vec();
+    utils::shuffle_bodies(&mut bodies, 0);
+    shuffled.reset(bodies);
+    group.bench_function(BenchmarkId::new("shuffled", n), |b| {
+        b.iter(|| shuffled.step())
+    });
+    group.finish();
+}
+
+/// Walks the same tree laid out in each `NodeOrder`, after checking they
+/// all give exactly the same accelerations.
+fn layout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("layout");
+    group.sample_size(10);
+    let n = SIZES[SIZES.len() - 1];
+
+    let mut reference = None;
+    for order in [
+        NodeOrder::Insertion,
+        NodeOrder::DepthFirst,
+        NodeOrder::BreadthFirst,
+    ] {
+        let mut simulation = simulation(n, 1.0);
+        simulation.quadtree.node_order = order;
+        simulation.build();
+        let walk = |simulation: &Simulation| -> Vec<Vec2> {
+            let quadtree = simulation.quadtree();
+            simulation
+                .bodies()
+                .iter()
+                .map(|body| quadtree.acc(body.pos))
+                .collect()
+        };
+
+        let accs = walk(&simulation);
+        match &reference {
+            None => reference = Some(accs),
+            Some(reference) => assert!(
+                *reference == accs,
+                "{:?} layout changes the accelerations",
+                order
+            ),
+        }
+        group.bench_function(BenchmarkId::new(format!("{:?}", order), n), |b| {
+            b.iter(|| walk(&simulation))
+        });
+    }
+    group.finish();
+}
+
+/// A light body on a circular orbit around a pinned central mass, with a
+/// timestep of exactly one period per `ORBIT_STEPS` steps.
+fn circular_orbit() -> Simulation {
+    let mut simulation = simulation(0, 1.0);
+    let e_sq = simulation.quadtree.e_sq;
+
+    let mut center = Body::new(Vec2::zero(), Vec2::zero(), CENTRAL_MASS, 1.0);
+    center.fixed = true;
+    // Circular speed in the softened potential
+    let r = ORBIT_RADIUS;
+    let speed = (CENTRAL_MASS * r * r / (r * r + e_sq).powf(1.5)).sqrt();
+    let body = Body::new(Vec2::new(r, 0.0), Vec2::new(0.0, speed), 1e-6, 0.1);
+
+    simulation.reset(vec![center, body]);
+    simulation.dt = TAU * r / speed / ORBIT_STEPS as f32;
+    simulation.attract();
+    simulation
+}
+
+/// Specific energy of the orbiting body in the softened potential of the
+/// central mass.
+fn orbit_energy(simulation: &Simulation) -> f32 {
+    let body = &simulation.bodies()[1];
+    let e_sq = simulation.quadtree.e_sq;
+    0.5 * body.vel.mag_sq() - CENTRAL_MASS / (body.pos.mag_sq() + e_sq).sqrt()
+}
+
+/// Integrates the orbit for one period and checks how well it closes.
+fn orbit(c: &mut Criterion) {
+    let mut simulation = circular_orbit();
+    let start = simulation.bodies()[1].pos;
+    let energy = orbit_energy(&simulation);
+    for _ in 0..ORBIT_STEPS {
+        simulation.step();
+    }
+    let closure = (simulation.bodies()[1].pos - start).mag() / ORBIT_RADIUS;
+    let drift = ((orbit_energy(&simulation) - energy) / energy).abs();
+    println!(
+        "orbit: closure error {:.3e}, energy drift {:.3e} after one period",
+        closure, drift
+    );
+    assert!(
+        closure < MAX_CLOSURE_ERROR,
+        "closure error {} exceeds {}",
+        closure,
+        MAX_CLOSURE_ERROR
+    );
+    assert!(
+        drift < MAX_ENERGY_DRIFT,
+        "energy drift {} exceeds {}",
+        drift,
+        MAX_ENERGY_DRIFT
+    );
+
+    c.bench_function("orbit", |b| {
+        b.iter(|| {
+            let mut simulation = circular_orbit();
+            for _ in 0..ORBIT_STEPS {
+                simulation.step();
+            }
+        })
+    });
+}
+
+criterion_group!(benches, build, step, order, layout, orbit);
+criterion_main!(benches);
@@ -1,22 +1,39 @@
+use serde::{Deserialize, Serialize};
 use ultraviolet::Vec2;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Body {
+    /// Stable identifier, unique within a simulation.
+    pub id: u64,
     pub pos: Vec2,
     pub vel: Vec2,
     pub acc: Vec2,
     pub mass: f32,
     pub radius: f32,
+    pub charge: f32,
+    pub is_sink: bool,
+    /// Pinned bodies attract others but are never moved themselves.
+    pub fixed: bool,
+    /// Drawn color when the renderer colors bodies individually.
+    pub color: [u8; 4],
+    /// Group the body belongs to, like the galaxy it started in.
+    pub population: u8,
 }
 
 impl Body {
     pub fn new(pos: Vec2, vel: Vec2, mass: f32, radius: f32) -> Self {
         Self {
+            id: 0,
             pos,
             vel,
             acc: Vec2::zero(),
             mass,
             radius,
+            charge: 0.0,
+            is_sink: false,
+            fixed: false,
+            color: [0xff; 4],
+            population: 0,
         }
     }
 
@@ -0,0 +1,79 @@
+use crate::body::Body;
+
+use image::{Rgba, RgbaImage};
+use ultraviolet::Vec2;
+
+/// Camera mapping world space onto an image, matching the renderer's view.
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    pub pos: Vec2,
+    pub scale: f32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl View {
+    pub fn world_to_pixel(&self, world: Vec2) -> Vec2 {
+        let height = self.height as f32;
+        let view = (world - self.pos) / self.scale;
+        let x = (view.x + self.width as f32 / height) * height * 0.5;
+        let y = (1.0 - view.y) * height * 0.5;
+        Vec2::new(x, y)
+    }
+
+    pub fn pixels_per_world(&self) -> f32 {
+        self.height as f32 * 0.5 / self.scale
+    }
+}
+
+/// Draws every body as a filled disc of `radius(body)` world units.
+pub fn rasterize_bodies(
+    bodies: &[Body],
+    view: &View,
+    radius: impl Fn(&Body) -> f32,
+    background: [u8; 4],
+    foreground: [u8; 4],
+) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(view.width, view.height, Rgba(background));
+
+    for body in bodies {
+        let center = view.world_to_pixel(body.pos);
+        let r = (radius(body) * view.pixels_per_world()).max(0.5);
+
+        let x0 = (center.x - r).floor().max(0.0) as u32;
+        let y0 = (center.y - r).floor().max(0.0) as u32;
+        let x1 = ((center.x + r).ceil().max(0.0) as u32).min(view.width);
+        let y1 = ((center.y + r).ceil().max(0.0) as u32).min(view.height);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let d = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - center;
+                if d.mag_sq() <= r * r {
+                    image.put_pixel(x, y, Rgba(foreground));
+                }
+            }
+        }
+    }
+
+    image
+}
+
+/// What the cells of `Quadtree::render_to_image` are colored by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellColoring {
+    Depth,
+    /// Logarithm of the mass in each leaf.
+    Mass,
+}
+
+/// Dark blue to yellow for `t` in `0..=1`, brighter meaning deeper or heavier.
+pub fn ramp(t: f32) -> [u8; 4] {
+    let t = t.clamp(0.0, 1.0);
+    let lerp = |a: f32, b: f32| (a + (b - a) * t) as u8;
+    [
+        lerp(20.0, 255.0),
+        lerp(30.0, 220.0),
+        lerp(110.0, 60.0),
+        0xff,
+    ]
+}
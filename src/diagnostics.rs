@@ -0,0 +1,94 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::simulation::Simulation;
+
+pub const HEADER: &str =
+    "step,time,kinetic,potential,total_energy,momentum_x,momentum_y,angular_momentum,virial_ratio";
+
+/// Conserved quantities of a run written as CSV rows every `stride` frames,
+/// to plot how well a setup conserves them.
+pub struct DiagnosticsLog {
+    writer: BufWriter<File>,
+    stride: usize,
+}
+
+impl DiagnosticsLog {
+    /// Creates the file at `path` and writes the header.
+    pub fn create(path: impl AsRef<Path>, stride: usize) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+        Ok(Self {
+            writer,
+            stride: stride.max(1),
+        })
+    }
+
+    /// Appends a row if the current frame is due.
+    pub fn record(&mut self, simulation: &Simulation) -> io::Result<()> {
+        if simulation.frame % self.stride != 0 {
+            return Ok(());
+        }
+
+        let kinetic = simulation.kinetic_energy();
+        let potential = simulation.potential_energy();
+        let momentum = simulation.total_momentum();
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{},{},{}",
+            simulation.frame,
+            simulation.time,
+            kinetic,
+            potential,
+            kinetic + potential,
+            momentum.x,
+            momentum.y,
+            simulation.total_angular_momentum(),
+            2.0 * kinetic / potential.abs(),
+        )
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    #[test]
+    fn log_writes_a_header_and_one_row_per_stride() {
+        let settings = Settings {
+            body_count: 50,
+            ..Default::default()
+        };
+        let mut simulation = Simulation::new(&settings);
+        let path = std::env::temp_dir().join("barnes-hut-diagnostics-test.csv");
+        let mut log = DiagnosticsLog::create(&path, 5).unwrap();
+        log.record(&simulation).unwrap();
+        for _ in 0..20 {
+            simulation.step();
+            log.record(&simulation).unwrap();
+        }
+        log.finish().unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(HEADER));
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+        let steps: Vec<&str> = rows.iter().map(|row| row[0]).collect();
+        assert_eq!(steps, ["0", "5", "10", "15", "20"]);
+
+        let columns = HEADER.split(',').count();
+        for row in &rows {
+            assert_eq!(row.len(), columns);
+            assert!(row.iter().all(|value| value.parse::<f32>().is_ok()));
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{
+    body::Body,
+    quadtree::{Quad, Quadtree},
+};
+
+use ultraviolet::Vec2;
+
+/// Average number of bodies per cell the grid is sized for.
+pub const BODIES_PER_CELL: usize = 16;
+
+/// Uniform grid of body buckets. Bodies in the 3×3 block of cells around a
+/// body interact with it directly, every other cell only through a tree
+/// over the cell monopoles.
+#[derive(Clone, Debug)]
+pub struct Grid {
+    pub origin: Vec2,
+    pub cell_size: f32,
+    /// Cells along each side.
+    pub dims: usize,
+    /// Tree over the center of mass of every non-empty cell.
+    pub far: Quadtree,
+    cell_offsets: Vec<usize>,
+    cell_bodies: Vec<usize>,
+}
+
+impl Grid {
+    pub fn new(theta: f32, epsilon: f32) -> Self {
+        Self {
+            origin: Vec2::zero(),
+            cell_size: 1.0,
+            dims: 1,
+            far: Quadtree::new(theta, epsilon),
+            cell_offsets: Vec::new(),
+            cell_bodies: Vec::new(),
+        }
+    }
+
+    /// Cell coordinates of `pos`, clamped to the grid.
+    pub fn cell(&self, pos: Vec2) -> (usize, usize) {
+        let p = (pos - self.origin) / self.cell_size;
+        let max = (self.dims - 1) as f32;
+        (
+            p.x.floor().clamp(0.0, max) as usize,
+            p.y.floor().clamp(0.0, max) as usize,
+        )
+    }
+
+    /// Indices into the built-from bodies of those in cell `(x, y)`.
+    pub fn cell_bodies(&self, x: usize, y: usize) -> &[usize] {
+        let cell = y * self.dims + x;
+        &self.cell_bodies[self.cell_offsets[cell]..self.cell_offsets[cell + 1]]
+    }
+
+    pub fn build(&mut self, bodies: &[Body], source: impl Fn(&Body) -> f32) {
+        let quad = Quad::new_containing(bodies);
+        self.dims = ((bodies.len() / BODIES_PER_CELL) as f32)
+            .sqrt()
+            .ceil()
+            .max(1.0) as usize;
+        self.cell_size = quad.size / self.dims as f32;
+        self.origin = quad.center - Vec2::broadcast(quad.size * 0.5);
+
+        // Counting sort of the bodies by cell
+        let cells: Vec<usize> = bodies
+            .iter()
+            .map(|body| {
+                let (x, y) = self.cell(body.pos);
+                y * self.dims + x
+            })
+            .collect();
+        self.cell_offsets.clear();
+        self.cell_offsets.resize(self.dims * self.dims + 1, 0);
+        for &cell in &cells {
+            self.cell_offsets[cell + 1] += 1;
+        }
+        for i in 1..self.cell_offsets.len() {
+            self.cell_offsets[i] += self.cell_offsets[i - 1];
+        }
+        let mut fill = self.cell_offsets.clone();
+        self.cell_bodies.clear();
+        self.cell_bodies.resize(bodies.len(), 0);
+        for (index, &cell) in cells.iter().enumerate() {
+            self.cell_bodies[fill[cell]] = index;
+            fill[cell] += 1;
+        }
+
+        self.far.clear(quad);
+        for cell in 0..self.dims * self.dims {
+            let members = &self.cell_bodies[self.cell_offsets[cell]..self.cell_offsets[cell + 1]];
+            let mut pos = Vec2::zero();
+            let mut mass = 0.0;
+            let mut abs_mass = 0.0;
+            for &i in members {
+                let s = source(&bodies[i]);
+                pos += bodies[i].pos * s.abs();
+                mass += s;
+                abs_mass += s.abs();
+            }
+            if abs_mass > 0.0 {
+                self.far.insert(pos / abs_mass, mass);
+            }
+        }
+        self.far.propagate();
+    }
+
+    /// Field at `bodies[index]`, which must be the bodies the grid was built
+    /// from. Uses the same softened kernel as the tree.
+    pub fn field(&self, index: usize, bodies: &[Body], source: impl Fn(&Body) -> f32) -> Vec2 {
+        let pos = bodies[index].pos;
+        let (cx, cy) = self.cell(pos);
+        let x0 = cx.saturating_sub(1);
+        let y0 = cy.saturating_sub(1);
+        let x1 = (cx + 1).min(self.dims - 1);
+        let y1 = (cy + 1).min(self.dims - 1);
+
+        let e_sq = self.far.e_sq;
+        let mut field = Vec2::zero();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                for &j in self.cell_bodies(x, y) {
+                    let d = bodies[j].pos - pos;
+                    let d_sq = d.mag_sq();
+                    if j == index || d_sq == 0.0 {
+                        continue;
+                    }
+                    let r_sq = d_sq.max(self.far.min_d_sq);
+                    field += d * (source(&bodies[j]) / ((r_sq + e_sq) * r_sq.sqrt()));
+                }
+            }
+        }
+
+        let min = self.origin + Vec2::new(x0 as f32, y0 as f32) * self.cell_size;
+        let max = self.origin + Vec2::new((x1 + 1) as f32, (y1 + 1) as f32) * self.cell_size;
+        let near = |p: Vec2| {
+            let (x, y) = self.cell(p);
+            (x0..=x1).contains(&x) && (y0..=y1).contains(&y)
+        };
+        field + self.far.acc_outside(pos, min, max, near)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{self, random_bodies};
+
+    #[test]
+    fn every_body_is_bucketed_once_in_its_cell() {
+        let bodies = random_bodies(1000, 0);
+        let mut grid = Grid::new(1.0, 1.0);
+        grid.build(&bodies, |body| body.mass);
+
+        let mut seen = vec![0; bodies.len()];
+        for y in 0..grid.dims {
+            for x in 0..grid.dims {
+                for &i in grid.cell_bodies(x, y) {
+                    assert_eq!(grid.cell(bodies[i].pos), (x, y));
+                    seen[i] += 1;
+                }
+            }
+        }
+        assert!(seen.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn field_stays_close_to_direct_summation() {
+        for seed in 0..3 {
+            let bodies = random_bodies(1000, seed);
+            let mut grid = Grid::new(0.5, 1.0);
+            grid.build(&bodies, |body| body.mass);
+
+            let errors: Vec<f32> = (0..bodies.len())
+                .map(|i| {
+                    let exact = utils::direct_field(&bodies, bodies[i].pos, grid.far.e_sq);
+                    (grid.field(i, &bodies, |body| body.mass) - exact).mag() / exact.mag()
+                })
+                .collect();
+            let mean = errors.iter().sum::<f32>() / errors.len() as f32;
+            assert!(mean < 0.05, "seed {}: mean error {}", seed, mean);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+pub mod body;
+pub mod capture;
+pub mod diagnostics;
+pub mod grid;
+pub mod quadtree;
+pub mod replay;
+pub mod rewind;
+pub mod settings;
+pub mod simulation;
+pub mod units;
+pub mod utils;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use body::Body;
+pub use quadtree::{Node, Quad, Quadtree};
+pub use settings::Settings;
+pub use simulation::Simulation;
@@ -1,40 +1,423 @@
-use std::sync::atomic::Ordering;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
 
-mod body;
-mod quadtree;
 mod renderer;
-mod simulation;
-mod utils;
 
+use barnes_hut::{
+    diagnostics::DiagnosticsLog,
+    replay::CommandLog,
+    settings,
+    simulation::{Decomposition, ForceMethod, SimCommand, SofteningMode},
+    utils::{self, Summary},
+    Settings, Simulation,
+};
 use renderer::Renderer;
-use simulation::Simulation;
+
+/// Command line options, everything else comes from the settings file.
+#[derive(Default)]
+struct Args {
+    /// Where to write the log of every applied command.
+    record_commands: Option<PathBuf>,
+    /// Command log to apply on the same frames again.
+    replay: Option<PathBuf>,
+    /// Runs without a window, writing conserved quantities to this CSV.
+    diagnostics: Option<PathBuf>,
+    /// Frames between diagnostics rows.
+    diagnostics_stride: usize,
+    /// Length of a run without a window.
+    steps: usize,
+    /// Prints the force error and time at several values of theta instead.
+    theta_sweep: bool,
+    /// Prints the speedup of each phase on all threads over one instead.
+    parallel_bench: bool,
+}
+
+/// Values of theta `--theta-sweep` measures.
+const SWEEP_THETAS: [f32; 8] = [0.2, 0.3, 0.5, 0.7, 1.0, 1.2, 1.5, 2.0];
+/// Most bodies the sweep sums the exact field for, strided past this.
+const SWEEP_SAMPLES: usize = 1000;
+/// Force passes timed per theta, the fastest counts.
+const SWEEP_REPEATS: usize = 3;
+
+/// Most bodies `--parallel-bench` sums the direct force for, which takes
+/// O(n²) on a single thread.
+#[cfg(feature = "parallel")]
+const BENCH_DIRECT_BODIES: usize = 20000;
+/// Passes timed per phase and thread count, the fastest counts.
+#[cfg(feature = "parallel")]
+const BENCH_REPEATS: usize = 3;
+
+impl Args {
+    fn parse() -> Self {
+        let mut args = Self {
+            diagnostics_stride: 1,
+            steps: 1000,
+            ..Self::default()
+        };
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--record-commands" => args.record_commands = iter.next().map(PathBuf::from),
+                "--replay" => args.replay = iter.next().map(PathBuf::from),
+                "--diagnostics" => args.diagnostics = iter.next().map(PathBuf::from),
+                "--diagnostics-stride" => {
+                    args.diagnostics_stride = parse_next(&mut iter, args.diagnostics_stride)
+                }
+                "--steps" => args.steps = parse_next(&mut iter, args.steps),
+                "--theta-sweep" => args.theta_sweep = true,
+                "--parallel-bench" => args.parallel_bench = true,
+                _ => eprintln!("Ignoring unknown argument {}", arg),
+            }
+        }
+        args
+    }
+}
+
+fn parse_next<T: std::str::FromStr>(iter: &mut impl Iterator<Item = String>, default: T) -> T {
+    let Some(arg) = iter.next() else {
+        return default;
+    };
+    arg.parse().unwrap_or_else(|_| {
+        eprintln!("Ignoring invalid value {}", arg);
+        default
+    })
+}
+
+/// Whether a run told to stop at step `target` has got there by `frame`.
+fn reached(target: Option<usize>, frame: usize) -> bool {
+    target.is_some_and(|target| frame >= target)
+}
+
+/// Pauses the run and shows why if the step that took the total energy
+/// from `before` to `energy` went unstable. Returns whether it did.
+fn pause_if_unstable(simulation: &Simulation, before: f32, energy: f32, max_change: f32) -> bool {
+    let Some(instability) = simulation.instability(before, energy, max_change) else {
+        return false;
+    };
+    renderer::PAUSED.store(true, Ordering::Relaxed);
+    renderer::STATS.lock().instability = Some(instability);
+    true
+}
 
 fn main() {
+    let args = Args::parse();
+    if args.theta_sweep {
+        let settings = Settings::load(settings::SETTINGS_PATH);
+        theta_sweep(&mut Simulation::new(&settings));
+        return;
+    }
+    if args.parallel_bench {
+        #[cfg(feature = "parallel")]
+        parallel_bench(&mut Simulation::new(&Settings::load(
+            settings::SETTINGS_PATH,
+        )));
+        #[cfg(not(feature = "parallel"))]
+        eprintln!("--parallel-bench needs the parallel feature");
+        return;
+    }
+    if let Some(path) = &args.diagnostics {
+        let settings = Settings::load(settings::SETTINGS_PATH);
+        let mut simulation = Simulation::new(&settings);
+        if let Err(err) = run_diagnostics(&mut simulation, path, &args) {
+            eprintln!("Failed to write {}: {}", path.display(), err);
+        }
+        return;
+    }
+
     let config = quarkstrom::Config {
         window_mode: quarkstrom::WindowMode::Windowed(900, 900),
     };
 
-    let mut simulation = Simulation::new();
-
-    std::thread::spawn(move || {
-	    loop {
-	        if renderer::PAUSED.load(Ordering::Relaxed) {
-	            std::thread::yield_now();
-	        } else {
-	            simulation.step();
-	        }
-	        render(&mut simulation);
-	    }
+    let settings = Settings::load(settings::SETTINGS_PATH);
+    let mut simulation = Simulation::new(&settings);
+    renderer::apply_settings(settings);
+
+    let mut log = CommandLog::default();
+    let mut replay = args.replay.map(|path| {
+        CommandLog::load(&path).unwrap_or_else(|err| {
+            eprintln!("Failed to load {}: {}", path.display(), err);
+            CommandLog::default()
+        })
+    });
+
+    let handle = std::thread::spawn(move || loop {
+        let steps = renderer::STEPS_PER_FRAME.load(Ordering::Relaxed);
+        if renderer::PAUSED.load(Ordering::Relaxed) {
+            // Woken right away on unpause, the timeout keeps edits flowing
+            std::thread::park_timeout(Duration::from_millis(10));
+        } else if steps == 0 {
+            std::thread::sleep(Duration::from_millis(10));
+        } else {
+            let start = Instant::now();
+            let guard = *renderer::PAUSE_ON_INSTABILITY.lock();
+            let mut energy = guard.map(|_| simulation.total_energy());
+            let run_until = *renderer::RUN_UNTIL.lock();
+            for _ in 0..steps {
+                if reached(run_until, simulation.frame) {
+                    renderer::PAUSED.store(true, Ordering::Relaxed);
+                    *renderer::RUN_UNTIL.lock() = None;
+                    break;
+                }
+                if let Some(replay) = &mut replay {
+                    replay.replay(&mut simulation);
+                    if let (Some(_), Some(energy)) = (guard, &mut energy) {
+                        // Replayed commands may add or remove energy
+                        *energy = simulation.total_energy();
+                    }
+                }
+                simulation.step();
+
+                if let (Some(max_change), Some(energy)) = (guard, &mut energy) {
+                    let before = *energy;
+                    *energy = simulation.total_energy();
+                    if pause_if_unstable(&simulation, before, *energy, max_change) {
+                        break;
+                    }
+                }
+            }
+
+            // Slow motion
+            let delay = renderer::STEP_DELAY_MS.load(Ordering::Relaxed);
+            if delay > 0 {
+                std::thread::sleep(Duration::from_millis(delay as u64));
+            }
+
+            let sps = renderer::MAX_STEPS_PER_SECOND.load(Ordering::Relaxed);
+            if sps > 0 {
+                let budget = Duration::from_secs_f64(steps as f64 / sps as f64);
+                if let Some(rest) = budget.checked_sub(start.elapsed()) {
+                    std::thread::sleep(rest);
+                }
+            }
+        }
+        if let Some(replay) = &mut replay {
+            replay.replay(&mut simulation);
+        }
+        render(&mut simulation, &mut log, args.record_commands.as_deref());
     });
+    let _ = renderer::SIMULATION_THREAD.set(handle.thread().clone());
 
     quarkstrom::run::<Renderer>(config);
 }
 
-fn render(simulation: &mut Simulation) {
+/// Steps `simulation` without a window, recording its conserved quantities.
+fn run_diagnostics(simulation: &mut Simulation, path: &Path, args: &Args) -> io::Result<()> {
+    let mut log = DiagnosticsLog::create(path, args.diagnostics_stride)?;
+    log.record(simulation)?;
+    for _ in 0..args.steps {
+        simulation.step();
+        log.record(simulation)?;
+    }
+    log.finish()
+}
+
+/// Prints a table of the relative error of the tree field against direct
+/// summation and of the time the force phase takes, for each of
+/// `SWEEP_THETAS` on the same bodies.
+fn theta_sweep(simulation: &mut Simulation) {
+    simulation.method = ForceMethod::BarnesHut;
+    simulation.decomposition = Decomposition::Quadtree;
+    simulation.gravity_enabled = true;
+    // The tree alone has to hold the whole field
+    simulation.direct_anchors = false;
+    simulation.attract();
+    let stride = simulation.bodies.len().div_ceil(SWEEP_SAMPLES).max(1);
+    let e_sq = simulation.quadtree.e_sq;
+    let exact: Vec<_> = simulation
+        .bodies
+        .iter()
+        .step_by(stride)
+        .map(|body| {
+            (
+                body.pos,
+                utils::direct_field(&simulation.bodies, body.pos, e_sq),
+            )
+        })
+        .collect();
+
+    println!("theta,mean_err,max_err,force_ms");
+    for theta in SWEEP_THETAS {
+        simulation.quadtree.t_sq = theta * theta;
+        let mut force = Duration::MAX;
+        for _ in 0..SWEEP_REPEATS {
+            let start = Instant::now();
+            simulation.attract();
+            force = force.min(start.elapsed());
+        }
+
+        let (mut sum, mut max) = (0.0, 0.0f32);
+        for &(pos, exact) in &exact {
+            let approx = simulation.quadtree.acc(pos);
+            let error = (approx - exact).mag() / exact.mag().max(f32::MIN_POSITIVE);
+            sum += error;
+            max = max.max(error);
+        }
+        println!(
+            "{},{:.3e},{:.3e},{:.3}",
+            theta,
+            sum / exact.len().max(1) as f32,
+            max,
+            force.as_secs_f64() * 1000.0
+        );
+    }
+}
+
+/// Times the hot phases on a single thread and on the whole rayon pool and
+/// prints the speedup, along with its share of the thread count as the
+/// core utilization. Phases near 1x are serial and bound the others.
+#[cfg(feature = "parallel")]
+fn parallel_bench(simulation: &mut Simulation) {
+    fn fastest(simulation: &mut Simulation, phase: fn(&mut Simulation)) -> f64 {
+        let mut time = Duration::MAX;
+        for _ in 0..BENCH_REPEATS {
+            let start = Instant::now();
+            phase(simulation);
+            time = time.min(start.elapsed());
+        }
+        time.as_secs_f64()
+    }
+
+    simulation.decomposition = Decomposition::Quadtree;
+    simulation.gravity_enabled = true;
+    let mut phases: Vec<(&str, fn(&mut Simulation))> = vec![
+        ("build", |simulation| simulation.build()),
+        ("barnes_hut", |simulation| {
+            simulation.method = ForceMethod::BarnesHut;
+            simulation.attract();
+        }),
+    ];
+    let n = simulation.bodies.len();
+    if n <= BENCH_DIRECT_BODIES {
+        phases.push(("direct", |simulation| {
+            simulation.method = ForceMethod::Direct;
+            simulation.attract();
+        }));
+    } else {
+        eprintln!("Skipping direct above {} bodies", BENCH_DIRECT_BODIES);
+    }
+
+    let single = match rayon::ThreadPoolBuilder::new().num_threads(1).build() {
+        Ok(pool) => pool,
+        Err(err) => {
+            eprintln!("Failed to start a single thread: {}", err);
+            return;
+        }
+    };
+    let threads = rayon::current_num_threads();
+    eprintln!("{} bodies on {} threads", n, threads);
+    println!("phase,serial_ms,parallel_ms,speedup,utilization");
+    for (name, phase) in phases {
+        let serial = single.install(|| fastest(simulation, phase));
+        let parallel = fastest(simulation, phase);
+        let speedup = serial / parallel;
+        println!(
+            "{},{:.3},{:.3},{:.2},{:.2}",
+            name,
+            serial * 1000.0,
+            parallel * 1000.0,
+            speedup,
+            speedup / threads as f64
+        );
+    }
+}
+
+fn render(simulation: &mut Simulation, log: &mut CommandLog, record: Option<&Path>) {
     let mut lock = renderer::UPDATE_LOCK.lock();
-    for body in renderer::SPAWN.lock().drain(..) {
-        simulation.bodies.push(body);
+    simulation.force_kind = *renderer::FORCE_KIND.lock();
+    simulation.quadtree.multipole_order = *renderer::MULTIPOLE_ORDER.lock();
+    simulation.quadtree.opening_criterion = *renderer::OPENING_CRITERION.lock();
+    let theta = *renderer::THETA.lock();
+    simulation.quadtree.t_sq = theta * theta;
+    simulation.grid.far.t_sq = theta * theta;
+    simulation.quadtree.min_node_size = *renderer::MIN_NODE_SIZE.lock();
+    simulation.domain = *renderer::DOMAIN.lock();
+    simulation.domain_policy = *renderer::DOMAIN_POLICY.lock();
+    simulation.method = *renderer::FORCE_METHOD.lock();
+    simulation.decomposition = *renderer::DECOMPOSITION.lock();
+    simulation.refit_interval = renderer::REFIT_INTERVAL.load(Ordering::Relaxed);
+    simulation.gravity_enabled = renderer::GRAVITY_ENABLED.load(Ordering::Relaxed);
+    simulation.build_tree_without_gravity = renderer::QUADTREE_VISIBLE.load(Ordering::Relaxed);
+    simulation.capture_radius = *renderer::CAPTURE_RADIUS.lock();
+    simulation.restitution = *renderer::RESTITUTION.lock();
+    simulation.merge_threshold = *renderer::MERGE_THRESHOLD.lock();
+    simulation.adaptive_timestep = *renderer::ADAPTIVE_TIMESTEP.lock();
+    simulation.block_timesteps = *renderer::BLOCK_TIMESTEPS.lock();
+    simulation.near_field_substeps = renderer::NEAR_FIELD_SUBSTEPS.load(Ordering::Relaxed);
+    if !simulation.adaptive_timestep.enabled {
+        simulation.dt = *renderer::DT.lock();
     }
+    simulation.time_direction = if renderer::REVERSED.load(Ordering::Relaxed) {
+        -1.0
+    } else {
+        1.0
+    };
+    simulation.integrator = *renderer::INTEGRATOR.lock();
+    simulation.max_speed = *renderer::MAX_SPEED.lock();
+    simulation.pin_com = renderer::PIN_COM.load(Ordering::Relaxed);
+    simulation.softening = if renderer::SOFTENING_BY_RADIUS.load(Ordering::Relaxed) {
+        SofteningMode::PerBodyRadius
+    } else if let Some(k) = *renderer::MASS_SOFTENING.lock() {
+        SofteningMode::PerBodyMass(k)
+    } else {
+        SofteningMode::Global(*renderer::EPSILON.lock())
+    };
+    simulation.min_interaction_distance = *renderer::MIN_INTERACTION_DISTANCE.lock();
+    simulation.f64_accumulation = renderer::F64_ACCUMULATION.load(Ordering::Relaxed);
+    simulation.direct_anchors = renderer::DIRECT_ANCHORS.load(Ordering::Relaxed);
+    simulation.enforce_momentum_conservation = renderer::CONSERVE_MOMENTUM.load(Ordering::Relaxed);
+    simulation.rotating_frame = *renderer::ROTATING_FRAME.lock();
+    simulation.soft_wall = *renderer::SOFT_WALL.lock();
+    simulation.emitters.clone_from(&renderer::EMITTERS.lock());
+    simulation.max_bodies = renderer::MAX_BODIES.load(Ordering::Relaxed);
+    simulation.history.capacity = renderer::REWIND_CAPACITY.load(Ordering::Relaxed);
+    simulation.history.interval = renderer::REWIND_INTERVAL.load(Ordering::Relaxed);
+    {
+        let mut stats = renderer::STATS.lock();
+        stats.frame = simulation.frame;
+        stats.time = simulation.time;
+        stats.dt = simulation.dt;
+        stats.accreted_mass = simulation.accreted_mass;
+        stats.nearest_neighbors = if renderer::NEAREST_NEIGHBORS.load(Ordering::Relaxed) {
+            Summary::new(&mut simulation.nearest_neighbor_distances())
+        } else {
+            None
+        };
+        stats.virial_ratio = renderer::VIRIAL_RATIO
+            .load(Ordering::Relaxed)
+            .then(|| simulation.virial_ratio());
+        stats.shape = renderer::SHAPE
+            .load(Ordering::Relaxed)
+            .then(|| simulation.shape())
+            .flatten();
+        stats.unbound = renderer::UNBOUND_COUNT
+            .load(Ordering::Relaxed)
+            .then(|| simulation.unbound_count());
+        stats.population_mixing = renderer::POPULATION_MIXING
+            .load(Ordering::Relaxed)
+            .then(|| simulation.population_mixing());
+        stats.angular_momentum = simulation.total_angular_momentum();
+        stats.snapshots = simulation.history.len();
+        stats.rewind_fits = simulation.history.fits(simulation.bodies.len());
+    }
+    let mut commands = renderer::COMMANDS.lock();
+    if let (Some(path), false) = (record, commands.is_empty()) {
+        for command in commands.drain(..) {
+            renderer::notify(describe(&command, simulation));
+            log.apply(simulation, command);
+        }
+        if let Err(err) = log.save(path) {
+            eprintln!("Failed to save {}: {}", path.display(), err);
+        }
+    }
+    for command in commands.drain(..) {
+        renderer::notify(describe(&command, simulation));
+        simulation.apply(command);
+    }
+    drop(commands);
     {
         let mut lock = renderer::BODIES.lock();
         lock.clear();
@@ -47,3 +430,81 @@ fn render(simulation: &mut Simulation) {
     }
     *lock |= true;
 }
+
+/// What `command` is about to do to `simulation`, for the status line.
+fn describe(command: &SimCommand, simulation: &Simulation) -> String {
+    match command {
+        SimCommand::Spawn(_) => format!("Spawned body #{}", simulation.next_body_id()),
+        SimCommand::Despawn(id) => format!("Removed body #{}", id),
+        SimCommand::Reset(bodies) if bodies.is_empty() => "Cleared every body".to_string(),
+        SimCommand::Reset(bodies) => format!("Reset to {} bodies", bodies.len()),
+        SimCommand::ReverseVelocities => "Reversed every velocity".to_string(),
+        SimCommand::SetFixed(id, true) => format!("Pinned body #{}", id),
+        SimCommand::SetFixed(id, false) => format!("Released body #{}", id),
+        SimCommand::Resample(n) => {
+            format!("Resampled {} bodies to {}", simulation.bodies.len(), n)
+        }
+        SimCommand::Rewind if simulation.history.is_empty() => {
+            "No snapshot to rewind to".to_string()
+        }
+        SimCommand::Rewind => "Rewound to the last snapshot".to_string(),
+        SimCommand::ScaleMasses(factor) => format!("Scaled every mass by {}", factor),
+        SimCommand::Kick(id, delta_v) => format!("Kicked body #{} by {:.3}", id, delta_v.mag()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use barnes_hut::simulation::Instability;
+
+    #[test]
+    fn run_until_stops_on_the_target_step() {
+        assert!(!reached(None, 0) && !reached(None, usize::MAX));
+        assert!(!reached(Some(10), 9));
+        assert!(reached(Some(10), 10) && reached(Some(10), 11));
+
+        let settings = Settings {
+            body_count: 20,
+            ..Default::default()
+        };
+        let mut simulation = Simulation::new(&settings);
+        let target = Some(7);
+        // Frames of three steps at a time, like the simulation thread runs
+        'run: loop {
+            for _ in 0..3 {
+                if reached(target, simulation.frame) {
+                    break 'run;
+                }
+                simulation.step();
+            }
+        }
+        assert_eq!(simulation.frame, 7);
+    }
+
+    #[test]
+    fn energy_jumps_pause_the_run() {
+        let settings = Settings {
+            body_count: 20,
+            ..Default::default()
+        };
+        let mut simulation = Simulation::new(&settings);
+        simulation.attract();
+        let before = simulation.total_energy();
+        assert!(!pause_if_unstable(&simulation, before, before, 0.1));
+        assert!(!renderer::PAUSED.load(Ordering::Relaxed));
+
+        for body in simulation.bodies_mut() {
+            body.vel *= 10.0;
+        }
+        let energy = simulation.total_energy();
+        assert!(pause_if_unstable(&simulation, before, energy, 0.1));
+        assert!(renderer::PAUSED.load(Ordering::Relaxed));
+        assert!(matches!(
+            renderer::STATS.lock().instability,
+            Some(Instability::EnergyJump(_))
+        ));
+        renderer::PAUSED.store(false, Ordering::Relaxed);
+        renderer::STATS.lock().instability = None;
+    }
+}
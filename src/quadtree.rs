@@ -1,7 +1,51 @@
-use crate::body::Body;
+use std::fmt::Write;
+
+use crate::{
+    body::Body,
+    capture::{self, CellColoring, View},
+    utils::FieldSum,
+};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
 use ultraviolet::Vec2;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MultipoleOrder {
+    /// Nodes act as a point mass at their center of mass.
+    Monopole,
+    /// Adds each node's quadrupole moment, allowing a larger theta for the
+    /// same error. Assumes positive masses, dipoles of charges are ignored.
+    Quadrupole,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpeningCriterion {
+    /// Approximate a node once `size / distance < theta`.
+    Classic,
+    /// Subtracts the offset of the center of mass from the geometric center
+    /// from the distance, so lopsided nodes are opened sooner.
+    ComCorrected,
+    /// Approximate a node once `(radius + size / 2) / distance < theta`,
+    /// with `Node::radius` bounding how far its bodies reach from the
+    /// center of mass. More conservative for uneven mass distributions.
+    NodeRadius,
+}
+
+/// Where sibling blocks of nodes are placed in `Quadtree::nodes`. The walk
+/// visits nodes in the same order whatever the layout, only how far apart
+/// they are in memory changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeOrder {
+    /// Wherever the insertions happened to split the nodes.
+    Insertion,
+    /// Each block right before the blocks below it, in the order the walk
+    /// descends into them.
+    DepthFirst,
+    /// Level by level from the root.
+    BreadthFirst,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Quad {
     pub center: Vec2,
     pub size: f32,
@@ -9,6 +53,13 @@ pub struct Quad {
 
 impl Quad {
     pub fn new_containing(bodies: &[Body]) -> Self {
+        if bodies.is_empty() {
+            return Self {
+                center: Vec2::zero(),
+                size: 0.0,
+            };
+        }
+
         let mut min_x = f32::MAX;
         let mut min_y = f32::MAX;
         let mut max_x = f32::MIN;
@@ -38,17 +89,44 @@ impl Quad {
         self
     }
 
+    /// Squared distance from `pos` to the closest point of the quad.
+    pub fn distance_sq(&self, pos: Vec2) -> f32 {
+        let half = self.size * 0.5;
+        let dx = ((pos.x - self.center.x).abs() - half).max(0.0);
+        let dy = ((pos.y - self.center.y).abs() - half).max(0.0);
+        dx * dx + dy * dy
+    }
+
+    /// The closest point of the quad to `pos`.
+    pub fn clamp(&self, pos: Vec2) -> Vec2 {
+        let half = Vec2::broadcast(self.size * 0.5);
+        pos.clamped(self.center - half, self.center + half)
+    }
+
+    pub fn contains(&self, pos: Vec2) -> bool {
+        let half = self.size * 0.5;
+        (pos.x - self.center.x).abs() <= half && (pos.y - self.center.y).abs() <= half
+    }
+
     pub fn subdivide(&self) -> [Quad; 4] {
         [0, 1, 2, 3].map(|i| self.into_quadrant(i))
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Node {
     pub children: usize,
     pub next: usize,
     pub pos: Vec2,
     pub mass: f32,
+    /// Sum of the absolute masses below this node. Equals `mass` unless
+    /// negative sources (charges) are inserted, and weights `pos`.
+    pub abs_mass: f32,
+    /// Traceless quadrupole moment `[xx, xy, yy]` about `pos`, only computed
+    /// for `MultipoleOrder::Quadrupole`.
+    pub quadrupole: [f32; 3],
+    /// Upper bound on the distance from `pos` to any body below the node.
+    pub radius: f32,
     pub quad: Quad,
 }
 
@@ -59,6 +137,9 @@ impl Node {
             next,
             pos: Vec2::zero(),
             mass: 0.0,
+            abs_mass: 0.0,
+            quadrupole: [0.0; 3],
+            radius: 0.0,
             quad,
         }
     }
@@ -72,15 +153,45 @@ impl Node {
     }
 
     pub fn is_empty(&self) -> bool {
-        self.mass == 0.0
+        self.abs_mass == 0.0
+    }
+
+    /// Acceleration correction from the quadrupole moment, where `d` points
+    /// from the field point to `pos` and `r_sq` is the softened distance.
+    pub fn quadrupole_acc(&self, d: Vec2, r_sq: f32) -> Vec2 {
+        let [xx, xy, yy] = self.quadrupole;
+        let qd = Vec2::new(xx * d.x + xy * d.y, xy * d.x + yy * d.y);
+        let d_q_d = d.dot(qd);
+        let inv_r5 = 1.0 / (r_sq * r_sq * r_sq.sqrt());
+        (d * (2.5 * d_q_d / r_sq) - qd) * inv_r5
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct Quadtree {
     pub t_sq: f32,
     pub e_sq: f32,
+    /// Squared distance below which leaves act as if they were this far
+    /// away, see `Simulation::min_interaction_distance`.
+    pub min_d_sq: f32,
+    /// Sum the contributions to each acceleration in `f64`.
+    pub f64_accumulation: bool,
+    pub multipole_order: MultipoleOrder,
+    pub opening_criterion: OpeningCriterion,
+    /// Nodes smaller than this aren't split, their bodies share the leaf.
+    /// Each of them then also feels its own mass through the leaf, which is
+    /// negligible while this is no larger than the softening length.
+    pub min_node_size: f32,
+    /// Layout `relayout` brings the nodes into, which `Simulation::build`
+    /// does after every build.
+    pub node_order: NodeOrder,
     pub nodes: Vec<Node>,
     pub parents: Vec<usize>,
+    /// Leaf containing each body, filled in by `index_bodies`.
+    pub leaves: Vec<usize>,
+    /// The bodies of leaf `i` are `leaf_bodies[leaf_offsets[i]..leaf_offsets[i + 1]]`.
+    leaf_offsets: Vec<usize>,
+    leaf_bodies: Vec<usize>,
 }
 
 impl Quadtree {
@@ -90,14 +201,148 @@ impl Quadtree {
         Self {
             t_sq: theta * theta,
             e_sq: epsilon * epsilon,
+            min_d_sq: 0.0,
+            f64_accumulation: false,
+            multipole_order: MultipoleOrder::Monopole,
+            opening_criterion: OpeningCriterion::Classic,
+            min_node_size: 0.0,
+            node_order: NodeOrder::Insertion,
             nodes: Vec::new(),
             parents: Vec::new(),
+            leaves: Vec::new(),
+            leaf_offsets: Vec::new(),
+            leaf_bodies: Vec::new(),
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Net source of everything in the tree, read off the root. 0 before
+    /// the first build.
+    pub fn total_mass(&self) -> f32 {
+        self.nodes.get(Self::ROOT).map_or(0.0, |root| root.mass)
+    }
+
+    /// Square spanning every body the tree was built from, `None` before the
+    /// first build. Refits keep the quad of the last build.
+    pub fn bounds(&self) -> Option<Quad> {
+        self.nodes.get(Self::ROOT).map(|root| root.quad)
+    }
+
+    /// Rasterizes the leaves overlapping the world space square `bounds` into
+    /// a `width` × `height` image, like the cell overlay of the window. Empty
+    /// leaves stay black, every cell gets a dark outline.
+    pub fn render_to_image(
+        &self,
+        width: u32,
+        height: u32,
+        bounds: Quad,
+        coloring: CellColoring,
+    ) -> RgbaImage {
+        let mut image = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 0xff]));
+        if self.nodes.is_empty() {
+            return image;
+        }
+
+        let view = View {
+            pos: bounds.center,
+            scale: bounds.size * 0.5,
+            width,
+            height,
+        };
+        let mut leaves = Vec::new();
+        let mut stack = vec![(Self::ROOT, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            let n = &self.nodes[node];
+            if n.is_branch() {
+                for i in 0..4 {
+                    stack.push((n.children + i, depth + 1));
+                }
+            } else {
+                leaves.push((n, depth));
+            }
+        }
+
+        let value = |n: &Node, depth: usize| match coloring {
+            CellColoring::Depth => depth as f32,
+            CellColoring::Mass => n.abs_mass.max(f32::MIN_POSITIVE).ln(),
+        };
+        let (lo, hi) = leaves
+            .iter()
+            .filter(|(n, _)| !n.is_empty())
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &(n, depth)| {
+                (lo.min(value(n, depth)), hi.max(value(n, depth)))
+            });
+        let range = (hi - lo).max(f32::EPSILON);
+
+        for (n, depth) in leaves {
+            let half = Vec2::broadcast(n.quad.size * 0.5);
+            let a = view.world_to_pixel(n.quad.center - half);
+            let b = view.world_to_pixel(n.quad.center + half);
+            let x0 = a.x.min(b.x).round().clamp(0.0, width as f32) as u32;
+            let x1 = a.x.max(b.x).round().clamp(0.0, width as f32) as u32;
+            let y0 = a.y.min(b.y).round().clamp(0.0, height as f32) as u32;
+            let y1 = a.y.max(b.y).round().clamp(0.0, height as f32) as u32;
+
+            let fill = if n.is_empty() {
+                [0, 0, 0, 0xff]
+            } else {
+                capture::ramp((value(n, depth) - lo) / range)
+            };
+            let edge = [0x40, 0x40, 0x40, 0xff];
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let border = x == x0 || y == y0 || x + 1 == x1 || y + 1 == y1;
+                    image.put_pixel(x, y, Rgba(if border { edge } else { fill }));
+                }
+            }
+        }
+
+        image
+    }
+
+    /// Depth of the deepest node, 0 for a lone root.
+    pub fn max_depth(&self) -> usize {
+        if self.nodes.is_empty() {
+            return 0;
+        }
+
+        let mut max_depth = 0;
+        let mut stack = vec![(Self::ROOT, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            max_depth = max_depth.max(depth);
+            let node = &self.nodes[node];
+            if node.is_branch() {
+                for i in 0..4 {
+                    stack.push((node.children + i, depth + 1));
+                }
+            }
         }
+        max_depth
+    }
+
+    /// Number of leaves holding any mass.
+    pub fn occupied_leaf_count(&self) -> usize {
+        self.occupied_leaves().count()
+    }
+
+    /// Every leaf node, in storage order. That order only depends on the
+    /// bodies the tree was built from, so it's the same for the same input.
+    pub fn leaves(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter().filter(|node| node.is_leaf())
+    }
+
+    /// Leaves holding any mass, in the order of `leaves`.
+    pub fn occupied_leaves(&self) -> impl Iterator<Item = &Node> {
+        self.leaves().filter(|node| !node.is_empty())
     }
 
     pub fn clear(&mut self, quad: Quad) {
         self.nodes.clear();
         self.parents.clear();
+        self.leaves.clear();
         self.nodes.push(Node::new(0, quad));
     }
 
@@ -120,7 +365,10 @@ impl Quadtree {
         return children;
     }
 
+    /// Adds a point mass. Positions outside the root are clamped into its
+    /// edge cells, which only happens with a fixed `Simulation::domain`.
     pub fn insert(&mut self, pos: Vec2, mass: f32) {
+        let pos = self.nodes[Self::ROOT].quad.clamp(pos);
         let mut node = Self::ROOT;
 
         while self.nodes[node].is_branch() {
@@ -131,16 +379,35 @@ impl Quadtree {
         if self.nodes[node].is_empty() {
             self.nodes[node].pos = pos;
             self.nodes[node].mass = mass;
+            self.nodes[node].abs_mass = mass.abs();
             return;
         }
 
-        let (p, m) = (self.nodes[node].pos, self.nodes[node].mass);
+        let (p, m, a) = (
+            self.nodes[node].pos,
+            self.nodes[node].mass,
+            self.nodes[node].abs_mass,
+        );
         if pos == p {
             self.nodes[node].mass += mass;
+            self.nodes[node].abs_mass += mass.abs();
             return;
         }
 
         loop {
+            if self.nodes[node].quad.size < self.min_node_size {
+                // Finer than worth resolving, both share the leaf
+                let abs_mass = a + mass.abs();
+                let n = &mut self.nodes[node];
+                if abs_mass > 0.0 {
+                    n.pos = (p * a + pos * mass.abs()) / abs_mass;
+                }
+                n.radius = (n.radius + (p - n.pos).mag()).max((pos - n.pos).mag());
+                n.mass = m + mass;
+                n.abs_mass = abs_mass;
+                return;
+            }
+
             let children = self.subdivide(node);
 
             let q1 = self.nodes[node].quad.find_quadrant(p);
@@ -154,8 +421,10 @@ impl Quadtree {
 
                 self.nodes[n1].pos = p;
                 self.nodes[n1].mass = m;
+                self.nodes[n1].abs_mass = a;
                 self.nodes[n2].pos = pos;
                 self.nodes[n2].mass = mass;
+                self.nodes[n2].abs_mass = mass.abs();
                 return;
             }
         }
@@ -164,23 +433,505 @@ impl Quadtree {
     pub fn propagate(&mut self) {
         for &node in self.parents.iter().rev() {
             let i = self.nodes[node].children;
+            debug_assert!(i > node && i + 3 < self.nodes.len());
 
-            self.nodes[node].pos = self.nodes[i].pos * self.nodes[i].mass
-                + self.nodes[i + 1].pos * self.nodes[i + 1].mass
-                + self.nodes[i + 2].pos * self.nodes[i + 2].mass
-                + self.nodes[i + 3].pos * self.nodes[i + 3].mass;
+            self.nodes[node].pos = self.nodes[i].pos * self.nodes[i].abs_mass
+                + self.nodes[i + 1].pos * self.nodes[i + 1].abs_mass
+                + self.nodes[i + 2].pos * self.nodes[i + 2].abs_mass
+                + self.nodes[i + 3].pos * self.nodes[i + 3].abs_mass;
 
             self.nodes[node].mass = self.nodes[i].mass
                 + self.nodes[i + 1].mass
                 + self.nodes[i + 2].mass
                 + self.nodes[i + 3].mass;
 
-            let mass = self.nodes[node].mass;
-            self.nodes[node].pos /= mass;
+            self.nodes[node].abs_mass = self.nodes[i].abs_mass
+                + self.nodes[i + 1].abs_mass
+                + self.nodes[i + 2].abs_mass
+                + self.nodes[i + 3].abs_mass;
+
+            let abs_mass = self.nodes[node].abs_mass;
+            self.nodes[node].pos /= abs_mass;
+
+            let com = self.nodes[node].pos;
+            self.nodes[node].radius = self.nodes[i..i + 4]
+                .iter()
+                .filter(|child| !child.is_empty())
+                .map(|child| (child.pos - com).mag() + child.radius)
+                .fold(0.0, f32::max);
+
+            if self.multipole_order == MultipoleOrder::Quadrupole {
+                // Parallel axis theorem for each child about the new center
+                let com = self.nodes[node].pos;
+                let mut q = [0.0; 3];
+                for child in &self.nodes[i..i + 4] {
+                    let s = child.pos - com;
+                    let m = child.mass;
+                    q[0] += child.quadrupole[0] + m * (2.0 * s.x * s.x - s.y * s.y);
+                    q[1] += child.quadrupole[1] + m * 3.0 * s.x * s.y;
+                    q[2] += child.quadrupole[2] + m * (2.0 * s.y * s.y - s.x * s.x);
+                }
+                self.nodes[node].quadrupole = q;
+            }
+        }
+    }
+
+    /// Moves the nodes into `node_order`, remapping every index. Siblings
+    /// stay together so `children` still points at a block of four. Drops
+    /// the body index, call `index_bodies` afterwards.
+    pub fn relayout(&mut self) {
+        if self.node_order == NodeOrder::Insertion || self.nodes.is_empty() {
+            return;
+        }
+
+        // Old index of the node going to each new index
+        let mut order = Vec::with_capacity(self.nodes.len());
+        order.push(Self::ROOT);
+        match self.node_order {
+            NodeOrder::Insertion => unreachable!(),
+            NodeOrder::DepthFirst => {
+                let mut stack = vec![Self::ROOT];
+                while let Some(node) = stack.pop() {
+                    let children = self.nodes[node].children;
+                    if children != 0 {
+                        order.extend(children..children + 4);
+                        stack.extend((children..children + 4).rev());
+                    }
+                }
+            }
+            NodeOrder::BreadthFirst => {
+                let mut i = 0;
+                while i < order.len() {
+                    let children = self.nodes[order[i]].children;
+                    if children != 0 {
+                        order.extend(children..children + 4);
+                    }
+                    i += 1;
+                }
+            }
+        }
+
+        let mut new_index = vec![0; self.nodes.len()];
+        for (new, &old) in order.iter().enumerate() {
+            new_index[old] = new;
+        }
+        self.nodes = order
+            .iter()
+            .map(|&old| {
+                let mut node = self.nodes[old].clone();
+                if node.is_branch() {
+                    node.children = new_index[node.children];
+                }
+                // The root is never a `next`, so 0 keeps ending the walk
+                node.next = new_index[node.next];
+                node
+            })
+            .collect();
+
+        // Both layouts place every parent before its children
+        self.parents.clear();
+        self.parents.extend(
+            self.nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, node)| node.is_branch())
+                .map(|(i, _)| i),
+        );
+        self.leaves.clear();
+    }
+
+    /// Leaf whose quad contains `point`, `None` outside the root or for an
+    /// empty tree. The root includes its edges, and a point on the border
+    /// between children belongs to the one on its lower or left side, as
+    /// in `Quad::find_quadrant`.
+    pub fn leaf_at(&self, point: Vec2) -> Option<usize> {
+        let root = self.nodes.first()?;
+        root.quad.contains(point).then(|| self.find_leaf(point))
+    }
+
+    pub fn find_leaf(&self, pos: Vec2) -> usize {
+        let pos = self.nodes[Self::ROOT].quad.clamp(pos);
+        let mut node = Self::ROOT;
+        while self.nodes[node].is_branch() {
+            let quadrant = self.nodes[node].quad.find_quadrant(pos);
+            node = self.nodes[node].children + quadrant;
+        }
+        node
+    }
+
+    /// Records which leaf each body belongs to, after all have been inserted.
+    pub fn index_bodies(&mut self, bodies: &[Body]) {
+        self.leaves.clear();
+        for body in bodies {
+            let leaf = self.find_leaf(body.pos);
+            self.leaves.push(leaf);
+        }
+
+        // Counting sort of the bodies by leaf
+        self.leaf_offsets.clear();
+        self.leaf_offsets.resize(self.nodes.len() + 1, 0);
+        for &leaf in &self.leaves {
+            self.leaf_offsets[leaf + 1] += 1;
+        }
+        for i in 0..self.nodes.len() {
+            self.leaf_offsets[i + 1] += self.leaf_offsets[i];
+        }
+
+        self.leaf_bodies.clear();
+        self.leaf_bodies.resize(bodies.len(), 0);
+        for (i, &leaf) in self.leaves.iter().enumerate() {
+            self.leaf_bodies[self.leaf_offsets[leaf]] = i;
+            self.leaf_offsets[leaf] += 1;
+        }
+
+        // Every offset now points at the end of its leaf, shift them back
+        for i in (1..self.leaf_offsets.len()).rev() {
+            self.leaf_offsets[i] = self.leaf_offsets[i - 1];
+        }
+        self.leaf_offsets[0] = 0;
+    }
+
+    /// Indices of the bodies in `node`, empty for branches.
+    pub fn leaf_bodies(&self, node: usize) -> &[usize] {
+        &self.leaf_bodies[self.leaf_offsets[node]..self.leaf_offsets[node + 1]]
+    }
+
+    /// Indices of all bodies within `radius` of `center`, where `bodies` are
+    /// the bodies the tree was last indexed with.
+    pub fn query_radius(&self, center: Vec2, radius: f32, bodies: &[Body]) -> Vec<usize> {
+        let mut result = Vec::new();
+        if self.nodes.is_empty() || self.leaves.len() != bodies.len() {
+            return result;
+        }
+
+        let r_sq = radius * radius;
+        let mut stack = vec![Self::ROOT];
+        while let Some(node) = stack.pop() {
+            let n = &self.nodes[node];
+            if n.quad.distance_sq(center) > r_sq {
+                continue;
+            }
+
+            if n.is_branch() {
+                stack.extend(n.children..n.children + 4);
+            } else {
+                for &i in self.leaf_bodies(node) {
+                    if (bodies[i].pos - center).mag_sq() <= r_sq {
+                        result.push(i);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Closest other body to `bodies[index]` along with its distance, which is
+    /// zero for coincident bodies.
+    pub fn nearest_neighbor(&self, index: usize, bodies: &[Body]) -> Option<(usize, f32)> {
+        if self.nodes.is_empty() || self.leaves.len() != bodies.len() {
+            return None;
+        }
+
+        let pos = bodies[index].pos;
+        let mut best = None;
+        let mut best_sq = f32::INFINITY;
+
+        let mut stack = vec![Self::ROOT];
+        while let Some(node) = stack.pop() {
+            let n = &self.nodes[node];
+            if n.quad.distance_sq(pos) > best_sq {
+                continue;
+            }
+
+            if n.is_branch() {
+                // Push the closest child last so it is visited first
+                let mut children = [0, 1, 2, 3].map(|i| n.children + i);
+                children.sort_by(|&a, &b| {
+                    let da = self.nodes[a].quad.distance_sq(pos);
+                    let db = self.nodes[b].quad.distance_sq(pos);
+                    db.total_cmp(&da)
+                });
+                stack.extend(children);
+            } else {
+                for &j in self.leaf_bodies(node) {
+                    let d_sq = (bodies[j].pos - pos).mag_sq();
+                    if j != index && d_sq < best_sq {
+                        best_sq = d_sq;
+                        best = Some(j);
+                    }
+                }
+            }
+        }
+
+        best.map(|j| (j, best_sq.sqrt()))
+    }
+
+    /// Graphviz DOT graph of every node and its children. The output grows
+    /// with the tree, so this is only readable for a handful of bodies.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph quadtree {\n    node [shape=box];\n");
+        for (i, n) in self.nodes.iter().enumerate() {
+            let kind = if n.is_branch() {
+                "branch"
+            } else if n.is_empty() {
+                "empty"
+            } else {
+                "leaf"
+            };
+            let _ = writeln!(
+                dot,
+                "    n{} [label=\"{} {}\\nmass {:.3}\\ncenter ({:.2}, {:.2}) size {:.2}\"];",
+                i, i, kind, n.mass, n.quad.center.x, n.quad.center.y, n.quad.size
+            );
+        }
+        for (i, n) in self.nodes.iter().enumerate() {
+            if n.is_branch() {
+                for child in n.children..n.children + 4 {
+                    let _ = writeln!(dot, "    n{} -> n{};", i, child);
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Checks that all node indices are in bounds and that every node is
+    /// reachable from the root exactly once, so that the tree is acyclic.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.nodes.is_empty() {
+            return Err("the root node is missing".to_string());
+        }
+
+        let mut visited = vec![false; self.nodes.len()];
+        let mut stack = vec![Self::ROOT];
+        while let Some(node) = stack.pop() {
+            if visited[node] {
+                return Err(format!("node {} is reachable more than once", node));
+            }
+            visited[node] = true;
+
+            let n = &self.nodes[node];
+            if n.next >= self.nodes.len() {
+                return Err(format!("node {} has next {} out of bounds", node, n.next));
+            }
+            if n.is_branch() {
+                if n.children <= node {
+                    return Err(format!(
+                        "node {} has children {} that don't come after it",
+                        node, n.children
+                    ));
+                }
+                if n.children + 3 >= self.nodes.len() {
+                    return Err(format!(
+                        "node {} has children {}..{} out of bounds",
+                        node,
+                        n.children,
+                        n.children + 4
+                    ));
+                }
+                stack.extend(n.children..n.children + 4);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the tree against the bodies it was built and indexed from, with
+    /// the masses given by `mass`: every body lies in a leaf containing it,
+    /// leaf masses add up to the total mass and every branch aggregates its
+    /// children. Meant for tests and debugging, this is O(n).
+    pub fn check(&self, bodies: &[Body], mass: impl Fn(&Body) -> f32) -> Result<(), String> {
+        let close = |a: f32, b: f32| (a - b).abs() <= 1e-3 * a.abs().max(b.abs()).max(1.0);
+
+        self.validate()?;
+        if self.leaves.len() != bodies.len() {
+            return Err(format!(
+                "{} bodies are indexed but {} were given",
+                self.leaves.len(),
+                bodies.len()
+            ));
+        }
+
+        for (i, (body, &leaf)) in bodies.iter().zip(&self.leaves).enumerate() {
+            let node = &self.nodes[leaf];
+            if !node.is_leaf() {
+                return Err(format!("body {} is indexed into branch {}", i, leaf));
+            }
+            if !node.quad.contains(body.pos) {
+                return Err(format!("body {} lies outside of its leaf {}", i, leaf));
+            }
+        }
+
+        let body_mass: f32 = bodies.iter().map(&mass).sum();
+        let leaf_mass: f32 = self
+            .nodes
+            .iter()
+            .filter(|n| n.is_leaf())
+            .map(|n| n.mass)
+            .sum();
+        if !close(body_mass, leaf_mass) {
+            return Err(format!(
+                "leaf mass {} differs from body mass {}",
+                leaf_mass, body_mass
+            ));
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.is_leaf() == node.is_branch() {
+                return Err(format!("node {} is both or neither leaf and branch", i));
+            }
+            if node.is_leaf() {
+                if node.is_empty() && node.mass != 0.0 {
+                    return Err(format!("empty leaf {} has mass {}", i, node.mass));
+                }
+                continue;
+            }
+
+            let children = &self.nodes[node.children..node.children + 4];
+            let mass: f32 = children.iter().map(|c| c.mass).sum();
+            let abs_mass: f32 = children.iter().map(|c| c.abs_mass).sum();
+            let pos = children
+                .iter()
+                .fold(Vec2::zero(), |pos, c| pos + c.pos * c.abs_mass)
+                / abs_mass;
+            if !close(node.mass, mass) || !close(node.abs_mass, abs_mass) {
+                return Err(format!("branch {} mass differs from its children", i));
+            }
+            if !close(node.pos.x, pos.x) || !close(node.pos.y, pos.y) {
+                return Err(format!(
+                    "branch {} center of mass {:?} differs from its children {:?}",
+                    i, node.pos, pos
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Recomputes masses and centers of mass with the current topology, given
+    /// the bodies that were indexed with the mass given by `mass(&bodies[i])`.
+    pub fn refit(&mut self, bodies: &[Body], mass: impl Fn(&Body) -> f32) {
+        for node in &mut self.nodes {
+            node.pos = Vec2::zero();
+            node.mass = 0.0;
+            node.abs_mass = 0.0;
+            node.quadrupole = [0.0; 3];
+            node.radius = 0.0;
+        }
+
+        for (body, &leaf) in bodies.iter().zip(&self.leaves) {
+            let m = mass(body);
+            self.nodes[leaf].pos += body.pos * m.abs();
+            self.nodes[leaf].mass += m;
+            self.nodes[leaf].abs_mass += m.abs();
+        }
+
+        for node in &mut self.nodes {
+            if node.is_leaf() && !node.is_empty() {
+                node.pos /= node.abs_mass;
+            }
+        }
+        for (body, &leaf) in bodies.iter().zip(&self.leaves) {
+            let node = &mut self.nodes[leaf];
+            node.radius = node.radius.max((body.pos - node.pos).mag());
+        }
+
+        self.propagate();
+    }
+
+    /// Whether `n` may be approximated at `d_sq` from its center of mass.
+    fn accept(&self, n: &Node, d_sq: f32) -> bool {
+        // Nodes whose masses partially cancel out need to be opened sooner,
+        // this is always 1 when all masses are positive
+        let purity = n.mass.abs() / n.abs_mass;
+
+        match self.opening_criterion {
+            OpeningCriterion::Classic => {
+                n.quad.size * n.quad.size < d_sq * self.t_sq * purity * purity
+            }
+            OpeningCriterion::ComCorrected => {
+                let d = d_sq.sqrt() - (n.pos - n.quad.center).mag();
+                d > 0.0 && n.quad.size < d * self.t_sq.sqrt() * purity
+            }
+            OpeningCriterion::NodeRadius => {
+                let extent = n.radius + 0.5 * n.quad.size;
+                extent * extent < d_sq * self.t_sq * purity * purity
+            }
         }
     }
 
     pub fn acc(&self, pos: Vec2) -> Vec2 {
+        self.acc_with(pos, |_| self.e_sq)
+    }
+
+    /// Like `acc`, but softening the interaction with each leaf by
+    /// `leaf_e_sq(leaf)` instead. Approximated branches keep `e_sq`.
+    pub fn acc_with(&self, pos: Vec2, leaf_e_sq: impl Fn(usize) -> f32) -> Vec2 {
+        let mut acc = FieldSum::new(self.f64_accumulation);
+
+        let mut node = Self::ROOT;
+        loop {
+            let n = &self.nodes[node];
+
+            let d = n.pos - pos;
+            let d_sq = d.mag_sq();
+
+            if n.is_leaf() || self.accept(n, d_sq) {
+                let (r_sq, e_sq) = if n.is_leaf() {
+                    (d_sq.max(self.min_d_sq), leaf_e_sq(node))
+                } else {
+                    (d_sq, self.e_sq)
+                };
+                let denom = (r_sq + e_sq) * r_sq.sqrt();
+                acc.add(d * (n.mass / denom).min(f32::MAX).max(f32::MIN));
+
+                if self.multipole_order == MultipoleOrder::Quadrupole && n.is_branch() {
+                    acc.add(n.quadrupole_acc(d, d_sq + self.e_sq));
+                }
+
+                if n.next == 0 {
+                    break;
+                }
+                node = n.next;
+            } else {
+                node = n.children;
+            }
+        }
+
+        acc.total()
+    }
+
+    /// Appends the non-empty leaves the walk of `acc` at `pos` sums directly
+    /// instead of approximating, the near field of `pos`.
+    pub fn near_leaves(&self, pos: Vec2, leaves: &mut Vec<usize>) {
+        let mut node = Self::ROOT;
+        loop {
+            let n = &self.nodes[node];
+            if n.is_leaf() || self.accept(n, (n.pos - pos).mag_sq()) {
+                if n.is_leaf() && !n.is_empty() {
+                    leaves.push(node);
+                }
+                if n.next == 0 {
+                    break;
+                }
+                node = n.next;
+            } else {
+                node = n.children;
+            }
+        }
+    }
+
+    /// Like `acc`, but nodes overlapping the box `min..max` are always
+    /// opened and leaves there for which `skip` holds are left out, so their
+    /// contribution can be summed exactly by the caller.
+    pub fn acc_outside(
+        &self,
+        pos: Vec2,
+        min: Vec2,
+        max: Vec2,
+        skip: impl Fn(Vec2) -> bool,
+    ) -> Vec2 {
         let mut acc = Vec2::zero();
 
         let mut node = Self::ROOT;
@@ -189,11 +940,21 @@ impl Quadtree {
 
             let d = n.pos - pos;
             let d_sq = d.mag_sq();
+            let half = Vec2::broadcast(n.quad.size * 0.5);
+            let (lo, hi) = (n.quad.center - half, n.quad.center + half);
+            let overlaps = lo.x < max.x && hi.x > min.x && lo.y < max.y && hi.y > min.y;
 
-            if n.is_leaf() || n.quad.size * n.quad.size < d_sq * self.t_sq {
+            let far = !overlaps && self.accept(n, d_sq);
+            if (n.is_leaf() && !skip(n.pos)) || (n.is_branch() && far) {
                 let denom = (d_sq + self.e_sq) * d_sq.sqrt();
-                acc += d * (n.mass / denom).min(f32::MAX);
+                acc += d * (n.mass / denom).min(f32::MAX).max(f32::MIN);
+
+                if self.multipole_order == MultipoleOrder::Quadrupole && n.is_branch() {
+                    acc += n.quadrupole_acc(d, d_sq + self.e_sq);
+                }
+            }
 
+            if n.is_leaf() || far {
                 if n.next == 0 {
                     break;
                 }
@@ -205,4 +966,432 @@ impl Quadtree {
 
         acc
     }
+
+    /// Softened potential `-m / sqrt(r² + ε²)` at `pos`, opening nodes with
+    /// the same criterion as `acc`. Only the monopole term is included.
+    pub fn potential(&self, pos: Vec2) -> f32 {
+        let mut potential = 0.0;
+
+        let mut node = Self::ROOT;
+        loop {
+            let n = &self.nodes[node];
+
+            let d_sq = (n.pos - pos).mag_sq();
+
+            if n.is_leaf() || self.accept(n, d_sq) {
+                // Like in `acc`, a leaf exactly at `pos` exerts nothing on it
+                if d_sq > 0.0 || n.is_branch() {
+                    let phi = n.mass / (d_sq + self.e_sq).sqrt();
+                    potential -= phi.min(f32::MAX).max(f32::MIN);
+                }
+
+                if n.next == 0 {
+                    break;
+                }
+                node = n.next;
+            } else {
+                node = n.children;
+            }
+        }
+
+        potential
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{self, random_bodies};
+
+    /// Fills `quadtree` with `bodies` the way `Simulation::build` does.
+    fn build(mut quadtree: Quadtree, bodies: &[Body]) -> Quadtree {
+        quadtree.clear(Quad::new_containing(bodies));
+        for body in bodies {
+            quadtree.insert(body.pos, body.mass);
+        }
+        quadtree.propagate();
+        quadtree.relayout();
+        quadtree.index_bodies(bodies);
+        assert_eq!(quadtree.validate(), Ok(()));
+        quadtree
+    }
+
+    /// `|a_tree - a_direct| / |a_direct|` at every body.
+    fn relative_errors(quadtree: &Quadtree, bodies: &[Body]) -> Vec<f32> {
+        bodies
+            .iter()
+            .map(|body| {
+                let exact = utils::direct_field(bodies, body.pos, quadtree.e_sq);
+                (quadtree.acc(body.pos) - exact).mag() / exact.mag()
+            })
+            .collect()
+    }
+
+    fn mean(values: &[f32]) -> f32 {
+        values.iter().sum::<f32>() / values.len() as f32
+    }
+
+    #[test]
+    fn quadrupole_beats_monopole_at_equal_theta() {
+        let bodies = random_bodies(500, 0);
+        let mut monopole = Quadtree::new(1.0, 1.0);
+        monopole.multipole_order = MultipoleOrder::Monopole;
+        let mut quadrupole = monopole.clone();
+        quadrupole.multipole_order = MultipoleOrder::Quadrupole;
+
+        let monopole = mean(&relative_errors(&build(monopole, &bodies), &bodies));
+        let quadrupole = mean(&relative_errors(&build(quadrupole, &bodies), &bodies));
+        assert!(
+            quadrupole < 0.7 * monopole,
+            "quadrupole error {} isn't well below monopole error {}",
+            quadrupole,
+            monopole
+        );
+    }
+
+    #[test]
+    fn query_radius_matches_a_linear_scan() {
+        let bodies = random_bodies(300, 1);
+        let quadtree = build(Quadtree::new(1.0, 1.0), &bodies);
+        let mut rng = fastrand::Rng::with_seed(2);
+        for _ in 0..50 {
+            let center = Vec2::new(rng.f32(), rng.f32()) * 300.0 - Vec2::broadcast(150.0);
+            let radius = rng.f32() * 60.0;
+            let mut found = quadtree.query_radius(center, radius, &bodies);
+            found.sort_unstable();
+            let expected: Vec<usize> = (0..bodies.len())
+                .filter(|&i| (bodies[i].pos - center).mag_sq() <= radius * radius)
+                .collect();
+            assert_eq!(
+                found, expected,
+                "query at {:?} of radius {}",
+                center, radius
+            );
+        }
+
+        let outside = Vec2::broadcast(1000.0);
+        assert!(quadtree.query_radius(outside, 10.0, &bodies).is_empty());
+        let everything = quadtree.query_radius(Vec2::zero(), 1000.0, &bodies);
+        assert_eq!(everything.len(), bodies.len());
+    }
+
+    #[test]
+    fn nearest_neighbor_matches_brute_force() {
+        let mut bodies = random_bodies(200, 3);
+        // Coincident bodies are each other's neighbor at distance 0
+        bodies[1].pos = bodies[0].pos;
+        let quadtree = build(Quadtree::new(1.0, 1.0), &bodies);
+        for (i, body) in bodies.iter().enumerate() {
+            let expected = bodies
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, other)| (other.pos - body.pos).mag())
+                .fold(f32::INFINITY, f32::min);
+            let (_, distance) = quadtree.nearest_neighbor(i, &bodies).unwrap();
+            assert_eq!(distance, expected, "nearest neighbor of body {}", i);
+        }
+        assert_eq!(quadtree.nearest_neighbor(0, &bodies).unwrap(), (1, 0.0));
+    }
+
+    fn assert_close(a: Vec2, b: Vec2) {
+        assert!(
+            (a - b).mag() <= 1e-5 * b.mag().max(1.0),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn one_body_per_quadrant_gives_one_level() {
+        // Masses 1 to 4 at (-1, -1), (1, -1), (-1, 1) and (1, 1), in quadrant order
+        let bodies: Vec<Body> = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+            .into_iter()
+            .zip(1..)
+            .map(|((x, y), mass)| Body::new(Vec2::new(x, y), Vec2::zero(), mass as f32, 1.0))
+            .collect();
+        let quadtree = build(Quadtree::new(1.0, 1.0), &bodies);
+
+        assert_eq!(quadtree.nodes.len(), 5);
+        let root = &quadtree.nodes[Quadtree::ROOT];
+        assert!(root.is_branch() && !root.is_leaf() && !root.is_empty());
+        assert_eq!(root.mass, 10.0);
+        assert_close(root.pos, Vec2::new(0.2, 0.4));
+
+        for (i, body) in bodies.iter().enumerate() {
+            assert_eq!(quadtree.leaves[i], root.children + i);
+            let leaf = &quadtree.nodes[quadtree.leaves[i]];
+            assert!(leaf.is_leaf() && !leaf.is_branch() && !leaf.is_empty());
+            assert!(leaf.quad.contains(body.pos));
+            assert_eq!((leaf.pos, leaf.mass), (body.pos, body.mass));
+            assert_eq!(quadtree.leaf_bodies(quadtree.leaves[i]), &[i]);
+        }
+    }
+
+    #[test]
+    fn close_pair_splits_its_quadrant_until_separated() {
+        let body = |x: f32, y: f32, mass: f32| Body::new(Vec2::new(x, y), Vec2::zero(), mass, 1.0);
+        let bodies = [
+            body(-4.0, -4.0, 1.0),
+            body(4.0, 4.0, 1.0),
+            body(1.0, 1.0, 2.0),
+        ];
+        let quadtree = build(Quadtree::new(1.0, 1.0), &bodies);
+
+        // The root splits once for the corners and the (1, 1) pair once more
+        assert_eq!(quadtree.nodes.len(), 9);
+        assert_eq!(quadtree.max_depth(), 2);
+        let pair = &quadtree.nodes[quadtree.nodes[Quadtree::ROOT].children + 3];
+        assert!(pair.is_branch());
+        assert_eq!(pair.mass, 3.0);
+        assert_close(pair.pos, Vec2::new(2.0, 2.0));
+
+        for (i, node) in quadtree.nodes.iter().enumerate() {
+            assert_ne!(node.is_leaf(), node.is_branch());
+            if node.is_empty() {
+                assert!(node.is_leaf() && node.mass == 0.0);
+                assert!(quadtree.leaf_bodies(i).is_empty());
+            }
+        }
+        assert_eq!(quadtree.check(&bodies, |body| body.mass), Ok(()));
+    }
+
+    #[test]
+    fn random_bodies_pass_the_tree_checks() {
+        for seed in 0..5 {
+            let bodies = random_bodies(500, seed);
+            let quadtree = build(Quadtree::new(1.0, 1.0), &bodies);
+            assert_eq!(quadtree.check(&bodies, |body| body.mass), Ok(()));
+
+            let leaf_mass: f32 = quadtree.leaves().map(|leaf| leaf.mass).sum();
+            let body_mass: f32 = bodies.iter().map(|body| body.mass).sum();
+            assert!((leaf_mass - body_mass).abs() <= 1e-4 * body_mass);
+        }
+    }
+
+    #[test]
+    fn zero_theta_matches_direct_summation() {
+        let bodies = random_bodies(300, 4);
+        let quadtree = build(Quadtree::new(0.0, 1.0), &bodies);
+        let max = relative_errors(&quadtree, &bodies)
+            .into_iter()
+            .fold(0.0, f32::max);
+        assert!(max < 1e-4, "theta 0 is off by up to {}", max);
+    }
+
+    #[test]
+    fn validate_rejects_broken_trees() {
+        assert!(Quadtree::new(1.0, 1.0).validate().is_err());
+
+        let bodies = random_bodies(50, 5);
+        let quadtree = build(Quadtree::new(1.0, 1.0), &bodies);
+
+        let mut cyclic = quadtree.clone();
+        let child = cyclic.nodes[Quadtree::ROOT].children;
+        // Pointing a child back at its own siblings would loop forever
+        cyclic.nodes[child].children = child;
+        assert!(cyclic.validate().is_err());
+
+        let mut dangling = quadtree;
+        let last = dangling.nodes.len() - 1;
+        dangling.nodes[Quadtree::ROOT].next = last + 1;
+        assert!(dangling.validate().is_err());
+    }
+
+    #[test]
+    fn dot_lists_every_node_and_edge() {
+        let body = |x: f32, y: f32| Body::new(Vec2::new(x, y), Vec2::zero(), 1.0, 1.0);
+        let bodies = [body(-4.0, -4.0), body(4.0, 4.0), body(1.0, 1.0)];
+        let quadtree = build(Quadtree::new(1.0, 1.0), &bodies);
+        let dot = quadtree.to_dot();
+
+        assert!(dot.starts_with("digraph quadtree {") && dot.ends_with("}\n"));
+        let count = |pattern: &str| dot.lines().filter(|line| line.contains(pattern)).count();
+        assert_eq!(count("[label="), 9);
+        assert_eq!(count(" -> "), 8);
+        assert_eq!(count(" branch\\n"), 2);
+        assert_eq!(count(" leaf\\n"), 3);
+        assert_eq!(count(" empty\\n"), 4);
+    }
+
+    #[test]
+    fn root_summaries_match_the_bodies() {
+        let empty = Quadtree::new(1.0, 1.0);
+        assert_eq!((empty.total_mass(), empty.bounds()), (0.0, None));
+
+        for seed in 0..5 {
+            let bodies = random_bodies(400, seed);
+            let quadtree = build(Quadtree::new(1.0, 1.0), &bodies);
+            let mass: f32 = bodies.iter().map(|body| body.mass).sum();
+            assert!((quadtree.total_mass() - mass).abs() <= 1e-4 * mass);
+
+            let bounds = quadtree.bounds().unwrap();
+            assert_eq!(bounds, Quad::new_containing(&bodies));
+            assert!(bodies.iter().all(|body| bounds.contains(body.pos)));
+        }
+    }
+
+    #[test]
+    fn rendered_image_has_the_requested_size_and_cells() {
+        let black = Rgba([0, 0, 0, 0xff]);
+        let empty = Quadtree::new(1.0, 1.0).render_to_image(
+            64,
+            32,
+            Quad::new_containing(&[]),
+            CellColoring::Depth,
+        );
+        assert_eq!(empty.dimensions(), (64, 32));
+        assert!(empty.pixels().all(|pixel| *pixel == black));
+
+        let bodies = random_bodies(300, 15);
+        let quadtree = build(Quadtree::new(1.0, 1.0), &bodies);
+        let bounds = quadtree.bounds().unwrap();
+        for coloring in [CellColoring::Depth, CellColoring::Mass] {
+            let image = quadtree.render_to_image(320, 200, bounds, coloring);
+            assert_eq!(image.dimensions(), (320, 200));
+            // Occupied cells fill most of a uniformly populated square
+            let colored = image.pixels().filter(|pixel| **pixel != black).count();
+            assert!(colored > 200 * 200 / 4, "only {} pixels colored", colored);
+        }
+    }
+
+    #[test]
+    fn occupied_leaves_cover_every_body_once() {
+        let bodies = random_bodies(500, 16);
+        for min_node_size in [0.0, 10.0] {
+            let mut quadtree = Quadtree::new(1.0, 1.0);
+            quadtree.min_node_size = min_node_size;
+            let quadtree = build(quadtree, &bodies);
+
+            let mut seen = vec![0; bodies.len()];
+            let mut occupied = 0;
+            for (leaf, node) in quadtree.nodes.iter().enumerate() {
+                let members = quadtree.leaf_bodies(leaf);
+                assert_eq!(members.is_empty(), node.is_empty() || node.is_branch());
+                if node.is_leaf() && !node.is_empty() {
+                    occupied += 1;
+                }
+                for &i in members {
+                    assert_eq!(quadtree.leaves[i], leaf);
+                    seen[i] += 1;
+                }
+            }
+            assert!(seen.iter().all(|&count| count == 1));
+
+            // Occupied leaves times their mean occupancy is every body
+            assert_eq!(quadtree.occupied_leaf_count(), occupied);
+            assert_eq!(quadtree.occupied_leaves().count(), occupied);
+            assert_eq!(
+                quadtree.leaves().count(),
+                quadtree.nodes.iter().filter(|n| n.is_leaf()).count()
+            );
+            if min_node_size > 0.0 {
+                assert!(occupied < bodies.len(), "no leaf holds more than one body");
+            } else {
+                assert_eq!(occupied, bodies.len());
+            }
+        }
+    }
+
+    #[test]
+    fn min_node_size_of_the_domain_keeps_one_level() {
+        let bodies = random_bodies(500, 18);
+        let size = Quad::new_containing(&bodies).size;
+
+        let mut quadtree = Quadtree::new(1.0, 1.0);
+        quadtree.min_node_size = size;
+        let quadtree = build(quadtree, &bodies);
+        assert_eq!(quadtree.nodes.len(), 5);
+        assert_eq!(quadtree.max_depth(), 1);
+        let in_children: usize = (1..5).map(|leaf| quadtree.leaf_bodies(leaf).len()).sum();
+        assert_eq!(in_children, bodies.len());
+        assert_eq!(quadtree.check(&bodies, |body| body.mass), Ok(()));
+
+        // Any larger and not even the root splits
+        let mut quadtree = Quadtree::new(1.0, 1.0);
+        quadtree.min_node_size = 2.0 * size;
+        let quadtree = build(quadtree, &bodies);
+        assert_eq!(quadtree.nodes.len(), 1);
+        assert_eq!(quadtree.leaf_bodies(Quadtree::ROOT).len(), bodies.len());
+        assert_eq!(quadtree.check(&bodies, |body| body.mass), Ok(()));
+    }
+
+    /// Most of `n` bodies in a tight clump off to one side of a sparse
+    /// background, so that many nodes have their center of mass far from
+    /// their center.
+    fn clustered_bodies(n: usize, seed: u64) -> Vec<Body> {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let mut bodies = random_bodies(n / 5, seed);
+        while bodies.len() < n {
+            let pos = Vec2::new(70.0, 70.0) + Vec2::new(rng.f32(), rng.f32()) * 20.0;
+            bodies.push(Body::new(pos, Vec2::zero(), 1.0, 1.0));
+        }
+        bodies
+    }
+
+    fn max_error(criterion: OpeningCriterion, bodies: &[Body]) -> f32 {
+        let mut quadtree = Quadtree::new(1.0, 1.0);
+        quadtree.opening_criterion = criterion;
+        let quadtree = build(quadtree, bodies);
+        relative_errors(&quadtree, bodies)
+            .into_iter()
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn com_corrected_criterion_lowers_the_max_error() {
+        for seed in 0..3 {
+            let bodies = clustered_bodies(1000, seed);
+            let classic = max_error(OpeningCriterion::Classic, &bodies);
+            let corrected = max_error(OpeningCriterion::ComCorrected, &bodies);
+            assert!(
+                corrected < classic,
+                "seed {}: {} against {}",
+                seed,
+                corrected,
+                classic
+            );
+        }
+    }
+
+    #[test]
+    fn node_radius_criterion_lowers_the_max_error() {
+        for seed in 0..3 {
+            let bodies = random_bodies(1000, seed);
+            let classic = max_error(OpeningCriterion::Classic, &bodies);
+            let radius = max_error(OpeningCriterion::NodeRadius, &bodies);
+            assert!(
+                radius < classic,
+                "seed {}: {} against {}",
+                seed,
+                radius,
+                classic
+            );
+        }
+    }
+
+    #[test]
+    fn leaf_at_finds_the_leaf_containing_the_point() {
+        let quadtree = build(Quadtree::new(1.0, 1.0), &random_bodies(300, 26));
+        let root = quadtree.nodes[Quadtree::ROOT].quad;
+
+        let mut rng = fastrand::Rng::with_seed(26);
+        for _ in 0..1000 {
+            let offset = Vec2::new(rng.f32(), rng.f32()) - Vec2::broadcast(0.5);
+            let point = root.center + offset * root.size;
+            let leaf = quadtree.leaf_at(point).unwrap();
+            assert!(quadtree.nodes[leaf].is_leaf());
+            assert!(quadtree.nodes[leaf].quad.contains(point), "{:?}", point);
+        }
+
+        // The centre is on every border between the root's children and
+        // goes to the lower left one
+        let leaf = quadtree.leaf_at(root.center).unwrap();
+        let quad = quadtree.nodes[leaf].quad;
+        assert!(quad.center.x < root.center.x && quad.center.y < root.center.y);
+
+        let outside = root.center + Vec2::new(root.size, 0.0);
+        assert_eq!(quadtree.leaf_at(outside), None);
+    }
 }
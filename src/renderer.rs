@@ -1,88 +1,1669 @@
 use std::{
+    collections::VecDeque,
     f32::consts::{PI, TAU},
-    sync::atomic::{AtomicBool, Ordering},
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{
+use barnes_hut::{
     body::Body,
-    quadtree::{Node, Quadtree},
+    capture::{self, View},
+    quadtree::{MultipoleOrder, Node, OpeningCriterion, Quad, Quadtree},
+    settings::{Settings, SETTINGS_PATH},
+    simulation::{
+        AdaptiveTimestep, BlockTimesteps, Decomposition, DomainPolicy, Emitter, ForceKind,
+        ForceMethod, Instability, Integrator, MergeThreshold, Shape, SimCommand, SoftWall,
+    },
+    utils::{self, DiscOptions, Rotation, Summary},
 };
 
 use quarkstrom::{egui, winit::event::VirtualKeyCode, winit_input_helper::WinitInputHelper};
 
+use image::RgbaImage;
 use palette::{rgb::Rgba, Hsluv, IntoColor};
 use ultraviolet::Vec2;
 
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use parking_lot::Mutex;
 
 pub static PAUSED: Lazy<AtomicBool> = Lazy::new(|| false.into());
+/// Runs the simulation backward, see `Simulation::time_direction`.
+pub static REVERSED: Lazy<AtomicBool> = Lazy::new(|| false.into());
+/// Parked while paused, unparked to resume without delay.
+pub static SIMULATION_THREAD: OnceCell<std::thread::Thread> = OnceCell::new();
+pub static FORCE_KIND: Lazy<Mutex<ForceKind>> = Lazy::new(|| Mutex::new(ForceKind::Gravity));
+pub static MULTIPOLE_ORDER: Lazy<Mutex<MultipoleOrder>> =
+    Lazy::new(|| Mutex::new(MultipoleOrder::Monopole));
+pub static OPENING_CRITERION: Lazy<Mutex<OpeningCriterion>> =
+    Lazy::new(|| Mutex::new(OpeningCriterion::Classic));
+pub static MIN_NODE_SIZE: Lazy<Mutex<f32>> = Lazy::new(|| Mutex::new(0.0));
+pub static DOMAIN: Lazy<Mutex<Option<Quad>>> = Lazy::new(|| Mutex::new(None));
+pub static DOMAIN_POLICY: Lazy<Mutex<DomainPolicy>> = Lazy::new(|| Mutex::new(DomainPolicy::Clamp));
+pub static FORCE_METHOD: Lazy<Mutex<ForceMethod>> =
+    Lazy::new(|| Mutex::new(ForceMethod::BarnesHut));
+pub static DECOMPOSITION: Lazy<Mutex<Decomposition>> =
+    Lazy::new(|| Mutex::new(Decomposition::Quadtree));
+pub static GRAVITY_ENABLED: Lazy<AtomicBool> = Lazy::new(|| true.into());
+pub static QUADTREE_VISIBLE: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static NEAREST_NEIGHBORS: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static VIRIAL_RATIO: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static UNBOUND_COUNT: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static SHAPE: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static POPULATION_MIXING: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static STEPS_PER_FRAME: Lazy<AtomicUsize> = Lazy::new(|| 1.into());
+pub static STEP_DELAY_MS: Lazy<AtomicUsize> = Lazy::new(|| 0.into());
+/// Upper bound on simulation steps per second, 0 for uncapped.
+pub static MAX_STEPS_PER_SECOND: Lazy<AtomicUsize> = Lazy::new(|| 0.into());
+pub static REFIT_INTERVAL: Lazy<AtomicUsize> = Lazy::new(|| 0.into());
+pub static CAPTURE_RADIUS: Lazy<Mutex<f32>> = Lazy::new(|| Mutex::new(0.0));
+pub static RESTITUTION: Lazy<Mutex<Option<f32>>> = Lazy::new(|| Mutex::new(Some(0.5)));
+pub static MERGE_THRESHOLD: Lazy<Mutex<Option<MergeThreshold>>> = Lazy::new(|| Mutex::new(None));
+pub static DT: Lazy<Mutex<f32>> = Lazy::new(|| Mutex::new(0.05));
+pub static ADAPTIVE_TIMESTEP: Lazy<Mutex<AdaptiveTimestep>> =
+    Lazy::new(|| Mutex::new(AdaptiveTimestep::default()));
+pub static BLOCK_TIMESTEPS: Lazy<Mutex<BlockTimesteps>> =
+    Lazy::new(|| Mutex::new(BlockTimesteps::default()));
+pub static NEAR_FIELD_SUBSTEPS: Lazy<AtomicUsize> = Lazy::new(|| 1.into());
+pub static INTEGRATOR: Lazy<Mutex<Integrator>> = Lazy::new(|| Mutex::new(Integrator::Verlet));
+pub static MAX_SPEED: Lazy<Mutex<Option<f32>>> = Lazy::new(|| Mutex::new(None));
+pub static PIN_COM: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static THETA: Lazy<Mutex<f32>> = Lazy::new(|| Mutex::new(1.0));
+pub static EPSILON: Lazy<Mutex<f32>> = Lazy::new(|| Mutex::new(1.0));
+pub static SOFTENING_BY_RADIUS: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static MASS_SOFTENING: Lazy<Mutex<Option<f32>>> = Lazy::new(|| Mutex::new(None));
+pub static MIN_INTERACTION_DISTANCE: Lazy<Mutex<f32>> = Lazy::new(|| Mutex::new(0.0));
+pub static F64_ACCUMULATION: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static DIRECT_ANCHORS: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static CONSERVE_MOMENTUM: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static ROTATING_FRAME: Lazy<Mutex<Option<f32>>> = Lazy::new(|| Mutex::new(None));
+pub static SOFT_WALL: Lazy<Mutex<Option<SoftWall>>> = Lazy::new(|| Mutex::new(None));
+pub static EMITTERS: Lazy<Mutex<Vec<Emitter>>> = Lazy::new(|| Mutex::new(Vec::new()));
+pub static MAX_BODIES: Lazy<AtomicUsize> = Lazy::new(|| 200000.into());
+/// Largest fractional energy change in one step before pausing,
+/// `None` to never pause on its own.
+pub static PAUSE_ON_INSTABILITY: Lazy<Mutex<Option<f32>>> = Lazy::new(|| Mutex::new(None));
+pub static REWIND_CAPACITY: Lazy<AtomicUsize> = Lazy::new(|| 10.into());
+pub static REWIND_INTERVAL: Lazy<AtomicUsize> = Lazy::new(|| 100.into());
+
+/// Readouts published by the simulation thread.
+#[derive(Clone, Default)]
+pub struct Stats {
+    pub frame: usize,
+    pub time: f32,
+    pub dt: f32,
+    pub accreted_mass: f32,
+    pub nearest_neighbors: Option<Summary>,
+    pub virial_ratio: Option<f32>,
+    /// Bodies with positive energy, see `Simulation::unbound_count`.
+    pub unbound: Option<usize>,
+    pub shape: Option<Shape>,
+    pub population_mixing: Option<f32>,
+    pub angular_momentum: f32,
+    /// Why the simulation paused itself, cleared on continuing.
+    pub instability: Option<Instability>,
+    /// Snapshots available to rewind to, and whether the run is small
+    /// enough to take them.
+    pub snapshots: usize,
+    pub rewind_fits: bool,
+}
+
+/// Frame to pause at, cleared once it's reached.
+pub static RUN_UNTIL: Lazy<Mutex<Option<usize>>> = Lazy::new(|| Mutex::new(None));
+
+/// Recent messages about what actions did, oldest first, see `notify`.
+pub static STATUS: Lazy<Mutex<VecDeque<(String, Instant)>>> =
+    Lazy::new(|| Mutex::new(VecDeque::new()));
+
+pub static STATS: Lazy<Mutex<Stats>> = Lazy::new(|| Mutex::new(Stats::default()));
 pub static UPDATE_LOCK: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
 
 pub static BODIES: Lazy<Mutex<Vec<Body>>> = Lazy::new(|| Mutex::new(Vec::new()));
 pub static QUADTREE: Lazy<Mutex<Vec<Node>>> = Lazy::new(|| Mutex::new(Vec::new()));
 
-pub static SPAWN: Lazy<Mutex<Vec<Body>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// Changes to the simulation, applied in order before the next snapshot.
+pub static COMMANDS: Lazy<Mutex<Vec<SimCommand>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+pub static SETTINGS: Lazy<Mutex<Settings>> = Lazy::new(|| Mutex::new(Settings::default()));
+
+/// Initializes the shared simulation controls and the renderer defaults.
+pub fn apply_settings(settings: Settings) {
+    *FORCE_KIND.lock() = settings.force_kind;
+    *MULTIPOLE_ORDER.lock() = settings.multipole_order;
+    *FORCE_METHOD.lock() = settings.method;
+    *DECOMPOSITION.lock() = settings.decomposition;
+    *OPENING_CRITERION.lock() = settings.opening_criterion;
+    *MIN_NODE_SIZE.lock() = settings.min_node_size;
+    *DOMAIN.lock() = settings.domain;
+    *DOMAIN_POLICY.lock() = settings.domain_policy;
+    GRAVITY_ENABLED.store(settings.gravity_enabled, Ordering::Relaxed);
+    REFIT_INTERVAL.store(settings.refit_interval, Ordering::Relaxed);
+    *CAPTURE_RADIUS.lock() = settings.capture_radius;
+    *RESTITUTION.lock() = settings.restitution;
+    *MERGE_THRESHOLD.lock() = settings.merge_threshold;
+    STEPS_PER_FRAME.store(settings.steps_per_frame, Ordering::Relaxed);
+    STEP_DELAY_MS.store(settings.step_delay_ms, Ordering::Relaxed);
+    MAX_STEPS_PER_SECOND.store(settings.max_steps_per_second, Ordering::Relaxed);
+    *DT.lock() = settings.dt;
+    *ADAPTIVE_TIMESTEP.lock() = settings.adaptive_timestep;
+    *BLOCK_TIMESTEPS.lock() = settings.block_timesteps;
+    NEAR_FIELD_SUBSTEPS.store(settings.near_field_substeps, Ordering::Relaxed);
+    *PAUSE_ON_INSTABILITY.lock() = settings.pause_on_instability;
+    REWIND_CAPACITY.store(settings.rewind_capacity, Ordering::Relaxed);
+    REWIND_INTERVAL.store(settings.rewind_interval, Ordering::Relaxed);
+    *INTEGRATOR.lock() = settings.integrator;
+    *MAX_SPEED.lock() = settings.max_speed;
+    PIN_COM.store(settings.pin_com, Ordering::Relaxed);
+    *THETA.lock() = settings.theta;
+    *EPSILON.lock() = settings.epsilon;
+    SOFTENING_BY_RADIUS.store(settings.softening_by_radius, Ordering::Relaxed);
+    *MASS_SOFTENING.lock() = settings.mass_softening;
+    *MIN_INTERACTION_DISTANCE.lock() = settings.min_interaction_distance;
+    F64_ACCUMULATION.store(settings.f64_accumulation, Ordering::Relaxed);
+    DIRECT_ANCHORS.store(settings.direct_anchors, Ordering::Relaxed);
+    CONSERVE_MOMENTUM.store(settings.enforce_momentum_conservation, Ordering::Relaxed);
+    *ROTATING_FRAME.lock() = settings.rotating_frame;
+    *SOFT_WALL.lock() = settings.soft_wall;
+    EMITTERS.lock().clone_from(&settings.emitters);
+    MAX_BODIES.store(settings.max_bodies, Ordering::Relaxed);
+    *SETTINGS.lock() = settings;
+}
+
+/// Maximum on-screen length of the scale bar in pixels.
+const SCALE_BAR_PX: f32 = 150.0;
+const SCALE_BAR_MARGIN: f32 = 20.0;
+/// How long a notice like a saved screenshot takes to fade out.
+const NOTICE_DURATION: Duration = Duration::from_secs(3);
+/// Messages kept in `STATUS`, older ones are dropped.
+const STATUS_CAPACITY: usize = 5;
+
+/// Colors of `ColorMode::RootQuadrant`, indexed like `Quad::find_quadrant`:
+/// SW, SE, NW, NE.
+const QUADRANT_COLORS: [[u8; 4]; 4] = [
+    [0xff, 0x60, 0x60, 0xff],
+    [0x60, 0xd0, 0x60, 0xff],
+    [0x60, 0x90, 0xff, 0xff],
+    [0xff, 0xd0, 0x40, 0xff],
+];
+
+/// Most bodies `ColorMode::ForceError` compares against direct summation,
+/// which costs O(n²) per snapshot.
+const MAX_ERROR_BODIES: usize = 5000;
+
+/// Bodies the theta auto-tune compares against direct summation, a random
+/// subsample beyond this.
+const MAX_TUNE_BODIES: usize = 500;
+/// Bisections of the theta auto-tune, between `TUNE_THETA_RANGE`.
+const TUNE_STEPS: usize = 12;
+const TUNE_THETA_RANGE: (f32, f32) = (0.05, 2.0);
+
+/// Leaf sizes a body may cross per step before `show_warnings` flags it,
+/// like a CFL number.
+const CFL_LIMIT: f32 = 1.0;
+
+/// Most vectors drawn by an overlay, bodies are strided past this.
+const MAX_VECTORS: usize = 10000;
+/// Screen length overlay vectors are clipped to.
+const MAX_VECTOR_PX: f32 = 200.0;
+/// Most line segments spent tracing body edges per frame.
+const MAX_EDGE_SEGMENTS: usize = 100000;
+/// Screen radius from which a body's edge is traced, smaller ones are always
+/// plain circles.
+const EDGE_MIN_RADIUS_PX: f32 = 4.0;
+/// Side in pixels of the screen cells bodies are aggregated into.
+const DECIMATE_CELL_PX: usize = 2;
+
+const BOOKMARK_KEYS: [VirtualKeyCode; 9] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum QuadtreeView {
+    /// Every cell filled by depth.
+    Cells,
+    /// A point at the center of mass of every occupied leaf.
+    LeafMasses,
+}
+
+impl QuadtreeView {
+    const ALL: [QuadtreeView; 2] = [QuadtreeView::Cells, QuadtreeView::LeafMasses];
+
+    fn name(self) -> &'static str {
+        match self {
+            QuadtreeView::Cells => "Cells",
+            QuadtreeView::LeafMasses => "Leaf Masses",
+        }
+    }
+}
+
+/// Origin positions are drawn relative to, only for display.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ReferenceFrame {
+    World,
+    CenterOfMass,
+    /// The body with this id, back to `World` once it's gone.
+    Body(u64),
+}
+
+impl ReferenceFrame {
+    fn name(self) -> &'static str {
+        match self {
+            ReferenceFrame::World => "World",
+            ReferenceFrame::CenterOfMass => "Center of Mass",
+            ReferenceFrame::Body(_) => "Body",
+        }
+    }
+}
+
+/// What the drawn size of a body follows, only for display.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RadiusMode {
+    /// The body's own radius, `mass.cbrt()` for generated ones.
+    Physical,
+    /// `log10(mass)`, at least 1, so a huge central mass and the light
+    /// bodies around it are visible at the same zoom.
+    LogMass,
+    /// The same size for every body.
+    Fixed,
+}
+
+impl RadiusMode {
+    const ALL: [RadiusMode; 3] = [RadiusMode::Physical, RadiusMode::LogMass, RadiusMode::Fixed];
+
+    fn name(self) -> &'static str {
+        match self {
+            RadiusMode::Physical => "Physical",
+            RadiusMode::LogMass => "Log Mass",
+            RadiusMode::Fixed => "Fixed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ColorMode {
+    /// Everything in the foreground color.
+    Uniform,
+    /// Each body's own `color`.
+    PerBody,
+    /// Gradient from the slowest to the fastest body.
+    Speed,
+    /// Gradient over the logarithm of mass.
+    Mass,
+    /// Which child of the root quad each body falls in.
+    RootQuadrant,
+    /// Relative error of the tree's field against direct summation, up to
+    /// `MAX_ERROR_BODIES` bodies.
+    ForceError,
+}
+
+impl ColorMode {
+    const ALL: [ColorMode; 6] = [
+        ColorMode::Uniform,
+        ColorMode::PerBody,
+        ColorMode::Speed,
+        ColorMode::Mass,
+        ColorMode::RootQuadrant,
+        ColorMode::ForceError,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ColorMode::Uniform => "Uniform",
+            ColorMode::PerBody => "Per Body",
+            ColorMode::Speed => "Speed",
+            ColorMode::Mass => "Mass",
+            ColorMode::RootQuadrant => "Root Quadrant",
+            ColorMode::ForceError => "Force Error",
+        }
+    }
+
+    /// Quantity mapped onto the gradient, `None` for the fixed colors.
+    fn value(self, body: &Body) -> Option<f32> {
+        match self {
+            ColorMode::Uniform
+            | ColorMode::PerBody
+            | ColorMode::RootQuadrant
+            | ColorMode::ForceError => None,
+            ColorMode::Speed => Some(body.vel.mag()),
+            ColorMode::Mass => Some(body.mass.max(f32::MIN_POSITIVE).ln()),
+        }
+    }
+
+    /// The quantity behind a gradient value, for the color bar.
+    fn label(self, value: f32) -> String {
+        match self {
+            ColorMode::Mass => format!("{:.3}", value.exp()),
+            ColorMode::ForceError => format!("{:.1e}", 10f32.powf(value)),
+            _ => format!("{:.3}", value),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Action {
+    ToggleSettings,
+    TogglePause,
+    ToggleReverse,
+    ToggleMeasure,
+    ToggleFollow,
+    ToggleHelp,
+    Cancel,
+    ClearAll,
+    DeeperCells,
+    ShallowerCells,
+    Screenshot,
+    Rewind,
+    CycleColorMode,
+    CycleQuadtreeView,
+}
+
+/// Shows `text` in the status line for `NOTICE_DURATION`, from either thread.
+pub fn notify(text: String) {
+    let mut status = STATUS.lock();
+    if status.len() >= STATUS_CAPACITY {
+        status.pop_front();
+    }
+    status.push_back((text, Instant::now()));
+}
+
+fn resume() {
+    PAUSED.store(false, Ordering::Relaxed);
+    STATS.lock().instability = None;
+    if let Some(thread) = SIMULATION_THREAD.get() {
+        thread.unpark();
+    }
+}
+
+/// The option after `current`, wrapping around.
+fn cycle<T: Copy + PartialEq>(options: &[T], current: T) -> T {
+    let i = options
+        .iter()
+        .position(|&option| option == current)
+        .unwrap_or(0);
+    options[(i + 1) % options.len()]
+}
+
+struct KeyBinding {
+    keys: &'static [VirtualKeyCode],
+    label: &'static str,
+    description: &'static str,
+    action: Action,
+}
+
+/// Every single key binding, dispatched by `Renderer::input` and listed
+/// by the help window.
+const KEY_BINDINGS: &[KeyBinding] = &[
+    KeyBinding {
+        keys: &[VirtualKeyCode::E],
+        label: "E",
+        description: "Open the settings window",
+        action: Action::ToggleSettings,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::Space],
+        label: "Space",
+        description: "Pause or continue",
+        action: Action::TogglePause,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::R],
+        label: "R",
+        description: "Run backward or forward in time",
+        action: Action::ToggleReverse,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::M],
+        label: "M",
+        description: "Toggle measure mode",
+        action: Action::ToggleMeasure,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::F],
+        label: "F",
+        description: "Draw relative to the selected body, centering it",
+        action: Action::ToggleFollow,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::F1, VirtualKeyCode::Slash],
+        label: "F1, ?",
+        description: "Show this help",
+        action: Action::ToggleHelp,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::Escape],
+        label: "Escape",
+        description: "Close the help, deselect and cancel measuring",
+        action: Action::Cancel,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::Delete],
+        label: "Delete",
+        description: "Remove all bodies",
+        action: Action::ClearAll,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::RBracket],
+        label: "]",
+        description: "Draw quadtree cells one level deeper",
+        action: Action::DeeperCells,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::LBracket],
+        label: "[",
+        description: "Draw quadtree cells one level shallower",
+        action: Action::ShallowerCells,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::P],
+        label: "P",
+        description: "Save a screenshot to the working directory",
+        action: Action::Screenshot,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::Back],
+        label: "Backspace",
+        description: "Rewind to the last snapshot",
+        action: Action::Rewind,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::C],
+        label: "C",
+        description: "Cycle the body color mode",
+        action: Action::CycleColorMode,
+    },
+    KeyBinding {
+        keys: &[VirtualKeyCode::V],
+        label: "V",
+        description: "Cycle the quadtree view",
+        action: Action::CycleQuadtreeView,
+    },
+];
+
+/// Mouse and modifier bindings, which are handled in place.
+const OTHER_BINDINGS: &[(&str, &str)] = &[
+    ("Scroll", "Zoom"),
+    ("Middle drag", "Grab the view"),
+    ("Left click", "Select a body, or pick one to measure"),
+    ("Alt + left drag", "Kick the selected body along the drag"),
+    (
+        "Right drag",
+        "Spawn a body, scrolling or winding around it sets its mass",
+    ),
+    ("Shift + right click", "Spawn a body on a circular orbit"),
+    (
+        "Ctrl + right drag",
+        "Spawn a ring of bodies on circular orbits",
+    ),
+    ("Ctrl + 1-9", "Save a camera bookmark"),
+    ("1-9", "Jump to a camera bookmark"),
+];
 
 pub struct Renderer {
     pos: Vec2,
     scale: f32,
+    width: u16,
+    height: u16,
+
+    bookmarks: [Option<(Vec2, f32)>; 9],
 
     settings_window_open: bool,
+    help_open: bool,
+
+    /// Upper bound on rendered frames per second, 0 for uncapped.
+    max_fps: usize,
+    last_frame: Instant,
+    /// Milliseconds between the most recent frames, oldest first.
+    frame_times: VecDeque<f32>,
+    frame_time_history: usize,
+    show_frame_times: bool,
+    /// Outlines instead of fills, to see overdraw.
+    wireframe: bool,
+    /// Primitives drawn in each frame of the last second, oldest first.
+    draw_counts: VecDeque<(Instant, DrawCounts)>,
 
     show_bodies: bool,
+    color_mode: ColorMode,
     show_quadtree: bool,
+    quadtree_view: QuadtreeView,
+    show_scale_bar: bool,
+    show_com: bool,
+    /// Ellipse of the principal axes at one RMS extent, while the shape
+    /// is computed.
+    show_shape: bool,
+    /// Draws bodies in `warning_color` whatever the color mode.
+    show_warnings: bool,
+    /// Outline of the root quad, which encloses every body.
+    show_bounds: bool,
+    /// Scroll steps to double or halve the scale.
+    zoom_steps: f32,
+    invert_scroll: bool,
+    /// Scrolling during a spawn drag sets the mass instead of winding.
+    scroll_spawn_mass: bool,
+    show_velocities: bool,
+    show_accelerations: bool,
+
+    /// Angular velocity of the displayed frame, without affecting physics.
+    co_rotation: f32,
+    reference_frame: ReferenceFrame,
+    /// Frame `frame_origin` was placed for, to keep the view in place when
+    /// `reference_frame` changes.
+    shown_frame: ReferenceFrame,
+    /// World position of the reference frame's origin, the center of mass
+    /// relative to it and `(cos, sin)` of the display rotation this frame.
+    frame_origin: Vec2,
+    frame_center: Vec2,
+    frame_rotation: Vec2,
+    /// Length of the overlay vectors per unit of velocity or acceleration,
+    /// or the length of all of them when only showing the direction.
+    vel_scale: f32,
+    normalize_velocities: bool,
+    acc_scale: f32,
+    normalize_accelerations: bool,
+
+    background: [u8; 4],
+
+    recording: bool,
+    record_dir: String,
+    /// File the bodies are exported to and imported from.
+    json_path: String,
+    record_stride: usize,
+    record_index: usize,
+    record_counter: usize,
+
+    radius_mode: RadiusMode,
+    render_radius_scale: f32,
+    glow: bool,
+    glow_intensity: f32,
+    glow_cap: usize,
+    /// Draws larger bodies first so smaller ones stay on top, at the cost
+    /// of a sort per frame. `draw_order` is the reused index buffer.
+    sort_by_size: bool,
+    draw_order: Vec<usize>,
+    min_render_radius_px: f32,
+    /// Segments of the polygon tracing the edge of large bodies, 0 for plain
+    /// circles.
+    circle_segments: usize,
+    /// Aggregate bodies into screen cells once more than `decimate_density`
+    /// are visible per pixel on average.
+    decimate: bool,
+    decimate_density: f32,
+
+    /// Depths of the drawn cells, all leaf depths if `None`.
+    depth_range: Option<(usize, usize)>,
+    /// Shallowest and deepest leaf of the cached tree, rescanned when stale
+    /// after a new snapshot.
+    leaf_depths: (usize, usize),
+    leaf_depths_stale: bool,
+    hide_empty_cells: bool,
+    /// Most cells drawn per frame, the walk stops once they're used up.
+    max_cells: usize,
+    /// Whether the last frame hit `max_cells`.
+    cells_truncated: bool,
+    show_depth_grid: bool,
+    grid_depth: usize,
+
+    show_potential: bool,
+    potential_resolution: usize,
 
-    depth_range: (usize, usize),
+    /// Predicted orbit of a massless particle at the cursor in the frozen
+    /// potential of the cached tree.
+    trace: bool,
+    trace_steps: usize,
+    trace_start: Option<Vec2>,
+    trace_path: Vec<Vec2>,
+    trace_stale: bool,
+    /// Per body errors of `ColorMode::ForceError`, one set per snapshot.
+    force_errors: Vec<f32>,
+    force_errors_stale: bool,
+    /// Shows the potential and field of the cached tree at the cursor.
+    probe: bool,
+    /// World position under the cursor.
+    cursor: Vec2,
 
     spawn_body: Option<Body>,
+    spawn_charge: f32,
+    /// Center and radius of the ring being dragged out.
+    ring: Option<(Vec2, f32)>,
+    ring_count: usize,
+    /// Mass the ring orbits, 0 to use the mass already inside it.
+    ring_central_mass: f32,
     angle: Option<f32>,
     total: Option<f32>,
 
     confirmed_bodies: Option<Body>,
 
+    selected: Option<u64>,
+    /// Start and end of the drag kicking the selected body.
+    kick: Option<(Vec2, Vec2)>,
+    measuring: bool,
+    measure: [Option<u64>; 2],
+
+    body_count: usize,
+    seed: u64,
+    disc: DiscOptions,
+    /// Inputs of the elliptical disc generator.
+    bar_axis_ratio: f32,
+    bar_central_mass: bool,
+    mass_factor: f32,
+    /// Inputs of the run until controls.
+    run_until: usize,
+    run_more: usize,
+
+    settings: Settings,
+    save_settings: bool,
+    clear_all: bool,
+    /// Largest relative force error the theta auto-tune accepts, and
+    /// whether it runs after this frame's settings window.
+    tune_tolerance: f32,
+    tune_theta: bool,
+    screenshot_pending: bool,
+
     bodies: Vec<Body>,
-    quadtree: Vec<Node>,
+    quadtree: Quadtree,
+}
+
+impl Renderer {
+    /// World units covered by a single screen pixel at the current zoom.
+    fn world_per_pixel(&self) -> f32 {
+        2.0 * self.scale / self.height as f32
+    }
+
+    fn screen_to_world(&self, x: f32, y: f32) -> Vec2 {
+        let mut pos = Vec2::new(x, y) * (2.0 / self.height as f32);
+        pos.y = 1.0 - pos.y;
+        pos.x -= self.width as f32 / self.height as f32;
+        pos * self.scale + self.pos
+    }
+
+    /// Largest 1, 2 or 5 × 10ⁿ world length that fits in `SCALE_BAR_PX`
+    /// pixels, along with the number of decimals needed to print it.
+    fn scale_bar_length(&self) -> (f32, usize) {
+        let max_length = SCALE_BAR_PX * self.world_per_pixel();
+        let exp = max_length.log10().floor();
+        let base = 10f32.powf(exp);
+        let mantissa = [5.0, 2.0, 1.0]
+            .into_iter()
+            .find(|&m| m * base <= max_length)
+            .unwrap_or(1.0);
+        (mantissa * base, (-exp).max(0.0) as usize)
+    }
+
+    /// White or black, whichever contrasts more with the background.
+    fn foreground(&self) -> [u8; 4] {
+        let [r, g, b, _] = self.background.map(|c| c as f32 / 255.0);
+        let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        if luma > 0.5 {
+            [0, 0, 0, 0xff]
+        } else {
+            [0xff; 4]
+        }
+    }
+
+    /// Rasterizes the cached bodies as currently seen on screen.
+    fn capture(&self) -> RgbaImage {
+        let view = View {
+            pos: self.pos,
+            scale: self.scale,
+            width: self.width as u32,
+            height: self.height as u32,
+        };
+        let bodies: Vec<Body> = if self.show_bodies {
+            let view_body = |body: &Body| Body {
+                pos: self.to_view(body.pos),
+                ..*body
+            };
+            self.bodies.iter().map(view_body).collect()
+        } else {
+            Vec::new()
+        };
+        capture::rasterize_bodies(
+            &bodies,
+            &view,
+            |body| self.render_radius(body),
+            self.background,
+            self.foreground(),
+        )
+    }
+
+    fn save_screenshot(&mut self) {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let name = format!("screenshot_{}.png", secs);
+        match self.capture().save(&name) {
+            Ok(()) => notify(format!("Saved {}", name)),
+            Err(err) => notify(format!("Failed to save {}: {}", name, err)),
+        }
+    }
+
+    fn record_frame(&mut self) {
+        if self.record_counter % self.record_stride.max(1) == 0 {
+            let name = format!("frame_{:05}.png", self.record_index);
+            let path = Path::new(&self.record_dir).join(name);
+            if let Err(err) = self.capture().save(&path) {
+                eprintln!("Failed to save {}: {}", path.display(), err);
+                self.recording = false;
+            }
+            self.record_index += 1;
+        }
+        self.record_counter += 1;
+    }
+
+    /// Bodies evenly spaced around a circle, orbiting the mass inside it in
+    /// the same direction as the generated discs.
+    fn ring_bodies(&self, (center, radius): (Vec2, f32)) -> Vec<Body> {
+        if radius <= 0.0 || self.ring_count == 0 {
+            return Vec::new();
+        }
+
+        let mass = if self.ring_central_mass > 0.0 {
+            self.ring_central_mass
+        } else {
+            self.bodies
+                .iter()
+                .filter(|body| (body.pos - center).mag() < radius)
+                .map(|body| body.mass)
+                .sum()
+        };
+        let speed = (mass / radius).sqrt();
+
+        (0..self.ring_count)
+            .map(|i| {
+                let (sin, cos) = (i as f32 * TAU / self.ring_count as f32).sin_cos();
+                let pos = center + Vec2::new(cos, sin) * radius;
+                let mut body = Body::new(pos, Vec2::new(sin, -cos) * speed, 1.0, 1.0);
+                body.charge = self.spawn_charge;
+                body
+            })
+            .collect()
+    }
+
+    fn spawn_ring(&mut self, ring: (Vec2, f32)) {
+        let bodies = self.ring_bodies(ring);
+        let mut commands = COMMANDS.lock();
+        for body in bodies {
+            self.bodies.push(body);
+            commands.push(SimCommand::Spawn(body));
+        }
+    }
+
+    /// Velocity of a circular orbit at `pos` around the body pulling hardest
+    /// there, in the same direction as the generated discs.
+    fn orbit_velocity(&self, pos: Vec2) -> Vec2 {
+        let pull = |body: &Body| body.mass / (body.pos - pos).mag_sq();
+        let dominant = self
+            .bodies
+            .iter()
+            .filter(|body| body.pos != pos)
+            .max_by(|a, b| pull(a).total_cmp(&pull(b)));
+
+        let Some(center) = dominant else {
+            return Vec2::zero();
+        };
+
+        let d = pos - center.pos;
+        let r = d.mag();
+        let speed = (center.mass / r).sqrt();
+        center.vel + Vec2::new(d.y, -d.x) / r * speed
+    }
+
+    /// Integrates a massless particle from the cursor, starting on a circular
+    /// orbit, for `trace_steps` steps against the cached tree, which stays
+    /// frozen. Stops early once the particle falls into a sink.
+    fn trace_orbit(&self) -> Vec<Vec2> {
+        let Some(mut pos) = self.trace_start else {
+            return Vec::new();
+        };
+        let dt = STATS.lock().dt;
+        let sinks: Vec<&Body> = self.bodies.iter().filter(|body| body.is_sink).collect();
+        let anchors = self.anchors();
+        let e_sq = self.quadtree.e_sq;
+        let field = |pos| self.quadtree.acc(pos) + utils::direct_field(&anchors, pos, e_sq);
+
+        let mut vel = self.orbit_velocity(pos);
+        let mut acc = field(pos);
+        let mut path = vec![pos];
+        for _ in 0..self.trace_steps {
+            vel += acc * (0.5 * dt);
+            pos += vel * dt;
+            acc = field(pos);
+            vel += acc * (0.5 * dt);
+
+            if !(pos.x.is_finite() && pos.y.is_finite()) {
+                break;
+            }
+            path.push(pos);
+            if sinks
+                .iter()
+                .any(|sink| (sink.pos - pos).mag_sq() <= sink.radius * sink.radius)
+            {
+                break;
+            }
+        }
+        path
+    }
+
+    /// Writes the current controls on top of the settings loaded at startup.
+    fn save_settings(&mut self) {
+        let settings = &mut self.settings;
+        settings.body_count = self.body_count;
+        settings.seed = self.seed;
+        settings.disc = self.disc;
+        settings.force_kind = *FORCE_KIND.lock();
+        settings.multipole_order = *MULTIPOLE_ORDER.lock();
+        settings.method = *FORCE_METHOD.lock();
+        settings.decomposition = *DECOMPOSITION.lock();
+        settings.opening_criterion = *OPENING_CRITERION.lock();
+        settings.min_node_size = *MIN_NODE_SIZE.lock();
+        settings.domain = *DOMAIN.lock();
+        settings.domain_policy = *DOMAIN_POLICY.lock();
+        settings.gravity_enabled = GRAVITY_ENABLED.load(Ordering::Relaxed);
+        settings.refit_interval = REFIT_INTERVAL.load(Ordering::Relaxed);
+        settings.capture_radius = *CAPTURE_RADIUS.lock();
+        settings.restitution = *RESTITUTION.lock();
+        settings.merge_threshold = *MERGE_THRESHOLD.lock();
+        settings.steps_per_frame = STEPS_PER_FRAME.load(Ordering::Relaxed);
+        settings.step_delay_ms = STEP_DELAY_MS.load(Ordering::Relaxed);
+        settings.max_steps_per_second = MAX_STEPS_PER_SECOND.load(Ordering::Relaxed);
+        settings.max_fps = self.max_fps;
+        settings.dt = *DT.lock();
+        settings.adaptive_timestep = *ADAPTIVE_TIMESTEP.lock();
+        settings.block_timesteps = *BLOCK_TIMESTEPS.lock();
+        settings.near_field_substeps = NEAR_FIELD_SUBSTEPS.load(Ordering::Relaxed);
+        settings.pause_on_instability = *PAUSE_ON_INSTABILITY.lock();
+        settings.rewind_capacity = REWIND_CAPACITY.load(Ordering::Relaxed);
+        settings.rewind_interval = REWIND_INTERVAL.load(Ordering::Relaxed);
+        settings.integrator = *INTEGRATOR.lock();
+        settings.max_speed = *MAX_SPEED.lock();
+        settings.pin_com = PIN_COM.load(Ordering::Relaxed);
+        settings.theta = *THETA.lock();
+        settings.epsilon = *EPSILON.lock();
+        settings.softening_by_radius = SOFTENING_BY_RADIUS.load(Ordering::Relaxed);
+        settings.mass_softening = *MASS_SOFTENING.lock();
+        settings.min_interaction_distance = *MIN_INTERACTION_DISTANCE.lock();
+        settings.f64_accumulation = F64_ACCUMULATION.load(Ordering::Relaxed);
+        settings.direct_anchors = DIRECT_ANCHORS.load(Ordering::Relaxed);
+        settings.enforce_momentum_conservation = CONSERVE_MOMENTUM.load(Ordering::Relaxed);
+        settings.rotating_frame = *ROTATING_FRAME.lock();
+        settings.soft_wall = *SOFT_WALL.lock();
+        settings.emitters.clone_from(&EMITTERS.lock());
+        settings.max_bodies = MAX_BODIES.load(Ordering::Relaxed);
+
+        settings.background = self.background;
+        settings.render_radius_scale = self.render_radius_scale;
+        settings.min_render_radius_px = self.min_render_radius_px;
+        settings.show_scale_bar = self.show_scale_bar;
+        settings.show_com = self.show_com;
+        settings.show_bounds = self.show_bounds;
+        settings.zoom_steps = self.zoom_steps;
+        settings.invert_scroll = self.invert_scroll;
+        settings.scroll_spawn_mass = self.scroll_spawn_mass;
+        settings.record_dir = self.record_dir.clone();
+        settings.record_stride = self.record_stride;
+
+        match settings.save(SETTINGS_PATH) {
+            Ok(()) => notify(format!("Saved {}", SETTINGS_PATH)),
+            Err(err) => eprintln!("Failed to save {}: {}", SETTINGS_PATH, err),
+        }
+    }
+
+    /// Mass-weighted centroid of the cached bodies.
+    fn center_of_mass(&self) -> Option<Vec2> {
+        let mut pos = Vec2::zero();
+        let mut mass = 0.0;
+        for body in &self.bodies {
+            pos += body.pos * body.mass;
+            mass += body.mass;
+        }
+        (mass != 0.0).then(|| pos / mass)
+    }
+
+    fn perform(&mut self, action: Action) {
+        match action {
+            Action::ToggleSettings => self.settings_window_open ^= true,
+            Action::TogglePause => {
+                if PAUSED.load(Ordering::Relaxed) {
+                    resume();
+                } else {
+                    PAUSED.store(true, Ordering::Relaxed);
+                }
+            }
+            Action::ToggleMeasure => {
+                self.measuring = !self.measuring;
+                self.measure = [None; 2];
+            }
+            Action::ToggleFollow => {
+                self.reference_frame = match (self.reference_frame, self.selected) {
+                    (ReferenceFrame::Body(_), _) | (_, None) => ReferenceFrame::World,
+                    (_, Some(id)) => ReferenceFrame::Body(id),
+                };
+                self.update_reference_frame();
+                if let ReferenceFrame::Body(id) = self.reference_frame {
+                    if let Some(pos) = self.find_body(id).map(|body| body.pos) {
+                        self.pos = self.to_view(pos);
+                    }
+                }
+            }
+            Action::ToggleHelp => self.help_open ^= true,
+            Action::Cancel => {
+                self.help_open = false;
+                self.selected = None;
+                self.measure = [None; 2];
+            }
+            Action::ClearAll => {
+                COMMANDS.lock().push(SimCommand::Reset(Vec::new()));
+                self.bodies.clear();
+                self.quadtree.nodes.clear();
+                self.leaf_depths_stale = true;
+                self.spawn_body = None;
+                self.confirmed_bodies = None;
+                self.selected = None;
+                self.reference_frame = ReferenceFrame::World;
+                self.measure = [None; 2];
+            }
+            Action::DeeperCells | Action::ShallowerCells => {
+                let (min, max) = self.depth_range.unwrap_or(self.leaf_depths);
+                let max = match action {
+                    Action::DeeperCells => max + 1,
+                    _ => max.saturating_sub(1),
+                };
+                self.depth_range = Some((min.min(max), max));
+                self.clamp_depth_range();
+            }
+            Action::Screenshot => self.screenshot_pending = true,
+            Action::ToggleReverse => {
+                let reversed = !REVERSED.fetch_xor(true, Ordering::Relaxed);
+                notify(
+                    if reversed {
+                        "Running backward"
+                    } else {
+                        "Running forward"
+                    }
+                    .to_string(),
+                );
+            }
+            Action::Rewind => COMMANDS.lock().push(SimCommand::Rewind),
+            Action::CycleColorMode => {
+                self.color_mode = cycle(&ColorMode::ALL, self.color_mode);
+                notify(format!("Color: {}", self.color_mode.name()));
+            }
+            Action::CycleQuadtreeView => {
+                self.quadtree_view = cycle(&QuadtreeView::ALL, self.quadtree_view);
+                notify(format!("Quadtree: {}", self.quadtree_view.name()));
+            }
+        }
+    }
+
+    /// Shallowest and deepest leaf of the cached tree.
+    fn leaf_depth_span(&self) -> (usize, usize) {
+        if self.quadtree.nodes.is_empty() {
+            return (0, 0);
+        }
+
+        let mut stack = Vec::new();
+        stack.push((Quadtree::ROOT, 0));
+
+        let mut min_depth = usize::MAX;
+        let mut max_depth = 0;
+        while let Some((node, depth)) = stack.pop() {
+            let node = &self.quadtree.nodes[node];
+
+            if node.is_leaf() {
+                min_depth = min_depth.min(depth);
+                max_depth = max_depth.max(depth);
+            } else {
+                for i in 0..4 {
+                    stack.push((node.children + i, depth + 1));
+                }
+            }
+        }
+
+        (min_depth, max_depth)
+    }
+
+    /// Keeps the depth range ordered and within the tree.
+    fn clamp_depth_range(&mut self) {
+        if let Some((min, max)) = self.depth_range {
+            let max = max.min(self.leaf_depths.1);
+            self.depth_range = Some((min.min(max), max));
+        }
+    }
+
+    /// Places the display frame for this frame, keeping the view in place
+    /// if the reference frame changed or its body is gone, e.g. accreted.
+    fn update_reference_frame(&mut self) {
+        let origin = match self.reference_frame {
+            ReferenceFrame::World => Vec2::zero(),
+            ReferenceFrame::CenterOfMass => self.center_of_mass().unwrap_or_default(),
+            ReferenceFrame::Body(id) => match self.find_body(id).map(|body| body.pos) {
+                Some(pos) => pos,
+                None => {
+                    self.reference_frame = ReferenceFrame::World;
+                    Vec2::zero()
+                }
+            },
+        };
+        if self.reference_frame != self.shown_frame {
+            self.pos += self.frame_origin - origin;
+            self.shown_frame = self.reference_frame;
+        }
+        self.frame_origin = origin;
+
+        self.frame_center = Vec2::zero();
+        self.frame_rotation = Vec2::unit_x();
+        if self.co_rotation != 0.0 {
+            let angle = -self.co_rotation * STATS.lock().time;
+            self.frame_center = self.center_of_mass().unwrap_or_default() - origin;
+            self.frame_rotation = Vec2::new(angle.cos(), angle.sin());
+        }
+    }
+
+    /// Maps a world position into the co-rotating display frame.
+    fn to_view(&self, pos: Vec2) -> Vec2 {
+        self.frame_center
+            + rotate(
+                pos - self.frame_origin - self.frame_center,
+                self.frame_rotation,
+            )
+    }
+
+    fn from_view(&self, pos: Vec2) -> Vec2 {
+        let inverse = Vec2::new(self.frame_rotation.x, -self.frame_rotation.y);
+        self.frame_origin + self.frame_center + rotate(pos - self.frame_center, inverse)
+    }
+
+    fn to_view_dir(&self, dir: Vec2) -> Vec2 {
+        rotate(dir, self.frame_rotation)
+    }
+
+    /// View space offset an overlay draws for `vector`, clipped to
+    /// `MAX_VECTOR_PX` on screen.
+    fn overlay_vector(&self, vector: Vec2, scale: f32, normalize: bool) -> Vec2 {
+        let mag = vector.mag();
+        if mag == 0.0 {
+            return Vec2::zero();
+        }
+        let length = if normalize { scale } else { mag * scale };
+        let length = length.min(MAX_VECTOR_PX * self.world_per_pixel());
+        self.to_view_dir(vector) * (length / mag)
+    }
+
+    /// Draws a world space cell, as an outline once the display rotates.
+    fn draw_cell(&self, ctx: &mut Canvas, min: Vec2, max: Vec2, color: [u8; 4]) {
+        if self.frame_rotation == Vec2::unit_x() {
+            ctx.draw_rect(self.to_view(min), self.to_view(max), color);
+            return;
+        }
+        self.draw_outline(ctx, min, max, color);
+    }
+
+    fn draw_outline(&self, ctx: &mut Canvas, min: Vec2, max: Vec2, color: [u8; 4]) {
+        let corners =
+            [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)].map(|p| self.to_view(p));
+        for (&a, &b) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+            ctx.draw_line(a, b, color);
+        }
+    }
+
+    /// Rough memory footprint of the cached bodies and tree nodes in bytes.
+    fn memory_usage(&self) -> usize {
+        self.quadtree.node_count() * std::mem::size_of::<Node>()
+            + self.bodies.len() * std::mem::size_of::<Body>()
+    }
+
+    fn find_body(&self, id: u64) -> Option<&Body> {
+        self.bodies.iter().find(|body| body.id == id)
+    }
+
+    /// The two bodies picked in measure mode, if both still exist.
+    fn measured_pair(&self) -> Option<(&Body, &Body)> {
+        let [Some(a), Some(b)] = self.measure else {
+            return None;
+        };
+        Some((self.find_body(a)?, self.find_body(b)?))
+    }
+
+    /// Closest cached body whose drawn disc covers `pos`.
+    fn body_at(&self, pos: Vec2) -> Option<&Body> {
+        let distance = |body: &Body| (body.pos - pos).mag();
+        self.bodies
+            .iter()
+            .filter(|body| distance(body) <= self.render_radius(body))
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+    }
+
+    /// Samples the potential of the cached tree on a grid of
+    /// `potential_resolution` rows over the visible region and draws it
+    /// log-scaled, so zooming in also increases the detail.
+    fn draw_potential(&self, ctx: &mut Canvas) {
+        let rows = self.potential_resolution.max(1);
+        let cell = 2.0 * self.scale / rows as f32;
+        let min = self.screen_to_world(0.0, self.height as f32);
+        let max = self.screen_to_world(self.width as f32, 0.0);
+        let cols = ((max.x - min.x) / cell).ceil() as usize;
+
+        let mut samples = Vec::with_capacity(rows * cols);
+        for y in 0..rows {
+            for x in 0..cols {
+                let corner = min + Vec2::new(x as f32, y as f32) * cell;
+                let sample = self.from_view(corner + Vec2::broadcast(cell * 0.5));
+                let phi = self.quadtree.potential(sample);
+                samples.push((corner, phi.signum() * phi.abs().ln_1p()));
+            }
+        }
+
+        let (lo, hi) = samples
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &(_, s)| {
+                (lo.min(s), hi.max(s))
+            });
+        let range = (hi - lo).max(f32::EPSILON);
+        for (corner, s) in samples {
+            let color = gradient((s - lo) / range);
+            ctx.draw_rect(corner, corner + Vec2::broadcast(cell), color);
+        }
+    }
+
+    /// Color of every cached body under the current color mode, with the
+    /// warning colors on top while they are shown.
+    fn body_colors(&self) -> Vec<[u8; 4]> {
+        let mut colors = self.mode_colors();
+        if self.show_warnings {
+            let (max_speed, dt) = (*MAX_SPEED.lock(), STATS.lock().dt);
+            for (color, body) in colors.iter_mut().zip(&self.bodies) {
+                if let Some(warning) = self.warning_color(body, max_speed, dt) {
+                    *color = warning;
+                }
+            }
+        }
+        colors
+    }
+
+    /// Red for bodies with a non-finite position, velocity or acceleration,
+    /// orange for those held at `max_speed` and yellow for those crossing
+    /// more than `CFL_LIMIT` times their leaf per step.
+    fn warning_color(&self, body: &Body, max_speed: Option<f32>, dt: f32) -> Option<[u8; 4]> {
+        let finite = |v: Vec2| v.x.is_finite() && v.y.is_finite();
+        if !(finite(body.pos) && finite(body.vel) && finite(body.acc)) {
+            return Some([0xff, 0x20, 0x20, 0xff]);
+        }
+
+        let speed = body.vel.mag();
+        if max_speed.is_some_and(|max| speed >= max * (1.0 - 1e-4)) {
+            return Some([0xff, 0x80, 0x20, 0xff]);
+        }
+
+        let leaf = self.quadtree.leaf_at(body.pos)?;
+        let size = self.quadtree.nodes[leaf].quad.size;
+        (speed * dt.abs() > CFL_LIMIT * size).then_some([0xff, 0xe0, 0x20, 0xff])
+    }
+
+    fn mode_colors(&self) -> Vec<[u8; 4]> {
+        match self.color_mode {
+            ColorMode::Uniform => vec![self.foreground(); self.bodies.len()],
+            ColorMode::PerBody => self.bodies.iter().map(|body| body.color).collect(),
+            ColorMode::RootQuadrant => match self.quadtree.bounds() {
+                Some(root) => self
+                    .bodies
+                    .iter()
+                    .map(|body| QUADRANT_COLORS[root.find_quadrant(body.pos)])
+                    .collect(),
+                None => vec![self.foreground(); self.bodies.len()],
+            },
+            _ => match self.gradient_values() {
+                Some(values) => {
+                    let (lo, hi) = value_range(&values);
+                    let range = (hi - lo).max(f32::EPSILON);
+                    values
+                        .iter()
+                        .map(|v| {
+                            gradient(GRADIENT_START + (1.0 - GRADIENT_START) * (v - lo) / range)
+                        })
+                        .collect()
+                }
+                None => vec![self.foreground(); self.bodies.len()],
+            },
+        }
+    }
+
+    /// Value of each cached body the color mode maps onto the gradient,
+    /// `None` for the fixed colors or while there are no force errors.
+    fn gradient_values(&self) -> Option<Vec<f32>> {
+        match self.color_mode {
+            ColorMode::ForceError if self.force_errors.len() == self.bodies.len() => {
+                // Errors span decades, from machine precision to order one
+                Some(
+                    self.force_errors
+                        .iter()
+                        .map(|e| e.max(1e-7).log10())
+                        .collect(),
+                )
+            }
+            mode => self
+                .bodies
+                .iter()
+                .map(|body| mode.value(body))
+                .collect::<Option<Vec<f32>>>()
+                .filter(|values| !values.is_empty()),
+        }
+    }
+
+    /// The cached sinks if `DIRECT_ANCHORS` is on. They aren't in the tree
+    /// then, so their pull has to be summed exactly on top of it.
+    fn anchors(&self) -> Vec<Body> {
+        if !DIRECT_ANCHORS.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        self.bodies
+            .iter()
+            .filter(|body| body.is_sink)
+            .copied()
+            .collect()
+    }
+
+    /// `|a_tree - a_direct| / |a_direct|` of every cached body for the
+    /// cached tree, empty above `MAX_ERROR_BODIES` bodies.
+    fn compute_force_errors(&self) -> Vec<f32> {
+        if self.bodies.len() > MAX_ERROR_BODIES || self.quadtree.nodes.is_empty() {
+            return Vec::new();
+        }
+        let e_sq = self.quadtree.e_sq;
+        let anchors = self.anchors();
+        self.bodies
+            .iter()
+            .map(|body| {
+                let exact = utils::direct_field(&self.bodies, body.pos, e_sq);
+                let approx =
+                    self.quadtree.acc(body.pos) + utils::direct_field(&anchors, body.pos, e_sq);
+                (approx - exact).mag() / exact.mag().max(f32::MIN_POSITIVE)
+            })
+            .collect()
+    }
+
+    /// Bisects for the largest theta whose maximum relative error against
+    /// direct summation stays within `tune_tolerance` on the cached tree,
+    /// returning it with the error it reaches. Runs on up to
+    /// `MAX_TUNE_BODIES` random bodies, each summed exactly over all of
+    /// them, so it's only meant to run on demand.
+    fn tune_theta(&mut self) -> Option<(f32, f32)> {
+        if self.quadtree.nodes.is_empty() || self.bodies.is_empty() {
+            return None;
+        }
+        let mut rng = fastrand::Rng::with_seed(self.bodies.len() as u64);
+        let e_sq = self.quadtree.e_sq;
+        let samples: Vec<(Vec2, Vec2)> = (0..self.bodies.len().min(MAX_TUNE_BODIES))
+            .map(|_| {
+                let pos = self.bodies[rng.usize(..self.bodies.len())].pos;
+                (pos, utils::direct_field(&self.bodies, pos, e_sq))
+            })
+            .collect();
+        let anchors = self.anchors();
+
+        let t_sq = self.quadtree.t_sq;
+        let mut max_error = |theta: f32| {
+            self.quadtree.t_sq = theta * theta;
+            samples
+                .iter()
+                .map(|&(pos, exact)| {
+                    let approx = self.quadtree.acc(pos) + utils::direct_field(&anchors, pos, e_sq);
+                    (approx - exact).mag() / exact.mag().max(f32::MIN_POSITIVE)
+                })
+                .fold(0.0, f32::max)
+        };
+
+        let (mut lo, mut hi) = TUNE_THETA_RANGE;
+        let mut error = max_error(lo);
+        for _ in 0..TUNE_STEPS {
+            let mid = 0.5 * (lo + hi);
+            let mid_error = max_error(mid);
+            if mid_error <= self.tune_tolerance {
+                (lo, error) = (mid, mid_error);
+            } else {
+                hi = mid;
+            }
+        }
+        self.quadtree.t_sq = t_sq;
+        Some((lo, error))
+    }
+
+    /// Share of the cached bodies in each child of the root quad, in the
+    /// order of `Quad::find_quadrant`.
+    fn quadrant_shares(&self) -> Option<[f32; 4]> {
+        let root = self.quadtree.bounds()?;
+        let mut counts = [0usize; 4];
+        for body in &self.bodies {
+            counts[root.find_quadrant(body.pos)] += 1;
+        }
+        let n = self.bodies.len().max(1) as f32;
+        Some(counts.map(|count| count as f32 / n))
+    }
+
+    /// Draws the bodies as one translucent square per occupied screen cell,
+    /// as bright as the cell is full, if the view is dense enough for that.
+    /// Returns `false` without drawing anything otherwise.
+    fn draw_aggregated(&self, ctx: &mut Canvas) -> bool {
+        let min = self.screen_to_world(0.0, self.height as f32);
+        let max = self.screen_to_world(self.width as f32, 0.0);
+        let inside = |p: Vec2| p.x >= min.x && p.x < max.x && p.y >= min.y && p.y < max.y;
+        let positions: Vec<Vec2> = self
+            .bodies
+            .iter()
+            .map(|body| self.to_view(body.pos))
+            .collect();
+        let visible = positions.iter().filter(|&&p| inside(p)).count();
+        let pixels = self.width as f32 * self.height as f32;
+        if (visible as f32) < self.decimate_density * pixels {
+            return false;
+        }
+
+        let cell = DECIMATE_CELL_PX as f32 * self.world_per_pixel();
+        let cols = self.width as usize / DECIMATE_CELL_PX + 1;
+        let rows = self.height as usize / DECIMATE_CELL_PX + 1;
+        let mut counts = vec![0u32; cols * rows];
+        let mut sums = vec![[0u32; 3]; cols * rows];
+        let colors = self.body_colors();
+        for (&p, color) in positions.iter().zip(&colors) {
+            if !inside(p) {
+                continue;
+            }
+            let x = (((p.x - min.x) / cell) as usize).min(cols - 1);
+            let y = (((p.y - min.y) / cell) as usize).min(rows - 1);
+            counts[y * cols + x] += 1;
+            for c in 0..3 {
+                sums[y * cols + x][c] += color[c] as u32;
+            }
+        }
+
+        // A cell holding the threshold density is drawn opaque
+        let full = self.decimate_density * (DECIMATE_CELL_PX * DECIMATE_CELL_PX) as f32;
+        for (i, (&count, sum)) in counts.iter().zip(&sums).enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let corner = min + Vec2::new((i % cols) as f32, (i / cols) as f32) * cell;
+            let [r, g, b] = sum.map(|c| (c / count) as u8);
+            let alpha = (count as f32 / full).min(1.0);
+            let color = [r, g, b, (alpha * 255.0) as u8];
+            ctx.draw_rect(corner, corner + Vec2::broadcast(cell), color);
+        }
+        true
+    }
+
+    /// Display radius of a body, never smaller than `min_render_radius_px`
+    /// screen pixels. Used for both drawing and picking, so whatever is
+    /// visible can be clicked at any zoom. Never affects the simulation.
+    fn render_radius(&self, body: &Body) -> f32 {
+        let radius = match self.radius_mode {
+            RadiusMode::Physical => body.radius,
+            RadiusMode::LogMass => body.mass.max(f32::MIN_POSITIVE).log10().max(1.0),
+            RadiusMode::Fixed => 1.0,
+        };
+        let min_radius = self.min_render_radius_px * self.world_per_pixel();
+        (radius * self.render_radius_scale).max(min_radius)
+    }
+}
+
+/// Rotates `v` by the angle whose cosine and sine are `rotation.x` and `rotation.y`.
+fn rotate(v: Vec2, rotation: Vec2) -> Vec2 {
+    Vec2::new(
+        v.x * rotation.x - v.y * rotation.y,
+        v.x * rotation.y + v.y * rotation.x,
+    )
+}
+
+/// Where body colors start on the gradient, whose dark end would vanish on
+/// a black background.
+const GRADIENT_START: f32 = 0.25;
+/// Size in points of the color bar and how many steps it's drawn in.
+const COLOR_BAR_SIZE: [f32; 2] = [200.0, 12.0];
+const COLOR_BAR_STEPS: usize = 32;
+
+/// Smallest and largest of `values`.
+fn value_range(values: &[f32]) -> (f32, f32) {
+    values
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)))
+}
+
+/// Color of `t` in `0..=1` along the gradient used by the overlays.
+fn gradient(t: f32) -> [u8; 4] {
+    let start_h = -100.0;
+    let end_h = 80.0;
+    let h = start_h + (end_h - start_h) * t;
+    let s = 100.0;
+    let l = t * 100.0;
+
+    let c = Hsluv::new(h, s, l);
+    let rgba: Rgba = c.into_color();
+    rgba.into_format().into()
+}
+
+/// Primitives submitted in one frame.
+#[derive(Clone, Copy, Default)]
+struct DrawCounts {
+    circles: usize,
+    lines: usize,
+    rects: usize,
+}
+
+/// Segments of a circle drawn as a ring in wireframe mode.
+const WIREFRAME_SEGMENTS: usize = 12;
+
+/// Forwards draws to the render context, counting what's submitted. In
+/// wireframe mode circles become rings and rects outlines, so overdraw
+/// shows up as dense lines instead of solid fill.
+struct Canvas<'a> {
+    ctx: &'a mut quarkstrom::RenderContext,
+    wireframe: bool,
+    counts: DrawCounts,
+}
+
+impl Canvas<'_> {
+    fn draw_circle(&mut self, pos: Vec2, radius: f32, color: [u8; 4]) {
+        if !self.wireframe {
+            self.counts.circles += 1;
+            self.ctx.draw_circle(pos, radius, color);
+            return;
+        }
+        let corner = |i: usize| {
+            let (sin, cos) = (i as f32 * TAU / WIREFRAME_SEGMENTS as f32).sin_cos();
+            pos + Vec2::new(cos, sin) * radius
+        };
+        for i in 0..WIREFRAME_SEGMENTS {
+            self.draw_line(corner(i), corner(i + 1), color);
+        }
+    }
+
+    fn draw_line(&mut self, a: Vec2, b: Vec2, color: [u8; 4]) {
+        self.counts.lines += 1;
+        self.ctx.draw_line(a, b, color);
+    }
+
+    fn draw_rect(&mut self, min: Vec2, max: Vec2, color: [u8; 4]) {
+        if !self.wireframe {
+            self.counts.rects += 1;
+            self.ctx.draw_rect(min, max, color);
+            return;
+        }
+        let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+        for (&a, &b) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+            self.draw_line(a, b, color);
+        }
+    }
 }
 
 impl quarkstrom::Renderer for Renderer {
     fn new() -> Self {
+        let settings = SETTINGS.lock().clone();
+        let quadtree = Quadtree::new(settings.theta, settings.epsilon);
         Self {
             pos: Vec2::zero(),
             scale: 3600.0,
+            width: 1,
+            height: 1,
+
+            bookmarks: [None; 9],
+
+            settings_window_open: false,
+            help_open: false,
+
+            max_fps: settings.max_fps,
+            last_frame: Instant::now(),
+            frame_times: VecDeque::new(),
+            frame_time_history: 300,
+            show_frame_times: false,
+            wireframe: false,
+            draw_counts: VecDeque::new(),
+
+            show_bodies: true,
+            color_mode: ColorMode::Uniform,
+            show_quadtree: false,
+            quadtree_view: QuadtreeView::Cells,
+            show_scale_bar: settings.show_scale_bar,
+            show_com: settings.show_com,
+            show_shape: false,
+            show_warnings: false,
+            show_bounds: settings.show_bounds,
+            zoom_steps: settings.zoom_steps,
+            invert_scroll: settings.invert_scroll,
+            scroll_spawn_mass: settings.scroll_spawn_mass,
+            show_velocities: false,
+            show_accelerations: false,
+
+            co_rotation: 0.0,
+            reference_frame: ReferenceFrame::World,
+            shown_frame: ReferenceFrame::World,
+            frame_origin: Vec2::zero(),
+            frame_center: Vec2::zero(),
+            frame_rotation: Vec2::unit_x(),
+            vel_scale: 1.0,
+            normalize_velocities: false,
+            acc_scale: 10.0,
+            normalize_accelerations: false,
+
+            background: settings.background,
+
+            recording: false,
+            record_dir: settings.record_dir.clone(),
+            json_path: "bodies.json".to_string(),
+            record_stride: settings.record_stride,
+            record_index: 0,
+            record_counter: 0,
+
+            radius_mode: RadiusMode::Physical,
+            render_radius_scale: settings.render_radius_scale,
+            glow: false,
+            glow_intensity: 1.0,
+            glow_cap: 100000,
+            sort_by_size: false,
+            draw_order: Vec::new(),
+            min_render_radius_px: settings.min_render_radius_px,
+            circle_segments: 0,
+            decimate: false,
+            decimate_density: 1.0,
 
-            settings_window_open: false,
+            depth_range: None,
+            leaf_depths: (0, 0),
+            leaf_depths_stale: true,
+            hide_empty_cells: false,
+            max_cells: 200000,
+            cells_truncated: false,
+            show_depth_grid: false,
+            grid_depth: 3,
 
-            show_bodies: true,
-            show_quadtree: false,
+            show_potential: false,
+            potential_resolution: 64,
 
-            depth_range: (0, 0),
+            trace: false,
+            trace_steps: 500,
+            trace_start: None,
+            trace_path: Vec::new(),
+            trace_stale: true,
+            force_errors: Vec::new(),
+            force_errors_stale: true,
+            probe: false,
+            cursor: Vec2::zero(),
 
             spawn_body: None,
+            spawn_charge: 0.0,
+            ring: None,
+            ring_count: 32,
+            ring_central_mass: 0.0,
             angle: None,
             total: None,
 
             confirmed_bodies: None,
 
+            selected: None,
+            kick: None,
+            measuring: false,
+            measure: [None; 2],
+
+            body_count: settings.body_count,
+            mass_factor: 2.0,
+            run_until: 0,
+            run_more: 100,
+            seed: settings.seed,
+            disc: settings.disc,
+            bar_axis_ratio: 0.5,
+            bar_central_mass: false,
+
+            settings,
+            save_settings: false,
+            clear_all: false,
+            tune_tolerance: 0.01,
+            tune_theta: false,
+            screenshot_pending: false,
+
             bodies: Vec::new(),
-            quadtree: Vec::new(),
+            quadtree,
         }
     }
 
     fn input(&mut self, input: &WinitInputHelper, width: u16, height: u16) {
-        self.settings_window_open ^= input.key_pressed(VirtualKeyCode::E);
+        self.width = width;
+        self.height = height;
 
-        if input.key_pressed(VirtualKeyCode::Space) {
-            let val = PAUSED.load(Ordering::Relaxed);
-            PAUSED.store(!val, Ordering::Relaxed)
+        for binding in KEY_BINDINGS {
+            if binding.keys.iter().any(|&key| input.key_pressed(key)) {
+                self.perform(binding.action);
+            }
         }
 
-        if let Some((mx, my)) = input.mouse() {
-            // Scroll steps to double/halve the scale
-            let steps = 5.0;
+        // Ctrl + number saves a camera bookmark, number alone recalls it
+        for (i, &key) in BOOKMARK_KEYS.iter().enumerate() {
+            if input.key_pressed(key) {
+                if input.held_control() {
+                    self.bookmarks[i] = Some((self.pos, self.scale));
+                    notify(format!("Saved bookmark {}", i + 1));
+                } else if let Some((pos, scale)) = self.bookmarks[i] {
+                    self.pos = pos;
+                    self.scale = scale;
+                }
+            }
+        }
 
+        let spawning = self.spawn_body.as_mut().filter(|_| self.scroll_spawn_mass);
+        if let Some(body) = spawning {
+            // Each step up doubles the mass instead of zooming
+            body.mass *= input.scroll_diff().exp2();
+            body.radius = body.mass.cbrt();
+        } else if let Some((mx, my)) = input.mouse() {
             // Modify input
-            let zoom = (-input.scroll_diff() / steps).exp2();
+            let scroll = if self.invert_scroll {
+                input.scroll_diff()
+            } else {
+                -input.scroll_diff()
+            };
+            let zoom = (scroll / self.zoom_steps.max(f32::EPSILON)).exp2();
 
             // Screen space -> view space
             let target =
@@ -102,6 +1683,8 @@ impl quarkstrom::Renderer for Renderer {
             self.pos.y += mdy / height as f32 * self.scale * 2.0;
         }
 
+        let (origin, center) = (self.frame_origin, self.frame_center);
+        let inverse = Vec2::new(self.frame_rotation.x, -self.frame_rotation.y);
         let world_mouse = || -> Vec2 {
             let (mx, my) = input.mouse().unwrap_or_default();
             let mut mouse = Vec2::new(mx, my);
@@ -109,18 +1692,62 @@ impl quarkstrom::Renderer for Renderer {
             mouse.y -= 1.0;
             mouse.y *= -1.0;
             mouse.x -= width as f32 / height as f32;
-            mouse * self.scale + self.pos
+            origin + center + rotate(mouse * self.scale + self.pos - center, inverse)
         };
 
-        if input.mouse_pressed(1) {
+        self.cursor = world_mouse();
+        if self.trace && self.trace_start != Some(self.cursor) {
+            self.trace_start = Some(self.cursor);
+            self.trace_stale = true;
+        }
+
+        // Kick the selected body, or select or pick the bodies to measure between
+        if input.mouse_pressed(0) && input.held_alt() && self.selected.is_some() {
+            let mouse = world_mouse();
+            self.kick = Some((mouse, mouse));
+        } else if let (true, Some((_, end))) = (input.mouse_held(0), &mut self.kick) {
+            *end = world_mouse();
+        } else if let Some((start, end)) = self.kick.filter(|_| input.mouse_released(0)) {
+            self.kick = None;
+            if let Some(id) = self.selected {
+                COMMANDS.lock().push(SimCommand::Kick(id, end - start));
+            }
+        } else if input.mouse_pressed(0) {
+            if let Some(id) = self.body_at(world_mouse()).map(|body| body.id) {
+                if !self.measuring {
+                    self.selected = Some(id);
+                } else if matches!(self.measure, [Some(first), None] if first != id) {
+                    self.measure[1] = Some(id);
+                } else {
+                    self.measure = [Some(id), None];
+                }
+            }
+        }
+
+        if input.mouse_pressed(1) && input.held_control() {
+            self.ring = Some((world_mouse(), 0.0));
+        } else if let (true, Some((center, radius))) = (input.mouse_held(1), &mut self.ring) {
+            *radius = (world_mouse() - *center).mag();
+        } else if let (true, Some(ring)) = (input.mouse_released(1), self.ring) {
+            self.ring = None;
+            self.spawn_ring(ring);
+        } else if input.mouse_pressed(1) && input.held_shift() {
+            let mouse = world_mouse();
+            let mut body = Body::new(mouse, Vec2::zero(), 1.0, 1.0);
+            body.charge = self.spawn_charge;
+            body.vel = self.orbit_velocity(mouse);
+            self.confirmed_bodies = Some(body);
+        } else if input.mouse_pressed(1) {
             let mouse = world_mouse();
-            self.spawn_body = Some(Body::new(mouse, Vec2::zero(), 1.0, 1.0));
+            let mut body = Body::new(mouse, Vec2::zero(), 1.0, 1.0);
+            body.charge = self.spawn_charge;
+            self.spawn_body = Some(body);
             self.angle = None;
             self.total = Some(0.0);
         } else if input.mouse_held(1) {
             if let Some(body) = &mut self.spawn_body {
                 let mouse = world_mouse();
-                if let Some(angle) = self.angle {
+                if let (false, Some(angle)) = (self.scroll_spawn_mass, self.angle) {
                     let d = mouse - body.pos;
                     let angle2 = d.y.atan2(d.x);
                     let a = angle2 - angle;
@@ -138,87 +1765,307 @@ impl quarkstrom::Renderer for Renderer {
                 body.vel = mouse - body.pos;
             }
         } else if input.mouse_released(1) {
-            self.confirmed_bodies = self.spawn_body.take();
+            if let Some(body) = self.spawn_body.take() {
+                self.confirmed_bodies = Some(body);
+            }
         }
     }
 
     fn render(&mut self, ctx: &mut quarkstrom::RenderContext) {
+        if self.max_fps > 0 {
+            let frame = Duration::from_secs_f64(1.0 / self.max_fps as f64);
+            if let Some(rest) = frame.checked_sub(self.last_frame.elapsed()) {
+                std::thread::sleep(rest);
+            }
+        }
+        self.frame_times
+            .push_back(self.last_frame.elapsed().as_secs_f32() * 1000.0);
+        while self.frame_times.len() > self.frame_time_history.max(1) {
+            self.frame_times.pop_front();
+        }
+        self.last_frame = Instant::now();
+
         {
             let mut lock = UPDATE_LOCK.lock();
             if *lock {
                 std::mem::swap(&mut self.bodies, &mut BODIES.lock());
-                std::mem::swap(&mut self.quadtree, &mut QUADTREE.lock());
+                std::mem::swap(&mut self.quadtree.nodes, &mut QUADTREE.lock());
+                // The nodes are evaluated like the simulation does
+                self.quadtree.t_sq = THETA.lock().powi(2);
+                self.quadtree.e_sq = EPSILON.lock().powi(2);
+                self.quadtree.opening_criterion = *OPENING_CRITERION.lock();
+                self.quadtree.multipole_order = *MULTIPOLE_ORDER.lock();
+                self.leaf_depths_stale = true;
+                self.trace_stale = true;
+                self.force_errors_stale = true;
             }
             if let Some(body) = self.confirmed_bodies.take() {
                 self.bodies.push(body);
-                SPAWN.lock().push(body);
+                COMMANDS.lock().push(SimCommand::Spawn(body));
             }
             *lock = false;
         }
 
+        self.update_reference_frame();
+
         ctx.clear_circles();
         ctx.clear_lines();
         ctx.clear_rects();
         ctx.set_view_pos(self.pos);
         ctx.set_view_scale(self.scale);
+        let ctx = &mut Canvas {
+            ctx,
+            wireframe: self.wireframe,
+            counts: DrawCounts::default(),
+        };
+
+        let foreground = self.foreground();
+        if self.background != [0, 0, 0, 0xff] {
+            let min = self.screen_to_world(0.0, self.height as f32);
+            let max = self.screen_to_world(self.width as f32, 0.0);
+            ctx.draw_rect(min, max, self.background);
+        }
+
+        if self.show_potential && !self.quadtree.nodes.is_empty() {
+            self.draw_potential(ctx);
+        }
+
+        if self.color_mode == ColorMode::ForceError && std::mem::take(&mut self.force_errors_stale)
+        {
+            self.force_errors = self.compute_force_errors();
+        }
 
         if !self.bodies.is_empty() {
-            if self.show_bodies {
-                for i in 0..self.bodies.len() {
-                    ctx.draw_circle(self.bodies[i].pos, self.bodies[i].radius, [0xff; 4]);
+            let mut order = std::mem::take(&mut self.draw_order);
+            order.clear();
+            order.extend(0..self.bodies.len());
+            if self.sort_by_size {
+                let radii: Vec<f32> = self.bodies.iter().map(|b| self.render_radius(b)).collect();
+                order.sort_unstable_by(|&a, &b| radii[b].total_cmp(&radii[a]));
+            }
+
+            if self.show_bodies && self.glow {
+                // Translucent discs brighten where they overlap, small ones
+                // are more opaque so every body contributes similar light
+                let stride = self.bodies.len().div_ceil(self.glow_cap.max(1)).max(1);
+                let colors = self.body_colors();
+                for &i in order.iter().step_by(stride) {
+                    let (body, [r, g, b, _]) = (&self.bodies[i], colors[i]);
+                    let radius = self.render_radius(body);
+                    let px = radius / self.world_per_pixel();
+                    let alpha = (self.glow_intensity / (px * px)).clamp(0.02, 1.0);
+                    let color = [r, g, b, (alpha * 255.0) as u8];
+                    ctx.draw_circle(self.to_view(body.pos), radius, color);
+                }
+            } else if self.show_bodies && !(self.decimate && self.draw_aggregated(ctx)) {
+                let colors = self.body_colors();
+                let mut budget = MAX_EDGE_SEGMENTS;
+                for &i in &order {
+                    let (body, color) = (&self.bodies[i], colors[i]);
+                    let radius = self.render_radius(body);
+                    let pos = self.to_view(body.pos);
+                    ctx.draw_circle(pos, radius, color);
+
+                    let segments = self.circle_segments;
+                    let large = radius >= EDGE_MIN_RADIUS_PX * self.world_per_pixel();
+                    if segments >= 3 && large && budget >= segments {
+                        budget -= segments;
+                        let corner = |i: usize| {
+                            let (sin, cos) = (i as f32 * TAU / segments as f32).sin_cos();
+                            pos + Vec2::new(cos, sin) * radius
+                        };
+                        for i in 0..segments {
+                            ctx.draw_line(corner(i), corner(i + 1), color);
+                        }
+                    }
                 }
             }
+            self.draw_order = order;
 
-            if let Some(body) = &self.confirmed_bodies {
-                ctx.draw_circle(body.pos, body.radius, [0xff; 4]);
-                ctx.draw_line(body.pos, body.pos + body.vel, [0xff; 4]);
+            for body in self.confirmed_bodies.iter().chain(&self.spawn_body) {
+                let pos = self.to_view(body.pos);
+                ctx.draw_circle(pos, self.render_radius(body), foreground);
+                ctx.draw_line(pos, pos + self.to_view_dir(body.vel), foreground);
             }
 
-            if let Some(body) = &self.spawn_body {
-                ctx.draw_circle(body.pos, body.radius, [0xff; 4]);
-                ctx.draw_line(body.pos, body.pos + body.vel, [0xff; 4]);
+            let kicked = self.selected.and_then(|id| self.find_body(id));
+            if let (Some((start, end)), Some(body)) = (self.kick, kicked) {
+                let pos = self.to_view(body.pos);
+                ctx.draw_line(
+                    pos,
+                    pos + self.to_view_dir(end - start),
+                    [0xff, 0x80, 0x40, 0xff],
+                );
             }
         }
 
-        if self.show_quadtree && !self.quadtree.is_empty() {
-            let mut depth_range = self.depth_range;
-            if depth_range.0 >= depth_range.1 {
-                let mut stack = Vec::new();
-                stack.push((Quadtree::ROOT, 0));
+        if self.trace && !self.quadtree.nodes.is_empty() {
+            if std::mem::take(&mut self.trace_stale) {
+                self.trace_path = self.trace_orbit();
+            }
+            let color = [0x40, 0xc0, 0xff, 0xff];
+            for pair in self.trace_path.windows(2) {
+                ctx.draw_line(self.to_view(pair[0]), self.to_view(pair[1]), color);
+            }
+        }
 
-                let mut min_depth = usize::MAX;
-                let mut max_depth = 0;
-                while let Some((node, depth)) = stack.pop() {
-                    let node = &self.quadtree[node];
+        if let Some(ring) = self.ring {
+            for body in self.ring_bodies(ring) {
+                let pos = self.to_view(body.pos);
+                ctx.draw_circle(pos, self.render_radius(body), foreground);
+                ctx.draw_line(pos, pos + self.to_view_dir(body.vel), foreground);
+            }
+        }
 
-                    if node.is_leaf() {
-                        if depth < min_depth {
-                            min_depth = depth;
-                        }
-                        if depth > max_depth {
-                            max_depth = depth;
-                        }
-                    } else {
-                        for i in 0..4 {
-                            stack.push((node.children + i, depth + 1));
-                        }
-                    }
+        if self.show_com {
+            if let Some(com) = self.center_of_mass().map(|com| self.to_view(com)) {
+                let size = 10.0 * self.world_per_pixel();
+                let color = [0xff, 0x40, 0x40, 0xff];
+                ctx.draw_line(
+                    com - Vec2::new(size, 0.0),
+                    com + Vec2::new(size, 0.0),
+                    color,
+                );
+                ctx.draw_line(
+                    com - Vec2::new(0.0, size),
+                    com + Vec2::new(0.0, size),
+                    color,
+                );
+            }
+        }
+
+        let shape = STATS.lock().shape;
+        if let (true, Some(shape), Some(com)) = (self.show_shape, shape, self.center_of_mass()) {
+            let (sin, cos) = shape.angle.sin_cos();
+            let (major, minor) = (Vec2::new(cos, sin), Vec2::new(-sin, cos));
+            let point = |t: f32| {
+                let (sin, cos) = t.sin_cos();
+                self.to_view(com + major * (shape.major * cos) + minor * (shape.minor * sin))
+            };
+            let color = [0xff, 0xc0, 0x40, 0xff];
+            const SEGMENTS: usize = 64;
+            for i in 0..SEGMENTS {
+                let t = TAU * i as f32 / SEGMENTS as f32;
+                ctx.draw_line(point(t), point(t + TAU / SEGMENTS as f32), color);
+            }
+            ctx.draw_line(point(0.0), point(PI), color);
+        }
+
+        for emitter in EMITTERS.lock().iter() {
+            let pos = self.to_view(emitter.pos);
+            let size = 8.0 * self.world_per_pixel();
+            let color = [0xff, 0x80, 0xff, 0xff];
+            ctx.draw_line(
+                pos - Vec2::new(size, size),
+                pos + Vec2::new(size, size),
+                color,
+            );
+            ctx.draw_line(
+                pos - Vec2::new(size, -size),
+                pos + Vec2::new(size, -size),
+                color,
+            );
+        }
+
+        if self.show_bounds {
+            if let Some(root) = self.quadtree.bounds() {
+                let half = Vec2::broadcast(0.5 * root.size);
+                let color = [0xff, 0xc0, 0x40, 0xff];
+                self.draw_outline(ctx, root.center - half, root.center + half, color);
+            }
+        }
+
+        let stride = self.bodies.len().div_ceil(MAX_VECTORS).max(1);
+        if self.show_velocities {
+            let color = [0x40, 0xa0, 0xff, 0xff];
+            for body in self.bodies.iter().step_by(stride) {
+                let pos = self.to_view(body.pos);
+                let vel = self.overlay_vector(body.vel, self.vel_scale, self.normalize_velocities);
+                ctx.draw_line(pos, pos + vel, color);
+            }
+        }
+        if self.show_accelerations {
+            let color = [0x40, 0xff, 0x80, 0xff];
+            for body in self.bodies.iter().step_by(stride) {
+                let pos = self.to_view(body.pos);
+                let acc =
+                    self.overlay_vector(body.acc, self.acc_scale, self.normalize_accelerations);
+                ctx.draw_line(pos, pos + acc, color);
+            }
+        }
+
+        let selected = self.selected.and_then(|id| self.find_body(id));
+        if let Some(body) = selected {
+            let size = self.render_radius(body) + 6.0 * self.world_per_pixel();
+            let color = [0x40, 0xc0, 0xff, 0xff];
+            let corners = [
+                Vec2::new(-size, -size),
+                Vec2::new(size, -size),
+                Vec2::new(size, size),
+                Vec2::new(-size, size),
+            ];
+            let pos = self.to_view(body.pos);
+            for (&a, &b) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+                ctx.draw_line(pos + a, pos + b, color);
+            }
+        }
+
+        if let Some((a, b)) = self.measured_pair() {
+            ctx.draw_line(
+                self.to_view(a.pos),
+                self.to_view(b.pos),
+                [0xff, 0xc0, 0x40, 0xff],
+            );
+        }
+
+        if self.show_scale_bar {
+            let (length, _) = self.scale_bar_length();
+            let start =
+                self.screen_to_world(SCALE_BAR_MARGIN, self.height as f32 - SCALE_BAR_MARGIN);
+            let end = start + Vec2::new(length, 0.0);
+            let tick = Vec2::new(0.0, 5.0 * self.world_per_pixel());
+            ctx.draw_line(start, end, foreground);
+            ctx.draw_line(start - tick, start + tick, foreground);
+            ctx.draw_line(end - tick, end + tick, foreground);
+        }
+
+        if self.show_quadtree && self.quadtree_view == QuadtreeView::LeafMasses {
+            let color = [0xff, 0xa0, 0x40, 0xff];
+            for node in &self.quadtree.nodes {
+                if node.is_leaf() && !node.is_empty() {
+                    let px = 1.0 + node.abs_mass.max(1.0).log2() * 0.5;
+                    ctx.draw_circle(self.to_view(node.pos), px * self.world_per_pixel(), color);
                 }
+            }
+        }
 
-                depth_range = (min_depth, max_depth);
+        if self.show_quadtree
+            && self.quadtree_view == QuadtreeView::Cells
+            && !self.quadtree.nodes.is_empty()
+        {
+            // Only walk the tree for its depths once per snapshot
+            if std::mem::take(&mut self.leaf_depths_stale) {
+                self.leaf_depths = self.leaf_depth_span();
+                self.clamp_depth_range();
             }
-            let (min_depth, max_depth) = depth_range;
+            let (min_depth, max_depth) = self.depth_range.unwrap_or(self.leaf_depths);
 
+            let mut budget = self.max_cells;
             let mut stack = Vec::new();
             stack.push((Quadtree::ROOT, 0));
             while let Some((node, depth)) = stack.pop() {
-                let node = &self.quadtree[node];
+                if budget == 0 {
+                    // Left on the stack to tell it was cut short
+                    stack.push((node, depth));
+                    break;
+                }
+                let node = &self.quadtree.nodes[node];
 
                 if node.is_branch() && depth < max_depth {
                     for i in 0..4 {
                         stack.push((node.children + i, depth + 1));
                     }
-                } else if depth >= min_depth {
+                } else if depth >= min_depth && !(self.hide_empty_cells && node.is_empty()) {
                     let quad = node.quad;
                     let half = Vec2::new(0.5, 0.5) * quad.size;
                     let min = quad.center - half;
@@ -227,37 +2074,1476 @@ impl quarkstrom::Renderer for Renderer {
                     let t = ((depth - min_depth + !node.is_empty() as usize) as f32)
                         / (max_depth - min_depth + 1) as f32;
 
-                    let start_h = -100.0;
-                    let end_h = 80.0;
-                    let h = start_h + (end_h - start_h) * t;
-                    let s = 100.0;
-                    let l = t * 100.0;
-
-                    let c = Hsluv::new(h, s, l);
-                    let rgba: Rgba = c.into_color();
-                    let color = rgba.into_format().into();
+                    self.draw_cell(ctx, min, max, gradient(t));
+                    budget -= 1;
+                }
+            }
+            self.cells_truncated = !stack.is_empty();
+        }
 
-                    ctx.draw_rect(min, max, color);
+        if self.show_depth_grid && !self.quadtree.nodes.is_empty() {
+            // Leaves shallower than the chosen depth stand in for it
+            let color = [0x80, 0x80, 0x80, 0x60];
+            let mut stack = vec![(Quadtree::ROOT, 0)];
+            while let Some((node, depth)) = stack.pop() {
+                let node = &self.quadtree.nodes[node];
+                if node.is_branch() && depth < self.grid_depth {
+                    for i in 0..4 {
+                        stack.push((node.children + i, depth + 1));
+                    }
+                } else {
+                    let half = Vec2::broadcast(0.5 * node.quad.size);
+                    self.draw_outline(ctx, node.quad.center - half, node.quad.center + half, color);
                 }
             }
         }
+
+        let now = Instant::now();
+        self.draw_counts.push_back((now, ctx.counts));
+        while self
+            .draw_counts
+            .front()
+            .is_some_and(|&(time, _)| now - time > Duration::from_secs(1))
+        {
+            self.draw_counts.pop_front();
+        }
+
+        if self.recording {
+            self.record_frame();
+        }
+        if std::mem::take(&mut self.screenshot_pending) {
+            self.save_screenshot();
+        }
     }
 
     fn gui(&mut self, ctx: &quarkstrom::egui::Context) {
+        if self.show_scale_bar {
+            let (length, decimals) = self.scale_bar_length();
+            egui::Area::new("scale_bar")
+                .anchor(
+                    egui::Align2::LEFT_BOTTOM,
+                    [SCALE_BAR_MARGIN, -SCALE_BAR_MARGIN - 8.0],
+                )
+                .show(ctx, |ui| match self.settings.units {
+                    Some(units) => ui.label(format!(
+                        "{:.3e} {}",
+                        units.length(length),
+                        units.length_unit.symbol()
+                    )),
+                    None => ui.label(format!("{:.*}", decimals, length)),
+                });
+        }
+
+        if let Some((a, b)) = self.measured_pair() {
+            let d = b.pos - a.pos;
+            let v = b.vel - a.vel;
+            let distance = d.mag();
+            let radial = if distance > 0.0 {
+                d.dot(v) / distance
+            } else {
+                0.0
+            };
+            egui::Window::new("Measure").show(ctx, |ui| {
+                ui.label(format!("Bodies: {} and {}", a.id, b.id));
+                ui.label(format!("Distance: {:.3}", distance));
+                if let Some(units) = self.settings.units {
+                    ui.label(format!(
+                        "  = {:.3e} {}",
+                        units.length(distance),
+                        units.length_unit.symbol()
+                    ));
+                }
+                ui.label(format!("Relative Speed: {:.3}", v.mag()));
+                ui.label(format!("Radial Rate: {:+.3} (positive receding)", radial));
+            });
+        } else if self.measure[1].is_some() {
+            // One of the bodies is gone
+            self.measure = [None; 2];
+        }
+
+        if let Some(id) = self.selected {
+            let body = self.find_body(id).copied();
+            let mut open = true;
+            egui::Window::new("Body").open(&mut open).show(ctx, |ui| {
+                let Some(body) = body else {
+                    ui.label("No longer exists");
+                    return;
+                };
+                ui.label(format!("Id: {}", body.id));
+                ui.label(format!("Position: ({:.2}, {:.2})", body.pos.x, body.pos.y));
+                ui.label(format!("Velocity: ({:.3}, {:.3})", body.vel.x, body.vel.y));
+                ui.label(format!("Mass: {:.3}", body.mass));
+                ui.label(format!("Radius: {:.3}", body.radius));
+                ui.label(format!("Population: {}", body.population));
+                let mut fixed = body.fixed;
+                if ui.checkbox(&mut fixed, "Pinned").changed() {
+                    COMMANDS.lock().push(SimCommand::SetFixed(id, fixed));
+                }
+            });
+            if !open {
+                self.selected = None;
+            }
+        }
+
+        if let Some(values) = self.gradient_values() {
+            let (lo, hi) = value_range(&values);
+            egui::Area::new("color_bar")
+                .anchor(
+                    egui::Align2::RIGHT_BOTTOM,
+                    [-SCALE_BAR_MARGIN, -SCALE_BAR_MARGIN],
+                )
+                .show(ctx, |ui| {
+                    ui.set_max_width(COLOR_BAR_SIZE[0]);
+                    ui.label(self.color_mode.name());
+                    let (rect, _) =
+                        ui.allocate_exact_size(COLOR_BAR_SIZE.into(), egui::Sense::hover());
+                    let step = rect.width() / COLOR_BAR_STEPS as f32;
+                    for i in 0..COLOR_BAR_STEPS {
+                        let t = (i as f32 + 0.5) / COLOR_BAR_STEPS as f32;
+                        let [r, g, b, a] = gradient(GRADIENT_START + (1.0 - GRADIENT_START) * t);
+                        let min = rect.min + egui::vec2(i as f32 * step, 0.0);
+                        ui.painter().rect_filled(
+                            egui::Rect::from_min_size(min, egui::vec2(step, rect.height())),
+                            0.0,
+                            egui::Color32::from_rgba_unmultiplied(r, g, b, a),
+                        );
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label(self.color_mode.label(lo));
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                            ui.label(self.color_mode.label(hi));
+                        });
+                    });
+                });
+        }
+
+        if let (Some(body), Some(pos)) = (self.spawn_body, ctx.pointer_hover_pos()) {
+            egui::Area::new("spawn_mass")
+                .fixed_pos(pos + egui::vec2(16.0, 16.0))
+                .show(ctx, |ui| {
+                    ui.label(format!("Mass: {:.3}", body.mass));
+                });
+        }
+
+        // Newest last, each fading out over `NOTICE_DURATION`
+        let status: Vec<(String, f32)> = STATUS
+            .lock()
+            .iter()
+            .filter_map(|(text, shown)| {
+                let age = shown.elapsed().as_secs_f32() / NOTICE_DURATION.as_secs_f32();
+                (age < 1.0).then(|| (text.clone(), 1.0 - age))
+            })
+            .collect();
+        if !status.is_empty() {
+            egui::Area::new("notice")
+                .anchor(egui::Align2::CENTER_TOP, [0.0, SCALE_BAR_MARGIN])
+                .show(ctx, |ui| {
+                    for (text, alpha) in status {
+                        let color = ui.visuals().text_color().linear_multiply(alpha);
+                        ui.label(egui::RichText::new(text).color(color));
+                    }
+                });
+        }
+
+        if self.probe && !self.quadtree.nodes.is_empty() {
+            // The walk works anywhere, far outside it just sees a point mass
+            let acc = self.quadtree.acc(self.cursor);
+            let potential = self.quadtree.potential(self.cursor);
+            let leaf = self.quadtree.leaf_at(self.cursor);
+            egui::Window::new("Probe").show(ctx, |ui| {
+                ui.label(format!(
+                    "Position: ({:.1}, {:.1})",
+                    self.cursor.x, self.cursor.y
+                ));
+                ui.label(format!("Potential: {:.4}", potential));
+                ui.label(format!(
+                    "Acceleration: {:.4} toward {:.0}°",
+                    acc.mag(),
+                    acc.y.atan2(acc.x).to_degrees()
+                ));
+                match leaf.map(|leaf| &self.quadtree.nodes[leaf]) {
+                    Some(node) if node.is_empty() => {
+                        ui.label(format!("In an empty leaf of size {:.2}", node.quad.size))
+                    }
+                    Some(node) => ui.label(format!(
+                        "In a leaf of size {:.2} with mass {:.3}",
+                        node.quad.size, node.mass
+                    )),
+                    None => ui.label("Outside the tree's bounds"),
+                };
+            });
+        }
+
+        if let Some(instability) = STATS.lock().instability {
+            egui::Window::new("Paused").show(ctx, |ui| {
+                ui.label(format!("Unstable, {}.", instability));
+                ui.label("Press Space to continue anyway.");
+            });
+        }
+
+        egui::Window::new("Frame Times")
+            .open(&mut self.show_frame_times)
+            .show(ctx, |ui| {
+                let (sum, max) = self
+                    .frame_times
+                    .iter()
+                    .fold((0.0, 0.0f32), |(sum, max), &t| (sum + t, max.max(t)));
+                let mean = sum / self.frame_times.len().max(1) as f32;
+                ui.label(format!("Mean: {:.1} ms, Max: {:.1} ms", mean, max));
+                let last = self
+                    .draw_counts
+                    .back()
+                    .map_or_else(Default::default, |c| c.1);
+                let peak = self
+                    .draw_counts
+                    .iter()
+                    .fold(DrawCounts::default(), |peak, c| DrawCounts {
+                        circles: peak.circles.max(c.1.circles),
+                        lines: peak.lines.max(c.1.lines),
+                        rects: peak.rects.max(c.1.rects),
+                    });
+                ui.label(format!(
+                    "Circles: {} (peak {}), Lines: {} (peak {}), Rects: {} (peak {})",
+                    last.circles, peak.circles, last.lines, peak.lines, last.rects, peak.rects
+                ))
+                .on_hover_text("Primitives submitted this frame, and the most in the last second");
+                ui.checkbox(&mut self.wireframe, "Wireframe")
+                    .on_hover_text("Draw circles as rings and rects as outlines");
+                let points: egui::plot::PlotPoints = self
+                    .frame_times
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &t)| [i as f64, t as f64])
+                    .collect();
+                egui::plot::Plot::new("frame_times")
+                    .height(120.0)
+                    .include_y(0.0)
+                    .allow_drag(false)
+                    .allow_zoom(false)
+                    .show(ui, |plot| plot.line(egui::plot::Line::new(points)));
+                ui.horizontal(|ui| {
+                    ui.label("Frames:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.frame_time_history)
+                            .speed(10.0)
+                            .clamp_range(1..=10000),
+                    );
+                });
+            });
+
+        egui::Window::new("Controls")
+            .open(&mut self.help_open)
+            .show(ctx, |ui| {
+                egui::Grid::new("bindings").striped(true).show(ui, |ui| {
+                    for binding in KEY_BINDINGS {
+                        ui.label(binding.label);
+                        ui.label(binding.description);
+                        ui.end_row();
+                    }
+                    for (input, description) in OTHER_BINDINGS {
+                        ui.label(*input);
+                        ui.label(*description);
+                        ui.end_row();
+                    }
+                });
+            });
+
+        let memory_usage = self.memory_usage();
+        let view_center = self.from_view(self.pos);
+        let root_size = self
+            .quadtree
+            .nodes
+            .first()
+            .map_or(1000.0, |root| root.quad.size);
+        let quadrant_shares =
+            if self.settings_window_open && self.color_mode == ColorMode::RootQuadrant {
+                self.quadrant_shares()
+            } else {
+                None
+            };
+        let (max_depth, occupied_leaves) = if self.settings_window_open {
+            (
+                self.quadtree.max_depth(),
+                self.quadtree.occupied_leaf_count(),
+            )
+        } else {
+            (0, 0)
+        };
         egui::Window::new("")
             .open(&mut self.settings_window_open)
             .show(ctx, |ui| {
                 ui.checkbox(&mut self.show_bodies, "Show Bodies");
+                if self.show_bodies {
+                    ui.horizontal(|ui| {
+                        ui.label("Color:");
+                        let mode = &mut self.color_mode;
+                        egui::ComboBox::from_id_source("color_mode")
+                            .selected_text(mode.name())
+                            .show_ui(ui, |ui| {
+                                for option in ColorMode::ALL {
+                                    ui.selectable_value(mode, option, option.name());
+                                }
+                            });
+                    });
+                    if self.color_mode == ColorMode::ForceError
+                        && self.bodies.len() > MAX_ERROR_BODIES
+                    {
+                        ui.label(format!("Only up to {} bodies", MAX_ERROR_BODIES));
+                    }
+                    if let Some([sw, se, nw, ne]) = quadrant_shares {
+                        ui.label(format!(
+                            "NW {:.0}%, NE {:.0}%, SW {:.0}%, SE {:.0}%",
+                            nw * 100.0,
+                            ne * 100.0,
+                            sw * 100.0,
+                            se * 100.0
+                        ));
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Radius:");
+                        let mode = &mut self.radius_mode;
+                        egui::ComboBox::from_id_source("radius_mode")
+                            .selected_text(mode.name())
+                            .show_ui(ui, |ui| {
+                                for option in RadiusMode::ALL {
+                                    ui.selectable_value(mode, option, option.name());
+                                }
+                            });
+                        ui.label("Scale:");
+                        ui.add(
+                            egui::DragValue::new(&mut self.render_radius_scale)
+                                .speed(0.01)
+                                .clamp_range(0.0..=f32::MAX),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.glow, "Glow");
+                        if self.glow {
+                            ui.label("Intensity:");
+                            ui.add(egui::Slider::new(&mut self.glow_intensity, 0.0..=10.0));
+                            ui.label("Max Drawn:");
+                            ui.add(
+                                egui::DragValue::new(&mut self.glow_cap)
+                                    .speed(100.0)
+                                    .clamp_range(1..=usize::MAX),
+                            );
+                        }
+                    });
+                }
+                ui.checkbox(&mut self.sort_by_size, "Draw Large Bodies Behind")
+                    .on_hover_text("Sorts the bodies by radius every frame");
+                ui.checkbox(&mut self.show_warnings, "Highlight Warnings")
+                    .on_hover_text(
+                        "Red: non-finite, orange: at the speed cap, \
+                     yellow: crossing more than its leaf per step",
+                    );
+                ui.horizontal(|ui| {
+                    ui.label("Min Radius (px):");
+                    ui.add(
+                        egui::DragValue::new(&mut self.min_render_radius_px)
+                            .speed(0.05)
+                            .clamp_range(0.0..=f32::MAX),
+                    )
+                    .on_hover_text("Floor for both the drawn and the clickable radius");
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.decimate, "Aggregate When Dense")
+                        .on_hover_text("Draw screen cells instead of bodies when zoomed far out");
+                    if self.decimate {
+                        ui.label("Bodies per Pixel:");
+                        ui.add(
+                            egui::DragValue::new(&mut self.decimate_density)
+                                .speed(0.01)
+                                .clamp_range(0.001..=f32::MAX),
+                        );
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Edge Segments:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.circle_segments)
+                            .speed(0.2)
+                            .clamp_range(0..=128),
+                    )
+                    .on_hover_text(
+                        "Traces the edge of large bodies with a polygon, 0 for plain circles",
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Background:");
+                    ui.color_edit_button_srgba_unmultiplied(&mut self.background);
+                    if ui.button("Dark").clicked() {
+                        self.background = [0, 0, 0, 0xff];
+                    }
+                    if ui.button("Light").clicked() {
+                        self.background = [0xff; 4];
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Zoom Steps:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.zoom_steps)
+                            .speed(0.1)
+                            .clamp_range(0.1..=100.0),
+                    )
+                    .on_hover_text("Scroll steps to double or halve the scale, fewer zoom faster");
+                    ui.checkbox(&mut self.invert_scroll, "Invert Scroll");
+                });
+                ui.checkbox(&mut self.scroll_spawn_mass, "Scroll Sets Spawn Mass")
+                    .on_hover_text("Otherwise wind around the body being spawned");
+                ui.checkbox(&mut self.show_scale_bar, "Show Scale Bar");
+                ui.checkbox(&mut self.show_com, "Show Center of Mass");
+                ui.checkbox(&mut self.show_bounds, "Show Bounding Box");
+                ui.horizontal(|ui| {
+                    ui.label("Reference Frame:");
+                    egui::ComboBox::from_id_source("reference_frame")
+                        .selected_text(self.reference_frame.name())
+                        .show_ui(ui, |ui| {
+                            for frame in [ReferenceFrame::World, ReferenceFrame::CenterOfMass] {
+                                ui.selectable_value(&mut self.reference_frame, frame, frame.name());
+                            }
+                            if let Some(id) = self.selected {
+                                ui.selectable_value(
+                                    &mut self.reference_frame,
+                                    ReferenceFrame::Body(id),
+                                    "Selected Body",
+                                );
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Co-rotation:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.co_rotation)
+                            .speed(0.0001)
+                            .max_decimals(4),
+                    )
+                    .on_hover_text("Angular velocity the view turns with, 0 for a fixed view");
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_velocities, "Show Velocities");
+                    if self.show_velocities {
+                        ui.label("Scale:");
+                        ui.add(
+                            egui::Slider::new(&mut self.vel_scale, 0.01..=100.0).logarithmic(true),
+                        );
+                        ui.checkbox(&mut self.normalize_velocities, "Direction Only");
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_accelerations, "Show Accelerations");
+                    if self.show_accelerations {
+                        ui.label("Scale:");
+                        ui.add(
+                            egui::Slider::new(&mut self.acc_scale, 0.01..=1000.0).logarithmic(true),
+                        );
+                        ui.checkbox(&mut self.normalize_accelerations, "Direction Only");
+                    }
+                });
                 ui.checkbox(&mut self.show_quadtree, "Show Quadtree");
+                QUADTREE_VISIBLE.store(
+                    self.show_quadtree
+                        || self.show_potential
+                        || self.show_depth_grid
+                        || self.trace
+                        || self.probe
+                        || self.show_bounds
+                        || matches!(
+                            self.color_mode,
+                            ColorMode::RootQuadrant | ColorMode::ForceError
+                        ),
+                    Ordering::Relaxed,
+                );
                 if self.show_quadtree {
-                    let range = &mut self.depth_range;
+                    ui.horizontal(|ui| {
+                        ui.label("View:");
+                        let view = &mut self.quadtree_view;
+                        egui::ComboBox::from_id_source("quadtree_view")
+                            .selected_text(view.name())
+                            .show_ui(ui, |ui| {
+                                for option in QuadtreeView::ALL {
+                                    ui.selectable_value(view, option, option.name());
+                                }
+                            });
+                    });
+                }
+                if self.show_quadtree && self.quadtree_view == QuadtreeView::Cells {
                     ui.horizontal(|ui| {
                         ui.label("Depth Range:");
-                        ui.add(egui::DragValue::new(&mut range.0).speed(0.05));
+                        let deepest = self.leaf_depths.1;
+                        let (mut min, mut max) = self.depth_range.unwrap_or(self.leaf_depths);
+                        let mut changed = ui
+                            .add(
+                                egui::DragValue::new(&mut min)
+                                    .speed(0.05)
+                                    .clamp_range(0..=max),
+                            )
+                            .changed();
                         ui.label("to");
-                        ui.add(egui::DragValue::new(&mut range.1).speed(0.05));
+                        changed |= ui
+                            .add(
+                                egui::DragValue::new(&mut max)
+                                    .speed(0.05)
+                                    .clamp_range(min..=deepest),
+                            )
+                            .changed();
+                        if changed {
+                            self.depth_range = Some((min, max));
+                        }
+                        if ui.button("All").clicked() {
+                            self.depth_range = None;
+                        }
+                    });
+                    ui.checkbox(&mut self.hide_empty_cells, "Hide empty cells");
+                    ui.horizontal(|ui| {
+                        ui.label("Max Cells:");
+                        ui.add(
+                            egui::DragValue::new(&mut self.max_cells)
+                                .speed(1000.0)
+                                .clamp_range(1..=usize::MAX),
+                        );
+                        if self.cells_truncated {
+                            ui.colored_label(egui::Color32::YELLOW, "Truncated");
+                        }
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_depth_grid, "Grid at Depth");
+                    if self.show_depth_grid {
+                        ui.add(egui::DragValue::new(&mut self.grid_depth).speed(0.05));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.trace, "Trace Orbit")
+                        .on_hover_text("Path of a massless particle at the cursor");
+                    if self.trace {
+                        let steps = ui.add(
+                            egui::DragValue::new(&mut self.trace_steps)
+                                .speed(10.0)
+                                .clamp_range(1..=100000),
+                        );
+                        self.trace_stale |= steps.changed();
+                    }
+                });
+                ui.checkbox(&mut self.probe, "Probe Cursor")
+                    .on_hover_text("Show the potential and field at the cursor");
+                ui.checkbox(&mut self.show_potential, "Show Potential");
+                if self.show_potential {
+                    ui.horizontal(|ui| {
+                        ui.label("Potential Resolution:");
+                        ui.add(
+                            egui::DragValue::new(&mut self.potential_resolution)
+                                .speed(0.5)
+                                .clamp_range(1..=512),
+                        );
+                    });
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Force:");
+                    let mut kind = FORCE_KIND.lock();
+                    egui::ComboBox::from_id_source("force_kind")
+                        .selected_text(match *kind {
+                            ForceKind::Gravity => "Gravity",
+                            ForceKind::Coulomb => "Coulomb",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut *kind, ForceKind::Gravity, "Gravity");
+                            ui.selectable_value(&mut *kind, ForceKind::Coulomb, "Coulomb");
+                        });
+                    ui.label("Spawn Charge:");
+                    ui.add(egui::DragValue::new(&mut self.spawn_charge).speed(0.05));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Multipole Order:");
+                    let mut order = MULTIPOLE_ORDER.lock();
+                    egui::ComboBox::from_id_source("multipole_order")
+                        .selected_text(match *order {
+                            MultipoleOrder::Monopole => "Monopole",
+                            MultipoleOrder::Quadrupole => "Quadrupole",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut *order, MultipoleOrder::Monopole, "Monopole");
+                            ui.selectable_value(
+                                &mut *order,
+                                MultipoleOrder::Quadrupole,
+                                "Quadrupole",
+                            );
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Opening Criterion:");
+                    let mut criterion = OPENING_CRITERION.lock();
+                    egui::ComboBox::from_id_source("opening_criterion")
+                        .selected_text(match *criterion {
+                            OpeningCriterion::Classic => "Classic",
+                            OpeningCriterion::ComCorrected => "COM Corrected",
+                            OpeningCriterion::NodeRadius => "Node Radius",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(
+                                &mut *criterion,
+                                OpeningCriterion::Classic,
+                                "Classic",
+                            );
+                            ui.selectable_value(
+                                &mut *criterion,
+                                OpeningCriterion::ComCorrected,
+                                "COM Corrected",
+                            );
+                            ui.selectable_value(
+                                &mut *criterion,
+                                OpeningCriterion::NodeRadius,
+                                "Node Radius",
+                            );
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Method:");
+                    let mut method = FORCE_METHOD.lock();
+                    egui::ComboBox::from_id_source("force_method")
+                        .selected_text(match *method {
+                            ForceMethod::BarnesHut => "Barnes-Hut",
+                            ForceMethod::Direct => "Direct",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut *method, ForceMethod::BarnesHut, "Barnes-Hut");
+                            ui.selectable_value(&mut *method, ForceMethod::Direct, "Direct")
+                                .on_hover_text("Exact O(n²) sum, only feasible for small counts");
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Decomposition:");
+                    let mut decomposition = DECOMPOSITION.lock();
+                    egui::ComboBox::from_id_source("decomposition")
+                        .selected_text(match *decomposition {
+                            Decomposition::Quadtree => "Quadtree",
+                            Decomposition::Grid => "Grid",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(
+                                &mut *decomposition,
+                                Decomposition::Quadtree,
+                                "Quadtree",
+                            );
+                            ui.selectable_value(&mut *decomposition, Decomposition::Grid, "Grid");
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Theta:");
+                    ui.add(
+                        egui::DragValue::new(&mut *THETA.lock())
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::MAX),
+                    );
+                    if ui
+                        .button("Auto-tune")
+                        .on_hover_text("Find the largest theta whose error stays within the target")
+                        .clicked()
+                    {
+                        self.tune_theta = true;
+                    }
+                    ui.label("Max Error:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.tune_tolerance)
+                            .speed(0.001)
+                            .clamp_range(0.0..=1.0),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Softening:");
+                    ui.add(
+                        egui::DragValue::new(&mut *EPSILON.lock())
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::MAX),
+                    );
+                    let mut by_radius = SOFTENING_BY_RADIUS.load(Ordering::Relaxed);
+                    if ui
+                        .checkbox(&mut by_radius, "By Radius")
+                        .on_hover_text("Soften each pair by the sum of their radii")
+                        .changed()
+                    {
+                        SOFTENING_BY_RADIUS.store(by_radius, Ordering::Relaxed);
+                        if by_radius {
+                            *MASS_SOFTENING.lock() = None;
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let mut mass_softening = MASS_SOFTENING.lock();
+                    let mut by_mass = mass_softening.is_some();
+                    if ui
+                        .checkbox(&mut by_mass, "By Mass")
+                        .on_hover_text(
+                            "Soften each pair by k / m^(1/3) of the heavier body, \
+                             so heavy bodies stay sharp",
+                        )
+                        .changed()
+                    {
+                        *mass_softening = by_mass.then_some(*EPSILON.lock());
+                        if by_mass {
+                            SOFTENING_BY_RADIUS.store(false, Ordering::Relaxed);
+                        }
+                    }
+                    if let Some(k) = &mut *mass_softening {
+                        ui.label("k:");
+                        ui.add(
+                            egui::DragValue::new(k)
+                                .speed(0.01)
+                                .clamp_range(0.0..=f32::MAX),
+                        );
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Min Interaction Distance:");
+                    ui.add(
+                        egui::DragValue::new(&mut *MIN_INTERACTION_DISTANCE.lock())
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::MAX),
+                    )
+                    .on_hover_text(
+                        "Closer pairs of bodies interact as if this far apart, \
+                         without softening anything beyond it",
+                    );
+                });
+                let mut precise = F64_ACCUMULATION.load(Ordering::Relaxed);
+                if ui
+                    .checkbox(&mut precise, "Accumulate in f64")
+                    .on_hover_text("Less round-off when summing many small pulls")
+                    .changed()
+                {
+                    F64_ACCUMULATION.store(precise, Ordering::Relaxed);
+                }
+                let mut anchors = DIRECT_ANCHORS.load(Ordering::Relaxed);
+                if ui
+                    .checkbox(&mut anchors, "Exact Sink Pull")
+                    .on_hover_text("Keep sinks out of the tree and sum their pull directly")
+                    .changed()
+                {
+                    DIRECT_ANCHORS.store(anchors, Ordering::Relaxed);
+                }
+                let mut conserve = CONSERVE_MOMENTUM.load(Ordering::Relaxed);
+                if ui
+                    .checkbox(&mut conserve, "Cancel Net Force")
+                    .on_hover_text(
+                        "Remove the net force the approximation leaves, conserving momentum",
+                    )
+                    .changed()
+                {
+                    CONSERVE_MOMENTUM.store(conserve, Ordering::Relaxed);
+                }
+                let mut gravity = GRAVITY_ENABLED.load(Ordering::Relaxed);
+                if ui.checkbox(&mut gravity, "Gravity").changed() {
+                    GRAVITY_ENABLED.store(gravity, Ordering::Relaxed);
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Refits Between Rebuilds:");
+                    let mut interval = REFIT_INTERVAL.load(Ordering::Relaxed);
+                    if ui
+                        .add(egui::DragValue::new(&mut interval).speed(0.05))
+                        .changed()
+                    {
+                        REFIT_INTERVAL.store(interval, Ordering::Relaxed);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Min Node Size:");
+                    ui.add(
+                        egui::DragValue::new(&mut *MIN_NODE_SIZE.lock())
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::MAX),
+                    )
+                    .on_hover_text("Smaller nodes aren't split, e.g. below the softening length");
+                });
+                ui.horizontal(|ui| {
+                    let mut domain = DOMAIN.lock();
+                    let mut fixed = domain.is_some();
+                    ui.checkbox(&mut fixed, "Fixed Domain")
+                        .on_hover_text("Root of every tree, whatever the extent of the bodies");
+                    match (fixed, &mut *domain) {
+                        (true, Some(quad)) => {
+                            ui.label("Center:");
+                            ui.add(egui::DragValue::new(&mut quad.center.x).speed(1.0));
+                            ui.add(egui::DragValue::new(&mut quad.center.y).speed(1.0));
+                            ui.label("Size:");
+                            ui.add(
+                                egui::DragValue::new(&mut quad.size)
+                                    .speed(1.0)
+                                    .clamp_range(f32::MIN_POSITIVE..=f32::MAX),
+                            );
+                            let mut policy = DOMAIN_POLICY.lock();
+                            egui::ComboBox::from_id_source("domain_policy")
+                                .selected_text(format!("{:?}", *policy))
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut *policy, DomainPolicy::Clamp, "Clamp")
+                                        .on_hover_text("Outside bodies count on the nearest edge");
+                                    ui.selectable_value(&mut *policy, DomainPolicy::Cull, "Cull")
+                                        .on_hover_text("Outside bodies are removed");
+                                });
+                        }
+                        (true, None) => {
+                            *domain = Some(Quad {
+                                center: view_center,
+                                size: root_size,
+                            })
+                        }
+                        (false, _) => *domain = None,
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Sink Capture Radius:");
+                    let mut radius = CAPTURE_RADIUS.lock();
+                    ui.add(
+                        egui::DragValue::new(&mut *radius)
+                            .speed(0.1)
+                            .clamp_range(0.0..=f32::MAX),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    let mut restitution = RESTITUTION.lock();
+                    let mut colliding = restitution.is_some();
+                    ui.checkbox(&mut colliding, "Collisions")
+                        .on_hover_text("Restitution of bouncing bodies, 1 is elastic");
+                    match (colliding, &mut *restitution) {
+                        (true, Some(e)) => {
+                            ui.add(egui::Slider::new(e, 0.0..=1.0));
+                        }
+                        (true, None) => *restitution = Some(0.5),
+                        (false, _) => *restitution = None,
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let mut threshold = MERGE_THRESHOLD.lock();
+                    let mut enabled = threshold.is_some();
+                    ui.checkbox(&mut enabled, "Merging").on_hover_text(
+                        "Overlapping pairs merge once either body reaches the mass or radius, \
+                         smaller ones bounce or pass through",
+                    );
+                    match (enabled, &mut *threshold) {
+                        (true, Some(threshold)) => {
+                            ui.label("Mass:");
+                            ui.add(
+                                egui::DragValue::new(&mut threshold.mass)
+                                    .speed(1.0)
+                                    .clamp_range(0.0..=f32::MAX),
+                            );
+                            ui.label("Radius:");
+                            ui.add(
+                                egui::DragValue::new(&mut threshold.radius)
+                                    .speed(0.1)
+                                    .clamp_range(0.0..=f32::MAX),
+                            );
+                        }
+                        (true, None) => {
+                            *threshold = Some(MergeThreshold {
+                                mass: 1000.0,
+                                radius: 10.0,
+                            })
+                        }
+                        (false, _) => *threshold = None,
+                    }
+                });
+                let stats = STATS.lock().clone();
+                ui.label(format!("Accreted Mass: {:.1}", stats.accreted_mass));
+                if let Some(units) = self.settings.units {
+                    ui.label(format!(
+                        "  = {:.3e} solar masses",
+                        units.mass(stats.accreted_mass)
+                    ));
+                }
+                ui.label(format!(
+                    "Bodies: {}, Nodes: {}, Memory: {:.1} MiB",
+                    self.bodies.len(),
+                    self.quadtree.node_count(),
+                    memory_usage as f32 / (1024.0 * 1024.0)
+                ));
+                if occupied_leaves > 0 {
+                    ui.label(format!(
+                        "Max Depth: {}, Bodies per Occupied Leaf: {:.2}",
+                        max_depth,
+                        self.bodies.len() as f32 / occupied_leaves as f32
+                    ))
+                    .on_hover_text(
+                        "From the last snapshot, a spiking depth means bodies nearly coincide",
+                    );
+                }
+
+                let mut nearest = NEAREST_NEIGHBORS.load(Ordering::Relaxed);
+                if ui
+                    .checkbox(&mut nearest, "Nearest Neighbor Stats")
+                    .changed()
+                {
+                    NEAREST_NEIGHBORS.store(nearest, Ordering::Relaxed);
+                }
+                if let (true, Some(summary)) = (nearest, stats.nearest_neighbors) {
+                    ui.label(format!(
+                        "Nearest Neighbor: min {:.3}, mean {:.3}, median {:.3}",
+                        summary.min, summary.mean, summary.median
+                    ));
+                }
+                ui.horizontal(|ui| {
+                    let mut virial = VIRIAL_RATIO.load(Ordering::Relaxed);
+                    if ui.checkbox(&mut virial, "Virial Ratio").changed() {
+                        VIRIAL_RATIO.store(virial, Ordering::Relaxed);
+                    }
+                    if let (true, Some(ratio)) = (virial, stats.virial_ratio) {
+                        ui.label(format!("2T/|U| = {:.3}", ratio));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let mut shape = SHAPE.load(Ordering::Relaxed);
+                    if ui
+                        .checkbox(&mut shape, "Shape")
+                        .on_hover_text("Principal axes of the moment of inertia")
+                        .changed()
+                    {
+                        SHAPE.store(shape, Ordering::Relaxed);
+                    }
+                    if let (true, Some(shape)) = (shape, stats.shape) {
+                        ui.label(format!(
+                            "axis ratio {:.3}, angle {:.1}°",
+                            shape.axis_ratio(),
+                            shape.angle.to_degrees()
+                        ));
+                        ui.checkbox(&mut self.show_shape, "Draw");
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let mut unbound = UNBOUND_COUNT.load(Ordering::Relaxed);
+                    if ui
+                        .checkbox(&mut unbound, "Unbound Bodies")
+                        .on_hover_text("Bodies with positive energy, which are escaping")
+                        .changed()
+                    {
+                        UNBOUND_COUNT.store(unbound, Ordering::Relaxed);
+                    }
+                    if let (true, Some(count)) = (unbound, stats.unbound) {
+                        ui.label(format!("{}", count));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let mut mixing = POPULATION_MIXING.load(Ordering::Relaxed);
+                    if ui
+                        .checkbox(&mut mixing, "Population Mixing")
+                        .on_hover_text(
+                            "0 when every grid cell holds one population, 1 when evenly mixed",
+                        )
+                        .changed()
+                    {
+                        POPULATION_MIXING.store(mixing, Ordering::Relaxed);
+                    }
+                    if let (true, Some(mixing)) = (mixing, stats.population_mixing) {
+                        ui.label(format!("{:.3}", mixing));
+                    }
+                });
+                ui.label(format!("Angular Momentum: {:.4e}", stats.angular_momentum))
+                    .on_hover_text("About the center of mass, conserved by gravity");
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Steps per Frame:");
+                    let mut steps = STEPS_PER_FRAME.load(Ordering::Relaxed);
+                    if ui.add(egui::Slider::new(&mut steps, 0..=32)).changed() {
+                        STEPS_PER_FRAME.store(steps, Ordering::Relaxed);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Slow Motion Delay (ms):");
+                    let mut delay = STEP_DELAY_MS.load(Ordering::Relaxed);
+                    if ui.add(egui::Slider::new(&mut delay, 0..=500)).changed() {
+                        STEP_DELAY_MS.store(delay, Ordering::Relaxed);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Max Steps/s:");
+                    let mut sps = MAX_STEPS_PER_SECOND.load(Ordering::Relaxed);
+                    if ui
+                        .add(egui::DragValue::new(&mut sps).speed(1.0))
+                        .on_hover_text("0 for uncapped")
+                        .changed()
+                    {
+                        MAX_STEPS_PER_SECOND.store(sps, Ordering::Relaxed);
+                    }
+                    ui.label("Max FPS:");
+                    ui.add(egui::DragValue::new(&mut self.max_fps).speed(1.0))
+                        .on_hover_text("0 for uncapped");
+                    ui.checkbox(&mut self.show_frame_times, "Graph");
+                });
+                ui.horizontal(|ui| {
+                    ui.label(format!("Time: {:.2} (dt = {:.4})", stats.time, stats.dt));
+                    let mut reversed = REVERSED.load(Ordering::Relaxed);
+                    if ui
+                        .checkbox(&mut reversed, "Backward")
+                        .on_hover_text("Integrates with -dt, merging and accretion aren't undone")
+                        .changed()
+                    {
+                        REVERSED.store(reversed, Ordering::Relaxed);
+                    }
+                });
+                if let Some(units) = self.settings.units {
+                    ui.label(format!(
+                        "  = {:.3e} years (dt = {:.3e})",
+                        units.time(stats.time),
+                        units.time(stats.dt)
+                    ));
+                }
+                ui.horizontal(|ui| {
+                    let mut target = RUN_UNTIL.lock();
+                    ui.label(format!("Step: {}", stats.frame));
+                    if ui.button("Run to").clicked() {
+                        *target = Some(self.run_until);
+                        resume();
+                    }
+                    ui.add(egui::DragValue::new(&mut self.run_until).speed(10.0));
+                    if ui.button("Run").clicked() {
+                        *target = Some(stats.frame + self.run_more);
+                        resume();
+                    }
+                    ui.add(egui::DragValue::new(&mut self.run_more).speed(1.0));
+                    ui.label("more");
+                    if let Some(frame) = *target {
+                        ui.label(format!("(pausing at {})", frame));
+                        if ui.button("Clear").clicked() {
+                            *target = None;
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Integrator:");
+                    let mut integrator = INTEGRATOR.lock();
+                    egui::ComboBox::from_id_source("integrator")
+                        .selected_text(match *integrator {
+                            Integrator::Verlet => "Verlet",
+                            Integrator::Rk4 => "RK4",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut *integrator, Integrator::Verlet, "Verlet");
+                            ui.selectable_value(&mut *integrator, Integrator::Rk4, "RK4");
+                        });
+                });
+                ui.horizontal(|ui| {
+                    let mut max_speed = MAX_SPEED.lock();
+                    let mut limited = max_speed.is_some();
+                    ui.checkbox(&mut limited, "Limit Speed")
+                        .on_hover_text("Clamps every body's speed, a non-physical guard");
+                    match (limited, &mut *max_speed) {
+                        (true, Some(speed)) => {
+                            ui.add(
+                                egui::DragValue::new(speed)
+                                    .speed(0.1)
+                                    .clamp_range(0.0..=f32::MAX),
+                            );
+                        }
+                        (true, None) => *max_speed = Some(100.0),
+                        (false, _) => *max_speed = None,
+                    }
+                });
+                let mut pin_com = PIN_COM.load(Ordering::Relaxed);
+                if ui
+                    .checkbox(&mut pin_com, "Pin Center of Mass")
+                    .on_hover_text("Undo the round-off drift of the center of mass every step")
+                    .changed()
+                {
+                    PIN_COM.store(pin_com, Ordering::Relaxed);
+                }
+                ui.horizontal(|ui| {
+                    let mut frame = ROTATING_FRAME.lock();
+                    let mut rotating = frame.is_some();
+                    ui.checkbox(&mut rotating, "Rotating Frame")
+                        .on_hover_text("Simulate in a frame rotating about the origin");
+                    match (rotating, &mut *frame) {
+                        (true, Some(omega)) => {
+                            ui.label("Ω:");
+                            ui.add(egui::DragValue::new(omega).speed(0.0001).max_decimals(4));
+                        }
+                        (true, None) => *frame = Some(0.001),
+                        (false, _) => *frame = None,
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let mut wall = SOFT_WALL.lock();
+                    let mut enabled = wall.is_some();
+                    ui.checkbox(&mut enabled, "Soft Wall")
+                        .on_hover_text("Push bodies outside a square back like a spring");
+                    match (enabled, &mut *wall) {
+                        (true, Some(wall)) => {
+                            ui.label("Half Size:");
+                            ui.add(
+                                egui::DragValue::new(&mut wall.half_size)
+                                    .speed(10.0)
+                                    .clamp_range(0.0..=f32::MAX),
+                            );
+                            ui.label("Stiffness:");
+                            ui.add(
+                                egui::DragValue::new(&mut wall.stiffness)
+                                    .speed(0.001)
+                                    .clamp_range(0.0..=f32::MAX),
+                            );
+                        }
+                        (true, None) => {
+                            *wall = Some(SoftWall {
+                                half_size: 2000.0,
+                                stiffness: 0.01,
+                            })
+                        }
+                        (false, _) => *wall = None,
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let mut guard = PAUSE_ON_INSTABILITY.lock();
+                    let mut enabled = guard.is_some();
+                    ui.checkbox(&mut enabled, "Pause on Instability")
+                        .on_hover_text("Pause when a body stops being finite or the energy jumps");
+                    match (enabled, &mut *guard) {
+                        (true, Some(max_change)) => {
+                            ui.label("Max Energy Change:");
+                            ui.add(
+                                egui::DragValue::new(max_change)
+                                    .speed(0.01)
+                                    .clamp_range(0.0..=f32::MAX),
+                            );
+                        }
+                        (true, None) => *guard = Some(0.1),
+                        (false, _) => *guard = None,
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Rewind Snapshots:");
+                    let mut capacity = REWIND_CAPACITY.load(Ordering::Relaxed);
+                    if ui
+                        .add(egui::DragValue::new(&mut capacity).clamp_range(0..=100))
+                        .changed()
+                    {
+                        REWIND_CAPACITY.store(capacity, Ordering::Relaxed);
+                    }
+                    ui.label("Every:");
+                    let mut interval = REWIND_INTERVAL.load(Ordering::Relaxed);
+                    if ui
+                        .add(egui::DragValue::new(&mut interval).clamp_range(1..=usize::MAX))
+                        .changed()
+                    {
+                        REWIND_INTERVAL.store(interval, Ordering::Relaxed);
+                    }
+                    ui.label(format!("frames ({} stored)", stats.snapshots));
+                });
+                if !stats.rewind_fits {
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        "Too many bodies to keep that many snapshots, rewind is off",
+                    );
+                }
+                let mut timestep = ADAPTIVE_TIMESTEP.lock();
+                ui.add_enabled_ui(!timestep.enabled, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Timestep:");
+                        ui.add(
+                            egui::DragValue::new(&mut *DT.lock())
+                                .speed(0.001)
+                                .clamp_range(0.0..=f32::MAX),
+                        );
+                    });
+                });
+                ui.checkbox(&mut timestep.enabled, "Adaptive Timestep");
+                if timestep.enabled {
+                    ui.horizontal(|ui| {
+                        ui.label("Eta:");
+                        ui.add(
+                            egui::DragValue::new(&mut timestep.eta)
+                                .speed(0.01)
+                                .clamp_range(0.0..=f32::MAX),
+                        );
+                        ui.label("Min dt:");
+                        ui.add(
+                            egui::DragValue::new(&mut timestep.dt_min)
+                                .speed(0.0001)
+                                .clamp_range(0.0..=f32::MAX),
+                        );
+                        ui.label("Max dt:");
+                        ui.add(
+                            egui::DragValue::new(&mut timestep.dt_max)
+                                .speed(0.001)
+                                .clamp_range(0.0..=f32::MAX),
+                        );
+                    });
+                }
+                drop(timestep);
+                let mut blocks = BLOCK_TIMESTEPS.lock();
+                ui.checkbox(&mut blocks.enabled, "Block Timesteps")
+                    .on_hover_text("Only bodies in close encounters take the short substeps");
+                if blocks.enabled {
+                    ui.horizontal(|ui| {
+                        ui.label("Bins:");
+                        ui.add(
+                            egui::DragValue::new(&mut blocks.bins)
+                                .clamp_range(1..=BlockTimesteps::MAX_BINS),
+                        );
+                        ui.label("Eta:");
+                        ui.add(
+                            egui::DragValue::new(&mut blocks.eta)
+                                .speed(0.01)
+                                .clamp_range(0.0..=f32::MAX),
+                        );
+                    });
+                    if *INTEGRATOR.lock() != Integrator::Verlet {
+                        ui.colored_label(
+                            egui::Color32::YELLOW,
+                            "Block timesteps only apply to the Verlet integrator",
+                        );
+                    }
+                }
+                drop(blocks);
+                ui.horizontal(|ui| {
+                    ui.label("Near Field Substeps:");
+                    let mut substeps = NEAR_FIELD_SUBSTEPS.load(Ordering::Relaxed);
+                    if ui
+                        .add(egui::DragValue::new(&mut substeps).clamp_range(1..=64))
+                        .on_hover_text("Recompute only the directly summed pulls on each substep")
+                        .changed()
+                    {
+                        NEAR_FIELD_SUBSTEPS.store(substeps, Ordering::Relaxed);
+                    }
+                });
+
+                ui.separator();
+                let was_recording = self.recording;
+                ui.checkbox(&mut self.recording, "Record Frames");
+                if self.recording && !was_recording {
+                    if let Err(err) = std::fs::create_dir_all(&self.record_dir) {
+                        eprintln!("Failed to create {}: {}", self.record_dir, err);
+                        self.recording = false;
+                    }
+                    self.record_index = 0;
+                    self.record_counter = 0;
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Directory:");
+                    ui.text_edit_singleline(&mut self.record_dir);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Stride:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.record_stride)
+                            .speed(0.05)
+                            .clamp_range(1..=usize::MAX),
+                    );
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Bodies:");
+                    ui.add(egui::DragValue::new(&mut self.body_count).speed(100.0));
+                    ui.label("Seed:");
+                    ui.add(egui::DragValue::new(&mut self.seed));
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.disc.pin_center, "Pin Central Mass");
+                    egui::ComboBox::from_id_source("disc_rotation")
+                        .selected_text(format!("{:?}", self.disc.rotation))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(
+                                &mut self.disc.rotation,
+                                Rotation::Clockwise,
+                                "Clockwise",
+                            );
+                            ui.selectable_value(
+                                &mut self.disc.rotation,
+                                Rotation::CounterClockwise,
+                                "CounterClockwise",
+                            );
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Velocity Dispersion:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.disc.dispersion)
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::MAX),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Position Jitter:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.disc.jitter)
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::MAX),
+                    )
+                    .on_hover_text("Largest random offset, breaks exact symmetries");
+                });
+                ui.horizontal(|ui| {
+                    let warm_start = &mut self.disc.warm_start;
+                    ui.label("Center Offset:");
+                    ui.add(egui::DragValue::new(&mut warm_start.offset.x).speed(0.1));
+                    ui.add(egui::DragValue::new(&mut warm_start.offset.y).speed(0.1));
+                    ui.label("Velocity:");
+                    ui.add(egui::DragValue::new(&mut warm_start.velocity.x).speed(0.1));
+                    ui.add(egui::DragValue::new(&mut warm_start.velocity.y).speed(0.1));
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Regenerate").clicked() {
+                        let bodies = utils::uniform_disc(self.body_count, self.seed, &self.disc);
+                        COMMANDS.lock().push(SimCommand::Reset(bodies));
+                    }
+                    if ui
+                        .button("Resample")
+                        .on_hover_text("Merge or split the current bodies to reach the count")
+                        .clicked()
+                    {
+                        COMMANDS.lock().push(SimCommand::Resample(self.body_count));
+                    }
+                    if ui.button("Clear").clicked() {
+                        self.clear_all = true;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Elliptical")
+                        .on_hover_text("Generate a disc squashed along y, to study bars")
+                        .clicked()
+                    {
+                        let central_mass = self.bar_central_mass.then_some(1e6);
+                        let bodies = utils::elliptical_disc(
+                            self.body_count,
+                            self.bar_axis_ratio,
+                            self.seed,
+                            central_mass,
+                            &self.disc.warm_start,
+                        );
+                        COMMANDS.lock().push(SimCommand::Reset(bodies));
+                    }
+                    ui.label("Axis Ratio:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.bar_axis_ratio)
+                            .speed(0.01)
+                            .clamp_range(0.01..=1.0),
+                    );
+                    ui.checkbox(&mut self.bar_central_mass, "Central Mass");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("JSON:");
+                    ui.text_edit_singleline(&mut self.json_path);
+                    if ui.button("Export").clicked() {
+                        match utils::save_json(&self.json_path, &self.bodies) {
+                            Ok(()) => notify(format!("Saved {}", self.json_path)),
+                            Err(err) => eprintln!("Failed to save {}: {}", self.json_path, err),
+                        }
+                    }
+                    if ui.button("Import").clicked() {
+                        match utils::load_json(&self.json_path) {
+                            Ok(bodies) => COMMANDS.lock().push(SimCommand::Reset(bodies)),
+                            Err(err) => eprintln!("Failed to load {}: {}", self.json_path, err),
+                        }
+                    }
+                });
+
+                ui.separator();
+                let mut emitters = EMITTERS.lock();
+                ui.horizontal(|ui| {
+                    ui.label(format!("Emitters: {}", emitters.len()));
+                    if ui.button("Add at View Center").clicked() {
+                        emitters.push(Emitter {
+                            pos: view_center,
+                            ..Emitter::default()
+                        });
+                    }
+                    ui.label("Max Bodies:");
+                    let mut max_bodies = MAX_BODIES.load(Ordering::Relaxed);
+                    if ui
+                        .add(egui::DragValue::new(&mut max_bodies).speed(100.0))
+                        .on_hover_text("Emitters pause once there are this many bodies")
+                        .changed()
+                    {
+                        MAX_BODIES.store(max_bodies, Ordering::Relaxed);
+                    }
+                });
+                let mut removed = None;
+                for (i, emitter) in emitters.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label("Position:");
+                        ui.add(egui::DragValue::new(&mut emitter.pos.x));
+                        ui.add(egui::DragValue::new(&mut emitter.pos.y));
+                        ui.label("Rate:");
+                        ui.add(
+                            egui::DragValue::new(&mut emitter.rate)
+                                .speed(0.1)
+                                .clamp_range(0.0..=f32::MAX),
+                        );
+                        ui.label("Mass:");
+                        ui.add(
+                            egui::DragValue::new(&mut emitter.mass)
+                                .speed(0.01)
+                                .clamp_range(0.0..=f32::MAX),
+                        );
+                        if ui.button("Remove").clicked() {
+                            removed = Some(i);
+                        }
                     });
+                    ui.horizontal(|ui| {
+                        ui.label("    Velocity:");
+                        ui.add(egui::DragValue::new(&mut emitter.velocity.x).speed(0.1));
+                        ui.add(egui::DragValue::new(&mut emitter.velocity.y).speed(0.1));
+                        ui.label("Spread:");
+                        ui.add(
+                            egui::DragValue::new(&mut emitter.spread)
+                                .speed(0.1)
+                                .clamp_range(0.0..=f32::MAX),
+                        );
+                    });
+                }
+                if let Some(i) = removed {
+                    emitters.remove(i);
+                }
+                drop(emitters);
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Scale Masses")
+                        .on_hover_text("Multiply every mass by the factor, radii follow")
+                        .clicked()
+                    {
+                        COMMANDS
+                            .lock()
+                            .push(SimCommand::ScaleMasses(self.mass_factor));
+                    }
+                    ui.label("by");
+                    ui.add(
+                        egui::DragValue::new(&mut self.mass_factor)
+                            .speed(0.01)
+                            .clamp_range(0.001..=1000.0),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Ring Bodies:");
+                    ui.add(egui::DragValue::new(&mut self.ring_count).clamp_range(1..=10000))
+                        .on_hover_text("Spawned by dragging with Ctrl + right mouse button");
+                    ui.label("Central Mass:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.ring_central_mass)
+                            .speed(1.0)
+                            .clamp_range(0.0..=f32::MAX),
+                    )
+                    .on_hover_text("Mass the ring orbits, 0 for the mass already inside it");
+                });
+
+                ui.separator();
+                if ui.button("Save Settings").clicked() {
+                    self.save_settings = true;
                 }
             });
+
+        if std::mem::take(&mut self.save_settings) {
+            self.save_settings();
+        }
+        if std::mem::take(&mut self.clear_all) {
+            self.perform(Action::ClearAll);
+        }
+        if std::mem::take(&mut self.tune_theta) {
+            match self.tune_theta() {
+                Some((theta, error)) => {
+                    *THETA.lock() = theta;
+                    notify(format!("Theta {:.3}, max error {:.2e}", theta, error));
+                }
+                None => notify("No tree to tune theta on".to_string()),
+            }
+        }
     }
 }
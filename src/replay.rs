@@ -0,0 +1,116 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::{SimCommand, Simulation};
+
+/// Commands along with the frame they were applied at. Replaying them on a
+/// simulation created from the same settings reproduces the same run, as
+/// long as no other setting is changed along the way.
+#[derive(Default, Serialize, Deserialize)]
+pub struct CommandLog {
+    pub entries: Vec<(usize, SimCommand)>,
+    #[serde(skip)]
+    next: usize,
+}
+
+impl CommandLog {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        bincode::deserialize_from(reader)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(writer, self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Applies `command` and records it at the current frame.
+    pub fn apply(&mut self, simulation: &mut Simulation, command: SimCommand) {
+        self.entries.push((simulation.frame, command.clone()));
+        simulation.apply(command);
+    }
+
+    /// Applies every recorded command that is due by the current frame and
+    /// hasn't been replayed yet.
+    pub fn replay(&mut self, simulation: &mut Simulation) {
+        while let Some((frame, command)) = self.entries.get(self.next) {
+            if *frame > simulation.frame {
+                break;
+            }
+            simulation.apply(command.clone());
+            self.next += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{body::Body, settings::Settings};
+    use ultraviolet::Vec2;
+
+    fn bits(simulation: &Simulation) -> Vec<(u64, [u32; 5])> {
+        simulation
+            .bodies()
+            .iter()
+            .map(|body| {
+                let (pos, vel) = (body.pos, body.vel);
+                (
+                    body.id,
+                    [pos.x, pos.y, vel.x, vel.y, body.mass].map(f32::to_bits),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replay_reproduces_the_recorded_run() {
+        let settings = Settings {
+            body_count: 200,
+            seed: 3,
+            ..Default::default()
+        };
+        let schedule = [
+            (5, SimCommand::Kick(7, Vec2::new(3.0, -1.0))),
+            (
+                5,
+                SimCommand::Spawn(Body::new(Vec2::new(40.0, 0.0), Vec2::zero(), 50.0, 2.0)),
+            ),
+            (12, SimCommand::Despawn(11)),
+            (20, SimCommand::ReverseVelocities),
+        ];
+
+        let mut recorded = Simulation::new(&settings);
+        let mut log = CommandLog::default();
+        for _ in 0..30 {
+            for (_, command) in schedule
+                .iter()
+                .filter(|(frame, _)| *frame == recorded.frame)
+            {
+                log.apply(&mut recorded, command.clone());
+            }
+            recorded.step();
+        }
+
+        let path = std::env::temp_dir().join("barnes-hut-replay-test.bin");
+        log.save(&path).unwrap();
+        let mut log = CommandLog::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(log.entries.len(), schedule.len());
+
+        let mut replayed = Simulation::new(&settings);
+        for _ in 0..30 {
+            log.replay(&mut replayed);
+            replayed.step();
+        }
+        assert_eq!(replayed.frame, recorded.frame);
+        assert_eq!(bits(&replayed), bits(&recorded));
+    }
+}
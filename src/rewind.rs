@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+
+use crate::body::Body;
+
+/// Bytes all snapshots together may take, runs too large for that many
+/// snapshots aren't recorded at all.
+pub const MAX_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub frame: usize,
+    pub time: f32,
+    pub accreted_mass: f32,
+    pub bodies: Vec<Body>,
+}
+
+/// Ring buffer of the last `capacity` states, taken every `interval` frames,
+/// for stepping back interactively. Unlike saving to disk it only keeps
+/// what's needed to continue from there.
+#[derive(Clone, Debug)]
+pub struct Rewind {
+    pub capacity: usize,
+    pub interval: usize,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize, interval: usize) -> Self {
+        Self {
+            capacity,
+            interval,
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// Whether `capacity` snapshots of `body_count` bodies fit in `MAX_BYTES`.
+    pub fn fits(&self, body_count: usize) -> bool {
+        body_count * std::mem::size_of::<Body>() * self.capacity <= MAX_BYTES
+    }
+
+    /// Takes a snapshot if `frame` is due, dropping the oldest once full.
+    pub fn record(&mut self, frame: usize, time: f32, accreted_mass: f32, bodies: &[Body]) {
+        if self.capacity == 0 || self.interval == 0 || frame % self.interval != 0 {
+            return;
+        }
+        if !self.fits(bodies.len()) {
+            self.clear();
+            return;
+        }
+        if self
+            .snapshots
+            .back()
+            .is_some_and(|last| last.frame == frame)
+        {
+            return;
+        }
+
+        while self.snapshots.len() >= self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(Snapshot {
+            frame,
+            time,
+            accreted_mass,
+            bodies: bodies.to_vec(),
+        });
+    }
+
+    /// Drops every snapshot from `frame` on and returns the newest one
+    /// before it, which is kept so it can be returned to again.
+    pub fn before(&mut self, frame: usize) -> Option<&Snapshot> {
+        while self.snapshots.back()?.frame >= frame {
+            self.snapshots.pop_back();
+        }
+        self.snapshots.back()
+    }
+}
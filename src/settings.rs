@@ -0,0 +1,217 @@
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    quadtree::{MultipoleOrder, NodeOrder, OpeningCriterion, Quad},
+    simulation::{
+        AdaptiveTimestep, BlockTimesteps, Decomposition, DomainPolicy, Emitter, ForceKind,
+        ForceMethod, Integrator, MergeThreshold, SoftWall,
+    },
+    units::Units,
+    utils::DiscOptions,
+};
+
+pub const SETTINGS_PATH: &str = "config.toml";
+
+/// Startup parameters of the simulation and renderer. Keys missing from
+/// the file keep their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub dt: f32,
+    pub adaptive_timestep: AdaptiveTimestep,
+    pub block_timesteps: BlockTimesteps,
+    pub near_field_substeps: usize,
+    pub integrator: Integrator,
+    pub max_speed: Option<f32>,
+    pub pin_com: bool,
+    pub rotating_frame: Option<f32>,
+    pub soft_wall: Option<SoftWall>,
+    pub emitters: Vec<Emitter>,
+    /// Emitters pause while there are at least this many bodies.
+    pub max_bodies: usize,
+    pub body_count: usize,
+    pub seed: u64,
+    pub disc: DiscOptions,
+    pub theta: f32,
+    pub epsilon: f32,
+    pub softening_by_radius: bool,
+    /// Coefficient `k` of `SofteningMode::PerBodyMass`, `None` to disable.
+    pub mass_softening: Option<f32>,
+    pub min_interaction_distance: f32,
+    pub f64_accumulation: bool,
+    /// Sums the pull of sinks exactly instead of through the tree.
+    pub direct_anchors: bool,
+    pub enforce_momentum_conservation: bool,
+    pub multipole_order: MultipoleOrder,
+    pub opening_criterion: OpeningCriterion,
+    /// Size below which tree nodes aren't split, 0 to always split.
+    pub min_node_size: f32,
+    pub node_order: NodeOrder,
+    /// Fixed root of the tree, fitted to the bodies if `None`.
+    pub domain: Option<Quad>,
+    pub domain_policy: DomainPolicy,
+    pub method: ForceMethod,
+    pub decomposition: Decomposition,
+    pub force_kind: ForceKind,
+    pub gravity_enabled: bool,
+    pub refit_interval: usize,
+    pub capture_radius: f32,
+    pub restitution: Option<f32>,
+    pub merge_threshold: Option<MergeThreshold>,
+    pub steps_per_frame: usize,
+    pub step_delay_ms: usize,
+    pub pause_on_instability: Option<f32>,
+    /// Snapshots kept to rewind to, 0 to disable.
+    pub rewind_capacity: usize,
+    /// Frames between snapshots.
+    pub rewind_interval: usize,
+    pub max_steps_per_second: usize,
+
+    pub max_fps: usize,
+    pub background: [u8; 4],
+    pub render_radius_scale: f32,
+    pub min_render_radius_px: f32,
+    pub show_scale_bar: bool,
+    pub show_com: bool,
+    pub show_bounds: bool,
+    /// Scroll steps to double or halve the scale.
+    pub zoom_steps: f32,
+    pub invert_scroll: bool,
+    /// Scrolling during a spawn drag sets the mass, otherwise winding does.
+    pub scroll_spawn_mass: bool,
+    pub record_dir: String,
+    pub record_stride: usize,
+    /// Physical units to show values in, the simulation's own if `None`.
+    pub units: Option<Units>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            dt: 0.05,
+            adaptive_timestep: AdaptiveTimestep::default(),
+            block_timesteps: BlockTimesteps::default(),
+            near_field_substeps: 1,
+            integrator: Integrator::Verlet,
+            max_speed: None,
+            pin_com: false,
+            rotating_frame: None,
+            soft_wall: None,
+            emitters: Vec::new(),
+            max_bodies: 200000,
+            body_count: 100000,
+            seed: 0,
+            disc: DiscOptions::default(),
+            theta: 1.0,
+            epsilon: 1.0,
+            softening_by_radius: false,
+            mass_softening: None,
+            min_interaction_distance: 0.0,
+            f64_accumulation: false,
+            direct_anchors: false,
+            enforce_momentum_conservation: false,
+            multipole_order: MultipoleOrder::Monopole,
+            opening_criterion: OpeningCriterion::Classic,
+            min_node_size: 0.0,
+            node_order: NodeOrder::Insertion,
+            domain: None,
+            domain_policy: DomainPolicy::Clamp,
+            method: ForceMethod::BarnesHut,
+            decomposition: Decomposition::Quadtree,
+            force_kind: ForceKind::Gravity,
+            gravity_enabled: true,
+            refit_interval: 0,
+            capture_radius: 0.0,
+            restitution: Some(0.5),
+            merge_threshold: None,
+            steps_per_frame: 1,
+            step_delay_ms: 0,
+            pause_on_instability: None,
+            rewind_capacity: 10,
+            rewind_interval: 100,
+            max_steps_per_second: 0,
+
+            max_fps: 0,
+            background: [0, 0, 0, 0xff],
+            render_radius_scale: 1.0,
+            min_render_radius_px: 0.0,
+            show_scale_bar: false,
+            show_com: false,
+            show_bounds: false,
+            zoom_steps: 5.0,
+            invert_scroll: false,
+            scroll_spawn_mass: true,
+            record_dir: "frames".to_string(),
+            record_stride: 1,
+            units: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Reads the settings at `path`, falling back to the defaults if the
+    /// file is missing or malformed.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let Ok(text) = fs::read_to_string(path) else {
+            return Self::default();
+        };
+
+        toml::from_str(&text).unwrap_or_else(|err| {
+            eprintln!("Ignoring {}: {}", path.display(), err);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let text =
+            toml::to_string(self).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ultraviolet::Vec2;
+
+    #[test]
+    fn settings_round_trip_through_toml() {
+        let settings = Settings {
+            dt: 0.01,
+            theta: 0.7,
+            seed: 42,
+            soft_wall: Some(SoftWall {
+                half_size: 100.0,
+                stiffness: 0.5,
+            }),
+            emitters: vec![Emitter::default()],
+            domain: Some(Quad {
+                center: Vec2::new(1.0, 2.0),
+                size: 100.0,
+            }),
+            background: [1, 2, 3, 4],
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join("barnes-hut-settings-round-trip.toml");
+        settings.save(&path).unwrap();
+        let loaded = Settings::load(&path);
+        let _ = fs::remove_file(&path);
+
+        let text = toml::to_string(&settings).unwrap();
+        assert_eq!(toml::to_string(&loaded).unwrap(), text);
+        assert_eq!(loaded.theta, 0.7);
+        assert_eq!(loaded.domain, settings.domain);
+    }
+
+    #[test]
+    fn missing_keys_keep_their_default() {
+        let settings: Settings = toml::from_str("theta = 0.5").unwrap();
+        let defaults = Settings::default();
+        assert_eq!(settings.theta, 0.5);
+        assert_eq!(settings.dt, defaults.dt);
+        assert_eq!(settings.body_count, defaults.body_count);
+    }
+}
@@ -1,66 +1,1484 @@
 use crate::{
     body::Body,
+    grid::Grid,
     quadtree::{Quad, Quadtree},
-    utils,
+    rewind::Rewind,
+    settings::Settings,
+    utils::{self, FieldSum},
 };
 
 use broccoli::aabb::Rect;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use ultraviolet::Vec2;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ForceKind {
+    /// Attraction between masses.
+    Gravity,
+    /// Coulomb-like interaction between charges, repulsive for equal signs.
+    Coulomb,
+}
+
+impl ForceKind {
+    /// The quantity a body contributes to the field.
+    pub fn source(self, body: &Body) -> f32 {
+        match self {
+            ForceKind::Gravity => body.mass,
+            ForceKind::Coulomb => body.charge,
+        }
+    }
+
+    /// Converts the field at a body into its acceleration.
+    pub fn acc(self, body: &Body, field: Vec2) -> Vec2 {
+        match self {
+            ForceKind::Gravity => field,
+            ForceKind::Coulomb => field * (-body.charge / body.mass),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integrator {
+    /// Symplectic Euler, cheap and keeps energy bounded over long runs.
+    Verlet,
+    /// Classical fourth order Runge-Kutta. More accurate per step at four
+    /// field evaluations, but not symplectic, so energy drifts over time.
+    Rk4,
+}
+
+/// Spatial decomposition used for the force calculation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Decomposition {
+    /// Barnes-Hut quadtree with one body per leaf.
+    Quadtree,
+    /// Uniform grid of buckets summed directly in the near field, with a
+    /// tree over the cells for the far field. Fast for uniform
+    /// distributions, slow once bodies cluster into a few cells.
+    Grid,
+}
+
+/// How the field at each body is evaluated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ForceMethod {
+    /// Approximated with the chosen decomposition.
+    BarnesHut,
+    /// Exact O(n²) sum over all pairs, no tree is built.
+    Direct,
+}
+
+/// What happens to bodies outside a fixed `Simulation::domain`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DomainPolicy {
+    /// They keep moving, but the tree sees them on the nearest edge.
+    Clamp,
+    /// They're removed at the end of the step.
+    Cull,
+}
+
+/// A change to the simulation requested from outside the step loop.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SimCommand {
+    /// Adds a body, assigning it a fresh id.
+    Spawn(Body),
+    /// Removes the body with the given id.
+    Despawn(u64),
+    /// Replaces all bodies and restarts the clock.
+    Reset(Vec<Body>),
+    ReverseVelocities,
+    /// Pins or releases the body with the given id.
+    SetFixed(u64, bool),
+    /// Changes the body count while conserving mass, see `Simulation::resample`.
+    Resample(usize),
+    /// Returns to the last snapshot of `Simulation::history`.
+    Rewind,
+    /// Multiplies every mass by the factor, see `Simulation::scale_all_masses`.
+    ScaleMasses(f32),
+    /// Adds the velocity to the body with the given id, see `Simulation::kick`.
+    Kick(u64, Vec2),
+}
+
+/// How close encounters are softened.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SofteningMode {
+    /// The same length for every interaction.
+    Global(f32),
+    /// The sum of both bodies' radii, so larger bodies interact more softly.
+    /// Approximated nodes and the grid fall back to the last global length.
+    PerBodyRadius,
+    /// `k / m^(1/3)` of the heavier body of each pair. Light bodies stand in
+    /// for a smooth mass distribution and need smoothing, while a heavy
+    /// body like a central black hole is compact and stays nearly a point,
+    /// so everything orbiting it feels a sharp potential. Approximated
+    /// nodes and the grid fall back to the last global length.
+    PerBodyMass(f32),
+}
+
+impl SofteningMode {
+    /// Squared softening length of `k / m^(1/3)`.
+    pub fn mass_e_sq(k: f32, mass: f32) -> f32 {
+        k * k / mass.cbrt().powi(2)
+    }
+}
+
+/// Why a step is considered to have gone unstable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instability {
+    /// The body with this id has a non-finite position, velocity or
+    /// acceleration.
+    NonFinite(u64),
+    /// Fractional change of the total energy over one step.
+    EnergyJump(f32),
+}
+
+impl std::fmt::Display for Instability {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Instability::NonFinite(id) => write!(f, "body {} is no longer finite", id),
+            Instability::EnergyJump(change) => {
+                write!(f, "the energy changed by {:.1}%", change * 100.0)
+            }
+        }
+    }
+}
+
+/// Principal axes of a mass distribution, see `Simulation::shape`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shape {
+    /// Mass-weighted RMS extent along the major and minor axis.
+    pub major: f32,
+    pub minor: f32,
+    /// Angle of the major axis from the x axis, in `-PI/2..=PI/2`.
+    pub angle: f32,
+}
+
+impl Shape {
+    /// From 0 for a line to 1 for a round distribution.
+    pub fn axis_ratio(&self) -> f32 {
+        if self.major == 0.0 {
+            1.0
+        } else {
+            self.minor / self.major
+        }
+    }
+}
+
+/// Chooses `dt = eta * sqrt(epsilon / max_acc)` each step, clamped to
+/// `dt_min..=dt_max`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct AdaptiveTimestep {
+    pub enabled: bool,
+    pub eta: f32,
+    pub dt_min: f32,
+    pub dt_max: f32,
+}
+
+impl Default for AdaptiveTimestep {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            eta: 0.2,
+            dt_min: 0.001,
+            dt_max: 0.1,
+        }
+    }
+}
+
+/// Hierarchical timesteps, where each body steps with `dt / 2^k` for the
+/// smallest bin `k < bins` that resolves its acceleration the way
+/// `AdaptiveTimestep` chooses `dt`. Only the bodies in close encounters are
+/// kicked on the short substeps, the rest coast until their next kick, so a
+/// single close pass no longer slows down everything. Bins are assigned
+/// anew every step and only apply to the `Verlet` integrator.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct BlockTimesteps {
+    pub enabled: bool,
+    pub bins: usize,
+    pub eta: f32,
+}
+
+impl BlockTimesteps {
+    /// Bins beyond this would split a step into too many substeps.
+    pub const MAX_BINS: usize = 10;
+}
+
+impl Default for BlockTimesteps {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bins: 4,
+            eta: 0.2,
+        }
+    }
+}
+
+/// Fixed point emitting bodies at a steady rate as the simulation advances,
+/// see `Simulation::emit`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Emitter {
+    pub pos: Vec2,
+    /// Bodies per unit of simulated time.
+    pub rate: f32,
+    /// Mean launch velocity.
+    pub velocity: Vec2,
+    /// Launch velocities are spread uniformly over a disc of this radius
+    /// around `velocity`.
+    pub spread: f32,
+    pub mass: f32,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            pos: Vec2::zero(),
+            rate: 10.0,
+            velocity: Vec2::zero(),
+            spread: 1.0,
+            mass: 1.0,
+        }
+    }
+}
+
+/// Walls around the square `[-half_size, half_size]²` that push bodies
+/// beyond them back with `stiffness` times how far out they are, like a
+/// spring. Unlike a hard reflection this is a smooth potential, so a full
+/// bounce conserves energy up to the integration error.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SoftWall {
+    pub half_size: f32,
+    pub stiffness: f32,
+}
+
+impl SoftWall {
+    pub fn acc(&self, pos: Vec2) -> Vec2 {
+        let push = |x: f32| -x.signum() * (x.abs() - self.half_size).max(0.0) * self.stiffness;
+        Vec2::new(push(pos.x), push(pos.y))
+    }
+}
+
+/// Size from which overlapping bodies merge instead of colliding, a pair
+/// merges once either body reaches `mass` or `radius`. Zero for both
+/// merges every overlapping pair.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MergeThreshold {
+    pub mass: f32,
+    pub radius: f32,
+}
+
+impl MergeThreshold {
+    pub fn admits(&self, body: &Body) -> bool {
+        body.mass >= self.mass || body.radius >= self.radius
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Simulation {
     pub dt: f32,
+    /// 1 to run forward, -1 to integrate with `-dt` and play backward.
+    /// Only the forces are time reversible, merging, accretion and
+    /// emitters aren't undone and break the retracing.
+    pub time_direction: f32,
+    pub adaptive_timestep: AdaptiveTimestep,
+    pub block_timesteps: BlockTimesteps,
+    /// Substeps the Verlet integrator splits each step into, recomputing
+    /// only the near field the tree sums directly on each and holding the
+    /// rest of the acceleration from the start of the step. Resolves close
+    /// encounters more cheaply than a smaller `dt`. 1 to disable, block
+    /// timesteps take precedence.
+    pub near_field_substeps: usize,
+    pub integrator: Integrator,
+    /// Speed every body is clamped to after integrating. Not physical, only
+    /// a guard that keeps unstable setups from flinging bodies away.
+    pub max_speed: Option<f32>,
+    /// Moves the center of mass back to the origin and removes the net
+    /// momentum after every step. Numerical hygiene against round-off
+    /// drift, not a physical force. Pinned bodies are moved along.
+    pub pin_com: bool,
+    /// Angular velocity Ω of the frame the simulation runs in, rotating
+    /// counterclockwise about the origin for positive values. Adds the
+    /// centrifugal `Ω²r` and Coriolis `-2Ω × v` accelerations.
+    pub rotating_frame: Option<f32>,
+    pub soft_wall: Option<SoftWall>,
+    pub emitters: Vec<Emitter>,
+    /// Emitters pause while there are at least this many bodies.
+    pub max_bodies: usize,
+    pub time: f32,
     pub frame: usize,
     pub bodies: Vec<Body>,
     pub quadtree: Quadtree,
+    /// Root of every tree, whatever the extent of the bodies, so the tree
+    /// structure is reproducible and independent of escapers. Automatic
+    /// if `None`. Only the quadtree decomposition uses it.
+    pub domain: Option<Quad>,
+    pub domain_policy: DomainPolicy,
+    pub softening: SofteningMode,
+    /// Bodies closer than this pull on each other as if they were this far
+    /// apart, so the force ramps down to zero inside it. Only applies to
+    /// exact body to body terms, approximated nodes are unaffected. Unlike
+    /// softening, which weakens every interaction out to a few softening
+    /// lengths, the force beyond this distance stays exact.
+    pub min_interaction_distance: f32,
+    /// Sums the contributions to each acceleration in `f64`, see `FieldSum`.
+    pub f64_accumulation: bool,
+    /// Leaves sinks out of the tree and sums their pull exactly, so a heavy
+    /// central mass never skews the opening of its quadrant and is felt
+    /// exactly whatever theta is.
+    pub direct_anchors: bool,
+    /// Removes the mass-weighted mean of the field accelerations of the
+    /// free bodies after every evaluation. Each body approximates the far
+    /// field on its own, so the forces don't cancel like pairs would and
+    /// momentum slowly leaks. This corrects the approximation, external
+    /// forces like the walls are left alone.
+    pub enforce_momentum_conservation: bool,
+    pub method: ForceMethod,
+    pub decomposition: Decomposition,
+    pub grid: Grid,
+    pub force_kind: ForceKind,
+    pub refit_interval: usize,
+    pub gravity_enabled: bool,
+    pub build_tree_without_gravity: bool,
+    pub capture_radius: f32,
+    /// Fraction of the approach speed overlapping bodies bounce off with,
+    /// 1 for elastic and 0 for sticking. `None` lets them pass through.
+    pub restitution: Option<f32>,
+    /// Overlapping pairs with a body past it merge, `None` never merges.
+    pub merge_threshold: Option<MergeThreshold>,
+    pub accreted_mass: f32,
+    /// Recent states to rewind to.
+    pub history: Rewind,
+    refits: usize,
+    /// Bodies the next `attract` updates during a block step, all if empty.
+    active: Vec<bool>,
+    next_id: u64,
 }
 
-impl Simulation {
-    pub fn new() -> Self {
-        let dt = 0.05;
-        let n = 100000;
-        let theta = 1.0;
-        let epsilon = 1.0;
+impl Simulation {
+    pub fn new(settings: &Settings) -> Self {
+        let bodies: Vec<Body> =
+            utils::uniform_disc(settings.body_count, settings.seed, &settings.disc);
+        let next_id = Self::next_id(&bodies);
+        let mut quadtree = Quadtree::new(settings.theta, settings.epsilon);
+        quadtree.multipole_order = settings.multipole_order;
+        quadtree.opening_criterion = settings.opening_criterion;
+        quadtree.min_node_size = settings.min_node_size;
+        quadtree.node_order = settings.node_order;
+
+        Self {
+            dt: settings.dt,
+            time_direction: 1.0,
+            adaptive_timestep: settings.adaptive_timestep,
+            block_timesteps: settings.block_timesteps,
+            near_field_substeps: settings.near_field_substeps,
+            integrator: settings.integrator,
+            max_speed: settings.max_speed,
+            pin_com: settings.pin_com,
+            rotating_frame: settings.rotating_frame,
+            soft_wall: settings.soft_wall,
+            emitters: settings.emitters.clone(),
+            max_bodies: settings.max_bodies,
+            time: 0.0,
+            frame: 0,
+            bodies,
+            quadtree,
+            domain: settings.domain,
+            domain_policy: settings.domain_policy,
+            softening: match (settings.softening_by_radius, settings.mass_softening) {
+                (true, _) => SofteningMode::PerBodyRadius,
+                (false, Some(k)) => SofteningMode::PerBodyMass(k),
+                (false, None) => SofteningMode::Global(settings.epsilon),
+            },
+            min_interaction_distance: settings.min_interaction_distance,
+            f64_accumulation: settings.f64_accumulation,
+            direct_anchors: settings.direct_anchors,
+            enforce_momentum_conservation: settings.enforce_momentum_conservation,
+            method: settings.method,
+            decomposition: settings.decomposition,
+            grid: Grid::new(settings.theta, settings.epsilon),
+            force_kind: settings.force_kind,
+            refit_interval: settings.refit_interval,
+            gravity_enabled: settings.gravity_enabled,
+            build_tree_without_gravity: false,
+            capture_radius: settings.capture_radius,
+            restitution: settings.restitution,
+            merge_threshold: settings.merge_threshold,
+            accreted_mass: 0.0,
+            history: Rewind::new(settings.rewind_capacity, settings.rewind_interval),
+            refits: 0,
+            active: Vec::new(),
+            next_id,
+        }
+    }
+
+    pub fn bodies(&self) -> &[Body] {
+        &self.bodies
+    }
+
+    pub fn bodies_mut(&mut self) -> &mut [Body] {
+        &mut self.bodies
+    }
+
+    pub fn quadtree(&self) -> &Quadtree {
+        &self.quadtree
+    }
+
+    /// Exact O(n²) accelerations using the same softened kernel as the tree,
+    /// the reference the approximation can be measured against.
+    pub fn direct_accelerations(&self) -> Vec<Vec2> {
+        let e_sq = |a: &Body, b: &Body| match self.softening {
+            SofteningMode::Global(epsilon) => epsilon * epsilon,
+            SofteningMode::PerBodyRadius => (a.radius + b.radius).powi(2),
+            SofteningMode::PerBodyMass(k) => SofteningMode::mass_e_sq(k, a.mass.max(b.mass)),
+        };
+        let kind = self.force_kind;
+        let min_d_sq = self.min_interaction_distance.powi(2);
+        let precise = self.f64_accumulation;
+        let acc = |body: &Body| {
+            let mut field = FieldSum::new(precise);
+            for other in &self.bodies {
+                let d = other.pos - body.pos;
+                let d_sq = d.mag_sq();
+                if d_sq == 0.0 {
+                    continue;
+                }
+                let e_sq = e_sq(body, other);
+                let r_sq = d_sq.max(min_d_sq);
+                field.add(d * (kind.source(other) / ((r_sq + e_sq) * r_sq.sqrt())));
+            }
+            kind.acc(body, field.total())
+        };
+
+        #[cfg(feature = "parallel")]
+        let bodies = self.bodies.par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let bodies = self.bodies.iter();
+        bodies.map(acc).collect()
+    }
+
+    /// Sum of `mass * acc` over all bodies, which Newton's third law says
+    /// should vanish. The tree only satisfies it approximately.
+    pub fn net_force(&self) -> Vec2 {
+        self.bodies
+            .iter()
+            .fold(Vec2::zero(), |force, body| force + body.acc * body.mass)
+    }
+
+    pub fn kinetic_energy(&self) -> f32 {
+        self.bodies
+            .iter()
+            .map(|body| 0.5 * body.mass * body.vel.mag_sq())
+            .sum()
+    }
+
+    /// Softened potential energy of all pairs, evaluated with the tree from
+    /// the last step.
+    pub fn potential_energy(&self) -> f32 {
+        if self.quadtree.nodes.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self
+            .bodies
+            .iter()
+            .map(|body| self.body_potential_energy(body))
+            .sum();
+        0.5 * sum
+    }
+
+    /// Energy of `body` in the field of all others, through the tree from
+    /// the last step. Counts every pair once from either side.
+    fn body_potential_energy(&self, body: &Body) -> f32 {
+        let mut phi = self.quadtree.potential(body.pos);
+        if self.direct_anchors {
+            phi += self.anchor_potential(body.pos);
+        }
+        match self.force_kind {
+            ForceKind::Gravity => body.mass * phi,
+            // Like charges repel, so their energy is positive
+            ForceKind::Coulomb => -body.charge * phi,
+        }
+    }
+
+    /// Kinetic plus potential energy of each body, negative for bound ones.
+    /// Only the kinetic part before the first step builds the tree.
+    pub fn binding_energies(&self) -> Vec<f32> {
+        let built = !self.quadtree.nodes.is_empty();
+        self.bodies
+            .iter()
+            .map(|body| {
+                let kinetic = 0.5 * body.mass * body.vel.mag_sq();
+                if built {
+                    kinetic + self.body_potential_energy(body)
+                } else {
+                    kinetic
+                }
+            })
+            .collect()
+    }
+
+    /// Indices of the most and the least bound body, `None` without bodies.
+    pub fn binding_extremes(&self) -> Option<(usize, usize)> {
+        let energies = self.binding_energies();
+        let by_energy = |a: &(usize, &f32), b: &(usize, &f32)| a.1.total_cmp(b.1);
+        let most = energies.iter().enumerate().min_by(by_energy)?.0;
+        let least = energies.iter().enumerate().max_by(by_energy)?.0;
+        Some((most, least))
+    }
+
+    /// Bodies with positive energy, which escape unless something slows them.
+    pub fn unbound_count(&self) -> usize {
+        self.binding_energies()
+            .into_iter()
+            .filter(|&energy| energy > 0.0)
+            .count()
+    }
+
+    /// Exact potential of the sinks at `pos`, which the tree leaves out
+    /// with `direct_anchors`.
+    fn anchor_potential(&self, pos: Vec2) -> f32 {
+        let e_sq = self.quadtree.e_sq;
+        self.bodies
+            .iter()
+            .filter(|anchor| anchor.is_sink && anchor.pos != pos)
+            .map(|anchor| {
+                -self.force_kind.source(anchor) / ((anchor.pos - pos).mag_sq() + e_sq).sqrt()
+            })
+            .sum()
+    }
+
+    pub fn total_energy(&self) -> f32 {
+        self.kinetic_energy() + self.potential_energy()
+    }
+
+    /// Checks for non-finite bodies and for a fractional change of the total
+    /// energy from `energy_before` to `energy` above `max_change`.
+    pub fn instability(
+        &self,
+        energy_before: f32,
+        energy: f32,
+        max_change: f32,
+    ) -> Option<Instability> {
+        let finite = |v: Vec2| v.x.is_finite() && v.y.is_finite();
+        if let Some(body) = self
+            .bodies
+            .iter()
+            .find(|body| !(finite(body.pos) && finite(body.vel) && finite(body.acc)))
+        {
+            return Some(Instability::NonFinite(body.id));
+        }
+
+        let change = ((energy - energy_before) / energy_before).abs();
+        (change > max_change).then_some(Instability::EnergyJump(change))
+    }
+
+    /// `2T / |U|`, which the virial theorem says is close to 1 for a
+    /// gravitating system in equilibrium. Below 1 it collapses, above 1 it
+    /// expands.
+    pub fn virial_ratio(&self) -> f32 {
+        2.0 * self.kinetic_energy() / self.potential_energy().abs()
+    }
+
+    pub fn total_momentum(&self) -> Vec2 {
+        self.bodies.iter().fold(Vec2::zero(), |momentum, body| {
+            momentum + body.vel * body.mass
+        })
+    }
+
+    /// `Σ m (r × v)` about the center of mass and in its rest frame, which
+    /// gravity conserves. Drift points at the integrator or the force.
+    pub fn total_angular_momentum(&self) -> f32 {
+        let (mut mass, mut pos, mut vel) = (0.0, Vec2::zero(), Vec2::zero());
+        for body in &self.bodies {
+            mass += body.mass;
+            pos += body.pos * body.mass;
+            vel += body.vel * body.mass;
+        }
+        if mass == 0.0 {
+            return 0.0;
+        }
+        let (pos, vel) = (pos / mass, vel / mass);
+        self.bodies
+            .iter()
+            .map(|body| body.mass * (body.pos - pos).wedge(body.vel - vel).xy)
+            .sum()
+    }
+
+    /// `Σ m (|r|² I - r r^T)` about the center of mass.
+    pub fn inertia_tensor(&self) -> [[f32; 2]; 2] {
+        let (mut mass, mut com) = (0.0, Vec2::zero());
+        for body in &self.bodies {
+            mass += body.mass;
+            com += body.pos * body.mass;
+        }
+        if mass == 0.0 {
+            return [[0.0; 2]; 2];
+        }
+        let com = com / mass;
+
+        let (mut xx, mut xy, mut yy) = (0.0, 0.0, 0.0);
+        for body in &self.bodies {
+            let r = body.pos - com;
+            xx += body.mass * r.y * r.y;
+            xy -= body.mass * r.x * r.y;
+            yy += body.mass * r.x * r.x;
+        }
+        [[xx, xy], [xy, yy]]
+    }
+
+    /// Principal axes of `inertia_tensor`. The major axis is the one with
+    /// the smallest moment, along which the mass is spread out the most.
+    pub fn shape(&self) -> Option<Shape> {
+        let mass: f32 = self.bodies.iter().map(|body| body.mass).sum();
+        if mass == 0.0 {
+            return None;
+        }
+
+        // Second moments of the positions, the tensor with the axes swapped
+        let [[yy, xy], [_, xx]] = self.inertia_tensor();
+        let xy = -xy;
+        let mean = 0.5 * (xx + yy);
+        let spread = (0.25 * (xx - yy).powi(2) + xy * xy).sqrt();
+        Some(Shape {
+            major: ((mean + spread).max(0.0) / mass).sqrt(),
+            minor: ((mean - spread).max(0.0) / mass).sqrt(),
+            angle: 0.5 * (2.0 * xy).atan2(xx - yy),
+        })
+    }
+
+    /// How evenly the populations are mixed, from 0 when every grid cell
+    /// holds a single population to 1 when every cell has the overall mass
+    /// fractions. Per cell this is `1 - max fraction` normalized by its
+    /// value for the overall fractions, averaged weighted by cell mass.
+    pub fn population_mixing(&self) -> f32 {
+        let count = self
+            .bodies
+            .iter()
+            .map(|body| body.population as usize + 1)
+            .max()
+            .unwrap_or(0);
+        let mut total = vec![0.0f32; count];
+        for body in &self.bodies {
+            total[body.population as usize] += body.mass;
+        }
+        let mass: f32 = total.iter().sum();
+        let max_fraction = total.iter().fold(0.0f32, |max, &m| max.max(m)) / mass;
+        if mass <= 0.0 || max_fraction >= 1.0 {
+            return 0.0;
+        }
+
+        let mut grid = Grid::new(1.0, 0.0);
+        grid.build(&self.bodies, |body| body.mass);
+        let mut cell_masses = vec![0.0f32; count];
+        let mut sum = 0.0;
+        for y in 0..grid.dims {
+            for x in 0..grid.dims {
+                cell_masses.fill(0.0);
+                for &i in grid.cell_bodies(x, y) {
+                    let body = &self.bodies[i];
+                    cell_masses[body.population as usize] += body.mass;
+                }
+                let cell_mass: f32 = cell_masses.iter().sum();
+                let cell_max = cell_masses.iter().fold(0.0f32, |max, &m| max.max(m));
+                sum += cell_mass - cell_max;
+            }
+        }
+        sum / (mass * (1.0 - max_fraction))
+    }
+
+    /// Distance from every body to its closest neighbor, using the tree from
+    /// the last step. Empty if the tree is out of date.
+    pub fn nearest_neighbor_distances(&self) -> Vec<f32> {
+        (0..self.bodies.len())
+            .filter_map(|i| self.quadtree.nearest_neighbor(i, &self.bodies))
+            .map(|(_, distance)| distance)
+            .collect()
+    }
+
+    fn next_id(bodies: &[Body]) -> u64 {
+        bodies.iter().map(|body| body.id + 1).max().unwrap_or(0)
+    }
+
+    /// Id the next added body gets.
+    pub fn next_body_id(&self) -> u64 {
+        self.next_id
+    }
+
+    /// Adds a body with a fresh id, which is returned.
+    pub fn add_body(&mut self, mut body: Body) -> u64 {
+        body.id = self.next_id;
+        self.next_id += 1;
+        self.bodies.push(body);
+        self.quadtree.leaves.clear();
+        body.id
+    }
+
+    /// Appends a batch of bodies with fresh, consecutive ids, such as a
+    /// second galaxy sent into a running one. Returns the ids given out.
+    pub fn add_bodies(&mut self, bodies: Vec<Body>) -> std::ops::Range<u64> {
+        let first = self.next_id;
+        self.next_id += bodies.len() as u64;
+        self.bodies
+            .extend(bodies.into_iter().zip(first..).map(|(mut body, id)| {
+                body.id = id;
+                body
+            }));
+        self.quadtree.leaves.clear();
+        first..self.next_id
+    }
+
+    pub fn apply(&mut self, command: SimCommand) {
+        match command {
+            SimCommand::Spawn(body) => {
+                self.add_body(body);
+            }
+            SimCommand::Despawn(id) => {
+                self.bodies.retain(|body| body.id != id);
+                self.quadtree.leaves.clear();
+            }
+            SimCommand::Reset(bodies) => self.reset(bodies),
+            SimCommand::ReverseVelocities => {
+                for body in &mut self.bodies {
+                    body.vel = -body.vel;
+                }
+            }
+            SimCommand::SetFixed(id, fixed) => self.set_fixed(id, fixed),
+            SimCommand::Resample(n) => self.resample(n),
+            SimCommand::ScaleMasses(factor) => self.scale_all_masses(factor),
+            SimCommand::Kick(id, delta_v) => self.kick(id, delta_v),
+            SimCommand::Rewind => {
+                self.rewind();
+            }
+        }
+    }
+
+    /// Restores the newest snapshot older than the current frame, returns
+    /// `false` if there is none.
+    pub fn rewind(&mut self) -> bool {
+        let Some(snapshot) = self.history.before(self.frame) else {
+            return false;
+        };
+        self.frame = snapshot.frame;
+        self.time = snapshot.time;
+        self.accreted_mass = snapshot.accreted_mass;
+        self.bodies.clone_from(&snapshot.bodies);
+        self.next_id = self.next_id.max(Self::next_id(&self.bodies));
+        self.quadtree.leaves.clear();
+        true
+    }
+
+    /// Multiplies every mass by `factor` and recomputes the radii as
+    /// `mass.cbrt()`, like the generators make them. Bodies sized otherwise,
+    /// like the central sink, get that radius too.
+    pub fn scale_all_masses(&mut self, factor: f32) {
+        for body in &mut self.bodies {
+            body.mass *= factor;
+            body.radius = body.mass.cbrt();
+        }
+        self.quadtree.leaves.clear();
+    }
+
+    pub fn set_fixed(&mut self, id: u64, fixed: bool) {
+        if let Some(body) = self.bodies.iter_mut().find(|body| body.id == id) {
+            body.fixed = fixed;
+            if fixed {
+                body.vel = Vec2::zero();
+            }
+        }
+    }
+
+    /// Adds `delta_v` to the velocity of the body with the given id. Pinned
+    /// bodies stay at rest.
+    pub fn kick(&mut self, id: u64, delta_v: Vec2) {
+        if let Some(body) = self.bodies.iter_mut().find(|body| body.id == id) {
+            if !body.fixed {
+                body.vel += delta_v;
+            }
+        }
+    }
+
+    /// Brings the body count to `target_n`, conserving mass and momentum.
+    /// Shrinking merges randomly chosen bodies into random survivors,
+    /// growing splits the heaviest bodies in two halves offset by their
+    /// radius in opposite directions. Pinned bodies and sinks are left alone.
+    pub fn resample(&mut self, target_n: usize) {
+        let mut rng = fastrand::Rng::with_seed(self.frame as u64);
+        let n = self.bodies.len();
+        let mut candidates: Vec<usize> = (0..n)
+            .filter(|&i| !self.bodies[i].fixed && !self.bodies[i].is_sink)
+            .collect();
+
+        if target_n < n {
+            rng.shuffle(&mut candidates);
+            let count = (n - target_n).min(candidates.len().saturating_sub(1));
+            let (removed, survivors) = candidates.split_at(count);
+
+            let mut gone = vec![false; n];
+            for &i in removed {
+                let body = self.bodies[i];
+                let survivor = &mut self.bodies[survivors[rng.usize(..survivors.len())]];
+                let mass = survivor.mass + body.mass;
+                survivor.vel = (survivor.vel * survivor.mass + body.vel * body.mass) / mass;
+                survivor.mass = mass;
+                survivor.radius = mass.cbrt();
+                gone[i] = true;
+            }
+
+            let mut index = 0;
+            self.bodies.retain(|_| {
+                index += 1;
+                !gone[index - 1]
+            });
+        } else if !candidates.is_empty() {
+            candidates.sort_by(|&a, &b| self.bodies[b].mass.total_cmp(&self.bodies[a].mass));
+            for &i in candidates.iter().cycle().take(target_n - n) {
+                let body = &mut self.bodies[i];
+                body.mass *= 0.5;
+                body.radius = body.mass.cbrt();
+
+                let a = rng.f32() * std::f32::consts::TAU;
+                let offset = Vec2::new(a.cos(), a.sin()) * body.radius;
+                let mut half = *body;
+                body.pos += offset;
+                half.pos -= offset;
+                half.id = self.next_id;
+                self.next_id += 1;
+                self.bodies.push(half);
+            }
+        }
+
+        self.quadtree.leaves.clear();
+    }
+
+    pub fn reset(&mut self, bodies: Vec<Body>) {
+        self.next_id = Self::next_id(&bodies);
+        self.bodies = bodies;
+        self.frame = 0;
+        self.time = 0.0;
+        self.accreted_mass = 0.0;
+        self.quadtree.leaves.clear();
+        self.history.clear();
+    }
+
+    pub fn step(&mut self) {
+        self.emit();
+
+        #[cfg(debug_assertions)]
+        let before = (self.bodies.len(), self.total_mass());
+
+        if self.adaptive_timestep.enabled {
+            self.dt = self.adaptive_dt();
+        }
+        self.iterate();
+        let merged = self.collide();
+        let captured = merged + self.accrete();
+        let culled = self.cull_outside_domain();
+        self.attract();
+        if self.pin_com {
+            utils::recenter_and_zero_momentum(&mut self.bodies);
+        }
+        self.time += self.signed_dt();
+        self.frame += 1;
+        self.history
+            .record(self.frame, self.time, self.accreted_mass, &self.bodies);
+
+        #[cfg(debug_assertions)]
+        self.check_conservation(before, captured, culled);
+        #[cfg(not(debug_assertions))]
+        let _ = (captured, culled);
+    }
+
+    /// `dt` in the direction time runs in.
+    pub fn signed_dt(&self) -> f32 {
+        self.dt * self.time_direction
+    }
+
+    /// Steps and hands the new state to `hook`, returning what it returns,
+    /// e.g. whether to stop. `step` itself stays free of any hook.
+    pub fn step_with<R>(&mut self, hook: impl FnOnce(&Simulation) -> R) -> R {
+        self.step();
+        hook(self)
+    }
+
+    /// Adds the bodies every emitter is due over the coming step, while there
+    /// are fewer than `max_bodies`. Each starts as if launched at a random
+    /// moment of the step, so bodies emitted together don't coincide.
+    /// Returns how many were added.
+    pub fn emit(&mut self) -> usize {
+        let mut rng = fastrand::Rng::with_seed(self.frame as u64);
+        let (start, end) = (self.time, self.time + self.signed_dt());
+        let mut added = 0;
+        for i in 0..self.emitters.len() {
+            let emitter = self.emitters[i];
+            let due = (end * emitter.rate).floor() - (start * emitter.rate).floor();
+            for _ in 0..due.max(0.0) as usize {
+                if self.bodies.len() >= self.max_bodies {
+                    return added;
+                }
+                let angle = rng.f32() * std::f32::consts::TAU;
+                let offset = emitter.spread * rng.f32().sqrt();
+                let vel = emitter.velocity + Vec2::new(angle.cos(), angle.sin()) * offset;
+                let pos = emitter.pos + vel * (rng.f32() * self.dt);
+                self.add_body(Body::new(pos, vel, emitter.mass, emitter.mass.cbrt()));
+                added += 1;
+            }
+        }
+        added
+    }
+
+    pub fn total_mass(&self) -> f32 {
+        self.bodies.iter().map(|body| body.mass).sum()
+    }
+
+    /// Panics unless a step that started with `count` bodies of total `mass`
+    /// only lost the `captured` bodies, whose mass went into the sinks, and
+    /// the `culled` ones with their mass.
+    #[cfg(debug_assertions)]
+    fn check_conservation(
+        &self,
+        (count, mass): (usize, f32),
+        captured: usize,
+        (culled, culled_mass): (usize, f32),
+    ) {
+        assert_eq!(
+            self.bodies.len() + captured + culled,
+            count,
+            "step {} went from {} to {} bodies with {} merged or accreted and {} culled",
+            self.frame,
+            count,
+            self.bodies.len(),
+            captured,
+            culled
+        );
+        let total = self.total_mass() + culled_mass;
+        // Summing in a different order is all that may change it
+        let tolerance = 1e-4 * mass.abs().max(f32::MIN_POSITIVE);
+        assert!(
+            (total - mass).abs() <= tolerance,
+            "step {} changed the total mass from {} to {}",
+            self.frame,
+            mass,
+            total
+        );
+    }
+
+    /// Timestep resolving the largest current acceleration.
+    pub fn adaptive_dt(&self) -> f32 {
+        let AdaptiveTimestep {
+            eta,
+            dt_min,
+            dt_max,
+            ..
+        } = self.adaptive_timestep;
+        let max_acc = self
+            .bodies
+            .iter()
+            .filter(|body| !body.fixed)
+            .map(|body| body.acc.mag())
+            .fold(0.0, f32::max);
+        let epsilon = self.quadtree.e_sq.sqrt();
+        (eta * (epsilon / max_acc).sqrt()).max(dt_min).min(dt_max)
+    }
+
+    pub fn attract(&mut self) {
+        self.attract_field();
+        if self.enforce_momentum_conservation {
+            self.cancel_net_force();
+        }
+
+        let active = &self.active;
+        if let Some(omega) = self.rotating_frame {
+            for (i, body) in self.bodies.iter_mut().enumerate() {
+                if skipped(active, i) {
+                    continue;
+                }
+                let centrifugal = body.pos * (omega * omega);
+                // Ω × v for Ω along +z is Ω(-v.y, v.x)
+                let coriolis = Vec2::new(body.vel.y, -body.vel.x) * (2.0 * omega);
+                body.acc += centrifugal + coriolis;
+            }
+        }
+
+        if let Some(wall) = self.soft_wall {
+            for (i, body) in self.bodies.iter_mut().enumerate() {
+                if skipped(active, i) {
+                    continue;
+                }
+                body.acc += wall.acc(body.pos);
+            }
+        }
+    }
+
+    /// Subtracts the mean acceleration of the free bodies being updated,
+    /// so their total force is zero.
+    fn cancel_net_force(&mut self) {
+        let active = &self.active;
+        let (mut mass, mut force) = (0.0, Vec2::zero());
+        for (i, body) in self.bodies.iter().enumerate() {
+            if !body.fixed && !skipped(active, i) {
+                mass += body.mass;
+                force += body.acc * body.mass;
+            }
+        }
+        if mass == 0.0 {
+            return;
+        }
+
+        let mean = force / mass;
+        for (i, body) in self.bodies.iter_mut().enumerate() {
+            if !body.fixed && !skipped(active, i) {
+                body.acc -= mean;
+            }
+        }
+    }
+
+    fn attract_field(&mut self) {
+        if let SofteningMode::Global(epsilon) = self.softening {
+            self.quadtree.e_sq = epsilon * epsilon;
+            self.grid.far.e_sq = epsilon * epsilon;
+        }
+        self.quadtree.min_d_sq = self.min_interaction_distance.powi(2);
+        self.grid.far.min_d_sq = self.quadtree.min_d_sq;
+        self.quadtree.f64_accumulation = self.f64_accumulation;
+
+        if !self.gravity_enabled {
+            // Bodies only drift, the tree is built just for visualization
+            if self.build_tree_without_gravity && !self.refit() {
+                self.build();
+            }
+            for (i, body) in self.bodies.iter_mut().enumerate() {
+                if !skipped(&self.active, i) {
+                    body.acc = Vec2::zero();
+                }
+            }
+            return;
+        }
+
+        if self.method == ForceMethod::Direct {
+            // Like with gravity off, the tree is only kept for visualization
+            if self.build_tree_without_gravity && !self.refit() {
+                self.build();
+            }
+            let accs = self.direct_accelerations();
+            for (i, (body, acc)) in self.bodies.iter_mut().zip(accs).enumerate() {
+                if !skipped(&self.active, i) {
+                    body.acc = acc;
+                }
+            }
+            return;
+        }
+
+        if self.decomposition == Decomposition::Grid {
+            let kind = self.force_kind;
+            self.grid.far.multipole_order = self.quadtree.multipole_order;
+            self.grid.far.opening_criterion = self.quadtree.opening_criterion;
+            self.grid.build(&self.bodies, |body| kind.source(body));
+            let fields: Vec<Option<Vec2>> = (0..self.bodies.len())
+                .map(|i| {
+                    (!skipped(&self.active, i))
+                        .then(|| self.grid.field(i, &self.bodies, |body| kind.source(body)))
+                })
+                .collect();
+            for (body, field) in self.bodies.iter_mut().zip(fields) {
+                if let Some(field) = field {
+                    body.acc = kind.acc(body, field);
+                }
+            }
+            return;
+        }
+
+        if !self.refit() {
+            self.build();
+        }
+
+        // With `direct_anchors` the sinks are missing from the tree
+        let anchors: Vec<Body> = if self.direct_anchors {
+            self.bodies
+                .iter()
+                .filter(|body| body.is_sink)
+                .copied()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let e_sq = self.quadtree.e_sq;
+        let softening = self.softening;
+        let kind = self.force_kind;
+        let anchor_field = |body: &Body| {
+            let mut field = Vec2::zero();
+            for anchor in &anchors {
+                let d = anchor.pos - body.pos;
+                let d_sq = d.mag_sq();
+                if d_sq == 0.0 {
+                    continue;
+                }
+                let e_sq = match softening {
+                    SofteningMode::Global(_) => e_sq,
+                    SofteningMode::PerBodyRadius => (body.radius + anchor.radius).powi(2),
+                    SofteningMode::PerBodyMass(k) => {
+                        SofteningMode::mass_e_sq(k, body.mass.max(anchor.mass))
+                    }
+                };
+                field += d * (kind.source(anchor) / ((d_sq + e_sq) * d_sq.sqrt()));
+            }
+            field
+        };
+
+        if let SofteningMode::PerBodyRadius | SofteningMode::PerBodyMass(_) = self.softening {
+            // Largest radius and mass in each leaf, normally those of its only body
+            let mut leaf_radii = vec![0.0f32; self.quadtree.nodes.len()];
+            let mut leaf_masses = vec![0.0f32; self.quadtree.nodes.len()];
+            for (body, &leaf) in self.bodies.iter().zip(&self.quadtree.leaves) {
+                leaf_radii[leaf] = leaf_radii[leaf].max(body.radius);
+                leaf_masses[leaf] = leaf_masses[leaf].max(body.mass);
+            }
+            for (i, body) in self.bodies.iter_mut().enumerate() {
+                if skipped(&self.active, i) {
+                    continue;
+                }
+                let field = self.quadtree.acc_with(body.pos, |leaf| match softening {
+                    SofteningMode::PerBodyRadius => (body.radius + leaf_radii[leaf]).powi(2),
+                    SofteningMode::PerBodyMass(k) => {
+                        SofteningMode::mass_e_sq(k, body.mass.max(leaf_masses[leaf]))
+                    }
+                    SofteningMode::Global(_) => e_sq,
+                });
+                body.acc = kind.acc(body, field + anchor_field(body));
+            }
+            return;
+        }
+
+        for (i, body) in self.bodies.iter_mut().enumerate() {
+            if skipped(&self.active, i) {
+                continue;
+            }
+            let field = self.quadtree.acc(body.pos) + anchor_field(body);
+            body.acc = kind.acc(body, field);
+        }
+    }
+
+    /// What a body weighs in the tree, sinks are left out with
+    /// `direct_anchors` and summed exactly instead.
+    fn tree_source(&self) -> impl Fn(&Body) -> f32 {
+        let (kind, anchors) = (self.force_kind, self.direct_anchors);
+        move |body| {
+            if anchors && body.is_sink {
+                0.0
+            } else {
+                kind.source(body)
+            }
+        }
+    }
+
+    pub fn build(&mut self) {
+        let quad = self
+            .domain
+            .unwrap_or_else(|| Quad::new_containing(&self.bodies));
+        self.quadtree.clear(quad);
+
+        let source = self.tree_source();
+        for body in &self.bodies {
+            self.quadtree.insert(body.pos, source(body));
+        }
+
+        self.quadtree.propagate();
+        self.quadtree.relayout();
+        self.quadtree.index_bodies(&self.bodies);
+        debug_assert_eq!(self.quadtree.validate(), Ok(()));
+        self.refits = 0;
+    }
+
+    /// Reuses the previous tree topology if every body is still inside its
+    /// leaf and fewer than `refit_interval` refits have happened since the
+    /// last full build. Returns `false` if a rebuild is needed instead.
+    pub fn refit(&mut self) -> bool {
+        let leaves = &self.quadtree.leaves;
+        if self.refits >= self.refit_interval || leaves.len() != self.bodies.len() {
+            return false;
+        }
+
+        let nodes = &self.quadtree.nodes;
+        let moved = self
+            .bodies
+            .iter()
+            .zip(leaves)
+            .any(|(body, &leaf)| !nodes[leaf].quad.contains(body.pos));
+        if moved {
+            return false;
+        }
+
+        let source = self.tree_source();
+        self.quadtree.refit(&self.bodies, source);
+        self.refits += 1;
+        true
+    }
+
+    pub fn iterate(&mut self) {
+        match self.integrator {
+            Integrator::Verlet if self.block_timesteps.enabled => self.iterate_blocks(),
+            Integrator::Verlet if self.near_field_substeps > 1 && self.near_field_indexed() => {
+                self.iterate_near_field()
+            }
+            Integrator::Verlet => {
+                let dt = self.signed_dt();
+                for body in &mut self.bodies {
+                    if !body.fixed {
+                        body.update(dt);
+                    }
+                }
+            }
+            Integrator::Rk4 => self.rk4(),
+        }
+
+        if let Some(max_speed) = self.max_speed {
+            for body in &mut self.bodies {
+                let speed = body.vel.mag();
+                if speed > max_speed {
+                    body.vel *= max_speed / speed;
+                }
+            }
+        }
+    }
+
+    /// Advances by `dt` in `2^(bins - 1)` substeps. Every body drifts on each
+    /// substep, but is only kicked at the start of its own bin's step, with
+    /// the field evaluated for just the bodies due for a kick next.
+    fn iterate_blocks(&mut self) {
+        let BlockTimesteps { bins, eta, .. } = self.block_timesteps;
+        let levels = bins.clamp(1, BlockTimesteps::MAX_BINS) - 1;
+        let substeps = 1usize << levels;
+        let h = self.signed_dt() / substeps as f32;
+        let epsilon = self.quadtree.e_sq.sqrt();
+
+        // Substeps between the kicks of each body
+        let periods: Vec<usize> = self
+            .bodies
+            .iter()
+            .map(|body| {
+                let wanted = eta * (epsilon / body.acc.mag()).sqrt();
+                let mut bin = 0;
+                while bin < levels && self.dt / (1 << bin) as f32 > wanted {
+                    bin += 1;
+                }
+                substeps >> bin
+            })
+            .collect();
+
+        for substep in 0..substeps {
+            for (body, &period) in self.bodies.iter_mut().zip(&periods) {
+                if body.fixed {
+                    continue;
+                }
+                if substep % period == 0 {
+                    body.vel += body.acc * (h * period as f32);
+                }
+                body.pos += body.vel * h;
+            }
+
+            // The full step ends with the usual `attract` of every body
+            let next = substep + 1;
+            if next < substeps {
+                self.active = periods.iter().map(|&period| next % period == 0).collect();
+                if self.active.contains(&true) {
+                    self.attract();
+                }
+                self.active.clear();
+            }
+        }
+    }
+
+    /// Whether the tree of the last `attract` produced the accelerations and
+    /// still indexes every body, so the near field can be split off.
+    fn near_field_indexed(&self) -> bool {
+        self.gravity_enabled
+            && self.method == ForceMethod::BarnesHut
+            && self.decomposition == Decomposition::Quadtree
+            && self.quadtree.leaves.len() == self.bodies.len()
+    }
+
+    /// Advances by `dt` in `near_field_substeps` substeps. The part of each
+    /// acceleration coming from the leaves the tree summed directly is
+    /// recomputed from the bodies in them on every substep, the rest stays
+    /// as it was at the start. Per-body softening falls back to the global
+    /// length for the recomputed part.
+    fn iterate_near_field(&mut self) {
+        let substeps = self.near_field_substeps;
+        let h = self.signed_dt() / substeps as f32;
+        let (e_sq, min_d_sq) = (self.quadtree.e_sq, self.quadtree.min_d_sq);
+        let (source, kind) = (self.tree_source(), self.force_kind);
+
+        // Bodies in each body's near field, `partners[offsets[i]..offsets[i + 1]]`
+        let mut offsets = Vec::with_capacity(self.bodies.len() + 1);
+        let mut partners = Vec::new();
+        let mut leaves = Vec::new();
+        offsets.push(0);
+        for (i, body) in self.bodies.iter().enumerate() {
+            if !body.fixed {
+                leaves.clear();
+                self.quadtree.near_leaves(body.pos, &mut leaves);
+                for &leaf in &leaves {
+                    let bodies = self.quadtree.leaf_bodies(leaf);
+                    partners.extend(bodies.iter().filter(|&&j| j != i));
+                }
+            }
+            offsets.push(partners.len());
+        }
+
+        let near_field = |bodies: &[Body]| -> Vec<Vec2> {
+            bodies
+                .iter()
+                .enumerate()
+                .map(|(i, body)| {
+                    let mut field = Vec2::zero();
+                    for &j in &partners[offsets[i]..offsets[i + 1]] {
+                        let d = bodies[j].pos - body.pos;
+                        let d_sq = d.mag_sq();
+                        if d_sq == 0.0 {
+                            continue;
+                        }
+                        let r_sq = d_sq.max(min_d_sq);
+                        field += d * (source(&bodies[j]) / ((r_sq + e_sq) * r_sq.sqrt()));
+                    }
+                    kind.acc(body, field)
+                })
+                .collect()
+        };
+
+        let far: Vec<Vec2> = near_field(&self.bodies)
+            .iter()
+            .zip(&self.bodies)
+            .map(|(near, body)| body.acc - *near)
+            .collect();
+        for substep in 0..substeps {
+            // The first substep uses the accelerations as they are
+            let near = (substep > 0).then(|| near_field(&self.bodies));
+            for (i, body) in self.bodies.iter_mut().enumerate() {
+                if body.fixed {
+                    continue;
+                }
+                let acc = match &near {
+                    Some(near) => far[i] + near[i],
+                    None => body.acc,
+                };
+                body.vel += acc * h;
+                body.pos += body.vel * h;
+            }
+        }
+    }
+
+    /// Advances by `dt` from the accelerations of the last step, evaluating
+    /// the field at the three intermediate states.
+    fn rk4(&mut self) {
+        let dt = self.signed_dt();
+        let x0: Vec<Vec2> = self.bodies.iter().map(|body| body.pos).collect();
+        let v0: Vec<Vec2> = self.bodies.iter().map(|body| body.vel).collect();
+        let mut dx = v0.clone();
+        let mut dv: Vec<Vec2> = self.bodies.iter().map(|body| body.acc).collect();
 
-        let bodies: Vec<Body> = utils::uniform_disc(n);
-        let quadtree = Quadtree::new(theta, epsilon);
+        for (h, weight) in [(0.5, 2.0), (0.5, 2.0), (1.0, 1.0)] {
+            for (i, body) in self.bodies.iter_mut().enumerate() {
+                if !body.fixed {
+                    let (vel, acc) = (body.vel, body.acc);
+                    body.pos = x0[i] + vel * (dt * h);
+                    body.vel = v0[i] + acc * (dt * h);
+                }
+            }
+            self.attract();
+            for (i, body) in self.bodies.iter().enumerate() {
+                dx[i] += body.vel * weight;
+                dv[i] += body.acc * weight;
+            }
+        }
 
-        Self {
-            dt,
-            frame: 0,
-            bodies,
-            quadtree,
+        for (i, body) in self.bodies.iter_mut().enumerate() {
+            if !body.fixed {
+                body.pos = x0[i] + dx[i] * (dt / 6.0);
+                body.vel = v0[i] + dv[i] * (dt / 6.0);
+            }
         }
     }
 
-    pub fn step(&mut self) {
-        self.iterate();
-        self.collide();
-        self.attract();
-        self.frame += 1;
+    /// Removes the bodies outside `domain` under `DomainPolicy::Cull`.
+    /// Returns how many were removed and their total mass.
+    pub fn cull_outside_domain(&mut self) -> (usize, f32) {
+        let Some(domain) = self.domain else {
+            return (0, 0.0);
+        };
+        if self.domain_policy != DomainPolicy::Cull {
+            return (0, 0.0);
+        }
+
+        let (count, mut mass) = (self.bodies.len(), 0.0);
+        self.bodies.retain(|body| {
+            let inside = domain.contains(body.pos);
+            if !inside {
+                mass += body.mass;
+            }
+            inside
+        });
+        let culled = count - self.bodies.len();
+        if culled > 0 {
+            self.quadtree.leaves.clear();
+        }
+        (culled, mass)
     }
 
-    pub fn attract(&mut self) {
-        let quad = Quad::new_containing(&self.bodies);
-        self.quadtree.clear(quad);
+    /// Absorbs every non-sink body within `capture_radius` of a sink into
+    /// that sink, conserving mass and momentum. Returns how many were
+    /// absorbed.
+    pub fn accrete(&mut self) -> usize {
+        if self.capture_radius <= 0.0 {
+            return 0;
+        }
 
-        for body in &self.bodies {
-            self.quadtree.insert(body.pos, body.mass);
+        let sinks: Vec<usize> = (0..self.bodies.len())
+            .filter(|&i| self.bodies[i].is_sink)
+            .collect();
+        if sinks.is_empty() {
+            return 0;
         }
 
-        self.quadtree.propagate();
+        let r_sq = self.capture_radius * self.capture_radius;
+        let mut captured = vec![false; self.bodies.len()];
+        for j in 0..self.bodies.len() {
+            let body = self.bodies[j];
+            if body.is_sink {
+                continue;
+            }
 
-        for body in &mut self.bodies {
-            body.acc = self.quadtree.acc(body.pos);
+            for &i in &sinks {
+                let sink = &mut self.bodies[i];
+                if (body.pos - sink.pos).mag_sq() < r_sq {
+                    let mass = sink.mass + body.mass;
+                    if !sink.fixed {
+                        sink.vel = (sink.vel * sink.mass + body.vel * body.mass) / mass;
+                    }
+                    sink.mass = mass;
+                    self.accreted_mass += body.mass;
+                    captured[j] = true;
+                    break;
+                }
+            }
         }
-    }
 
-    pub fn iterate(&mut self) {
-        for body in &mut self.bodies {
-            body.update(self.dt);
+        let mut index = 0;
+        self.bodies.retain(|_| {
+            index += 1;
+            !captured[index - 1]
+        });
+        let count = captured.iter().filter(|&&captured| captured).count();
+        if count > 0 {
+            self.quadtree.leaves.clear();
         }
+        count
     }
 
-    pub fn collide(&mut self) {
+    /// Bounces overlapping bodies off each other with `restitution`, or
+    /// merges them if either is past `merge_threshold`. Returns how many
+    /// merged away.
+    pub fn collide(&mut self) -> usize {
+        if self.restitution.is_none() && self.merge_threshold.is_none() {
+            return 0;
+        }
         let mut rects = self
             .bodies
             .iter()
@@ -76,15 +1494,74 @@ impl Simulation {
 
         let mut broccoli = broccoli::Tree::new(&mut rects);
 
+        let mut merges = Vec::new();
         broccoli.find_colliding_pairs(|i, j| {
             let i = *i.unpack_inner();
             let j = *j.unpack_inner();
 
-            self.resolve(i, j);
+            let (b1, b2) = (&self.bodies[i], &self.bodies[j]);
+            let merging = self
+                .merge_threshold
+                .is_some_and(|threshold| threshold.admits(b1) || threshold.admits(b2));
+            if merging {
+                let r = b1.radius + b2.radius;
+                if (b2.pos - b1.pos).mag_sq() <= r * r {
+                    merges.push((i, j));
+                }
+            } else if let Some(restitution) = self.restitution {
+                self.resolve(i, j, restitution);
+            }
+        });
+        self.merge(&merges)
+    }
+
+    /// Merges each pair into its heavier body, conserving mass, momentum
+    /// and volume. A pinned body stays in place and pinned. Pairs with a
+    /// body already merged away are skipped, so clusters coalesce over a
+    /// few steps. Returns how many bodies were removed.
+    fn merge(&mut self, pairs: &[(usize, usize)]) -> usize {
+        if pairs.is_empty() {
+            return 0;
+        }
+
+        let mut merged = vec![false; self.bodies.len()];
+        for &(i, j) in pairs {
+            if merged[i] || merged[j] {
+                continue;
+            }
+            let (keep, gone) = if self.bodies[j].mass > self.bodies[i].mass {
+                (j, i)
+            } else {
+                (i, j)
+            };
+            let other = self.bodies[gone];
+            let body = &mut self.bodies[keep];
+            let mass = body.mass + other.mass;
+            if other.fixed && !body.fixed {
+                body.pos = other.pos;
+                body.vel = other.vel;
+                body.fixed = true;
+            } else if !body.fixed && mass > 0.0 {
+                body.pos = (body.pos * body.mass + other.pos * other.mass) / mass;
+                body.vel = (body.vel * body.mass + other.vel * other.mass) / mass;
+            }
+            body.mass = mass;
+            body.radius = (body.radius.powi(3) + other.radius.powi(3)).cbrt();
+            body.charge += other.charge;
+            body.is_sink |= other.is_sink;
+            merged[gone] = true;
+        }
+
+        let mut index = 0;
+        self.bodies.retain(|_| {
+            index += 1;
+            !merged[index - 1]
         });
+        self.quadtree.leaves.clear();
+        merged.iter().filter(|&&merged| merged).count()
     }
 
-    fn resolve(&mut self, i: usize, j: usize) {
+    fn resolve(&mut self, i: usize, j: usize, restitution: f32) {
         let b1 = &self.bodies[i];
         let b2 = &self.bodies[j];
 
@@ -111,8 +1588,13 @@ impl Simulation {
         let m1 = b1.mass;
         let m2 = b2.mass;
 
-        let weight1 = m2 / (m1 + m2);
-        let weight2 = m1 / (m1 + m2);
+        // Pinned bodies push others as if infinitely heavy
+        let (weight1, weight2) = match (b1.fixed, b2.fixed) {
+            (true, true) => return,
+            (true, false) => (0.0, 1.0),
+            (false, true) => (1.0, 0.0),
+            (false, false) => (m2 / (m1 + m2), m1 / (m1 + m2)),
+        };
 
         if d_dot_v >= 0.0 && d != Vec2::zero() {
             let tmp = d * (r / d.mag() - 1.0);
@@ -136,7 +1618,7 @@ impl Simulation {
         let d_dot_v = d.dot(v);
         let d_sq = d.mag_sq();
 
-        let tmp = d * (1.5 * d_dot_v / d_sq);
+        let tmp = d * ((1.0 + restitution) * d_dot_v / d_sq);
         let v1 = v1 + tmp * weight1;
         let v2 = v2 - tmp * weight2;
 
@@ -146,3 +1628,1291 @@ impl Simulation {
         self.bodies[j].pos += v2 * t;
     }
 }
+
+/// Whether body `i` keeps its acceleration in a partial `attract`.
+fn skipped(active: &[bool], i: usize) -> bool {
+    !active.is_empty() && !active[i]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quadtree::{MultipoleOrder, OpeningCriterion};
+    use crate::utils::random_bodies;
+
+    /// A simulation of just `bodies`, numbered in order, without collisions.
+    fn simulation(bodies: Vec<Body>) -> Simulation {
+        let settings = Settings {
+            body_count: 0,
+            restitution: None,
+            ..Default::default()
+        };
+        let mut simulation = Simulation::new(&settings);
+        let bodies = bodies
+            .into_iter()
+            .zip(0..)
+            .map(|(mut body, id)| {
+                body.id = id;
+                body
+            })
+            .collect();
+        simulation.reset(bodies);
+        simulation
+    }
+
+    fn accelerations(simulation: &Simulation) -> Vec<Vec2> {
+        simulation.bodies.iter().map(|body| body.acc).collect()
+    }
+
+    #[test]
+    fn refit_rebuilds_once_a_body_leaves_its_leaf() {
+        let mut simulation = simulation(random_bodies(100, 0));
+        simulation.refit_interval = 10;
+        simulation.attract();
+        let quadtree = &simulation.quadtree;
+        let (own, other) = (
+            quadtree.nodes[quadtree.leaves[0]].quad,
+            quadtree.nodes[quadtree.leaves[1]].quad,
+        );
+
+        // Halfway to the center of its leaf the body is still inside it
+        let body = &mut simulation.bodies[0];
+        body.pos += (own.center - body.pos) * 0.5;
+        assert!(simulation.refit());
+        let mass = |body: &Body| body.mass;
+        assert_eq!(simulation.quadtree.check(&simulation.bodies, mass), Ok(()));
+
+        simulation.bodies[0].pos = other.center;
+        assert!(!simulation.refit());
+        simulation.attract();
+        assert_eq!(simulation.quadtree.check(&simulation.bodies, mass), Ok(()));
+
+        let mut rebuilt = simulation.clone();
+        rebuilt.refit_interval = 0;
+        rebuilt.attract();
+        assert_eq!(accelerations(&simulation), accelerations(&rebuilt));
+    }
+
+    #[test]
+    fn like_charges_repel() {
+        let charged = |x: f32, charge: f32| {
+            let mut body = Body::new(Vec2::new(x, 0.0), Vec2::zero(), 1.0, 1.0);
+            body.charge = charge;
+            body
+        };
+        let mut simulation = simulation(vec![charged(-5.0, 1.0), charged(5.0, 1.0)]);
+        simulation.force_kind = ForceKind::Coulomb;
+        simulation.attract();
+
+        // q² / m · r / ((r² + ε²) r) with r = 10 and ε = 1
+        let expected = 1.0 / 101.0;
+        let (a, b) = (simulation.bodies[0].acc, simulation.bodies[1].acc);
+        assert!(a.x < 0.0 && b.x > 0.0, "{:?} and {:?} don't repel", a, b);
+        assert!((a.mag() - expected).abs() < 1e-6 && (b.mag() - expected).abs() < 1e-6);
+
+        simulation.bodies[1].charge = -1.0;
+        simulation.attract();
+        let (a, b) = (simulation.bodies[0].acc, simulation.bodies[1].acc);
+        assert!(a.x > 0.0 && b.x < 0.0, "{:?} and {:?} don't attract", a, b);
+    }
+
+    /// Sum of `|m a|` over all bodies, the scale net forces are compared to.
+    fn force_scale(simulation: &Simulation) -> f32 {
+        simulation
+            .bodies
+            .iter()
+            .map(|body| (body.acc * body.mass).mag())
+            .sum()
+    }
+
+    #[test]
+    fn direct_forces_cancel_in_total() {
+        for seed in 0..5 {
+            let mut simulation = simulation(random_bodies(200, seed));
+            simulation.method = ForceMethod::Direct;
+            simulation.attract();
+            let net = simulation.net_force().mag();
+            assert!(
+                net <= 1e-4 * force_scale(&simulation),
+                "seed {}: net force {}",
+                seed,
+                net
+            );
+        }
+    }
+
+    #[test]
+    fn pair_forces_are_equal_and_opposite() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        for _ in 0..100 {
+            let bodies = random_bodies(2, rng.u64(..));
+            let simulation = simulation(bodies);
+            let accs = simulation.direct_accelerations();
+            let (a, b) = (
+                accs[0] * simulation.bodies[0].mass,
+                accs[1] * simulation.bodies[1].mass,
+            );
+            assert!((a + b).mag() <= 1e-5 * a.mag(), "{:?} and {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn direct_accelerations_are_translation_invariant() {
+        let mut rng = fastrand::Rng::with_seed(2);
+        for seed in 0..5 {
+            let bodies = random_bodies(100, seed);
+            let offset = Vec2::new(rng.f32(), rng.f32()) * 1000.0 - Vec2::broadcast(500.0);
+            let shifted = bodies
+                .iter()
+                .map(|body| Body::new(body.pos + offset, body.vel, body.mass, body.radius))
+                .collect();
+
+            let before = simulation(bodies).direct_accelerations();
+            let after = simulation(shifted).direct_accelerations();
+            let scale = before.iter().map(|acc| acc.mag()).fold(0.0, f32::max);
+            for (a, b) in before.iter().zip(&after) {
+                assert!((*a - *b).mag() <= 1e-3 * scale, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn tree_forces_stay_close_to_direct_summation() {
+        for seed in 0..5 {
+            let mut simulation = simulation(random_bodies(300, seed));
+            simulation.method = ForceMethod::Direct;
+            simulation.attract();
+            let exact = accelerations(&simulation);
+
+            simulation.method = ForceMethod::BarnesHut;
+            simulation.quadtree.t_sq = 0.5 * 0.5;
+            simulation.attract();
+            let approx = accelerations(&simulation);
+
+            let errors: Vec<f32> = exact
+                .iter()
+                .zip(&approx)
+                .map(|(exact, approx)| (*approx - *exact).mag() / exact.mag())
+                .collect();
+            let mean = errors.iter().sum::<f32>() / errors.len() as f32;
+            assert!(mean < 0.02, "seed {}: mean error {}", seed, mean);
+
+            // The tree breaks Newton's third law, but only by about its error
+            let net = simulation.net_force().mag();
+            assert!(
+                net <= 0.02 * force_scale(&simulation),
+                "seed {}: net force {}",
+                seed,
+                net
+            );
+        }
+    }
+
+    #[test]
+    fn pinned_body_stays_put() {
+        for integrator in [Integrator::Verlet, Integrator::Rk4] {
+            let mut simulation = simulation(vec![
+                Body::new(Vec2::new(5.0, -3.0), Vec2::new(2.0, 1.0), 100.0, 1.0),
+                Body::new(Vec2::new(25.0, -3.0), Vec2::new(0.0, 1.0), 1.0, 1.0),
+                Body::new(Vec2::new(5.0, 17.0), Vec2::new(-1.0, 0.0), 1.0, 1.0),
+            ]);
+            simulation.integrator = integrator;
+            simulation.set_fixed(0, true);
+            simulation.attract();
+
+            let start = simulation.bodies()[0].pos;
+            let others: Vec<Vec2> = simulation.bodies()[1..].iter().map(|b| b.pos).collect();
+            for _ in 0..50 {
+                simulation.step();
+                assert_eq!(simulation.bodies()[0].pos, start);
+                assert_eq!(simulation.bodies()[0].vel, Vec2::zero());
+            }
+            // It still pulls the others in
+            for (body, before) in simulation.bodies()[1..].iter().zip(others) {
+                assert!((body.pos - start).mag() < (before - start).mag());
+            }
+        }
+    }
+
+    /// Largest deviation from the starting radius over one circular orbit of
+    /// a light body around a pinned mass of 1000 at distance 50.
+    fn orbit_radius_drift(integrator: Integrator) -> f32 {
+        let (mass, r) = (1000.0_f32, 50.0_f32);
+        let e_sq = 1.0;
+        let speed = (mass * r * r / (r * r + e_sq).powf(1.5)).sqrt();
+        let mut simulation = simulation(vec![
+            Body::new(Vec2::zero(), Vec2::zero(), mass, 1.0),
+            Body::new(Vec2::new(r, 0.0), Vec2::new(0.0, speed), 1e-3, 1.0),
+        ]);
+        simulation.set_fixed(0, true);
+        simulation.integrator = integrator;
+        simulation.attract();
+
+        let period = std::f32::consts::TAU * r / speed;
+        let steps = (period / simulation.dt) as usize;
+        let mut drift = 0.0_f32;
+        for _ in 0..steps {
+            simulation.step();
+            drift = drift.max((simulation.bodies()[1].pos.mag() - r).abs());
+        }
+        drift
+    }
+
+    #[test]
+    fn rk4_holds_a_circular_orbit_tighter_than_verlet() {
+        let verlet = orbit_radius_drift(Integrator::Verlet);
+        let rk4 = orbit_radius_drift(Integrator::Rk4);
+        assert!(
+            rk4 < 0.5 * verlet,
+            "rk4 drifts {} and verlet {}",
+            rk4,
+            verlet
+        );
+    }
+
+    #[test]
+    fn commands_apply_in_order() {
+        let mut simulation = simulation(vec![
+            Body::new(Vec2::new(-10.0, 0.0), Vec2::new(0.0, 1.0), 1.0, 1.0),
+            Body::new(Vec2::new(10.0, 0.0), Vec2::new(0.0, 2.0), 1.0, 1.0),
+        ]);
+        let spawned = Body::new(Vec2::new(0.0, 10.0), Vec2::new(0.0, 1.0), 1.0, 1.0);
+        for command in [
+            SimCommand::Spawn(spawned),
+            SimCommand::Despawn(0),
+            SimCommand::Kick(2, Vec2::new(1.0, 0.0)),
+            SimCommand::ReverseVelocities,
+            SimCommand::SetFixed(1, true),
+            SimCommand::Despawn(99),
+        ] {
+            simulation.apply(command);
+        }
+
+        let ids: Vec<u64> = simulation.bodies().iter().map(|body| body.id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert!(simulation.bodies()[0].fixed);
+        assert_eq!(simulation.bodies()[0].vel, Vec2::zero());
+        assert_eq!(simulation.bodies()[1].pos, spawned.pos);
+        assert_eq!(simulation.bodies()[1].vel, Vec2::new(-1.0, -1.0));
+
+        // A spawn before a reset is dropped, one after it is kept
+        let kept = Body::new(Vec2::new(5.0, 5.0), Vec2::zero(), 2.0, 1.0);
+        for command in [
+            SimCommand::Spawn(spawned),
+            SimCommand::Reset(vec![Body::new(Vec2::zero(), Vec2::zero(), 1.0, 1.0)]),
+            SimCommand::Spawn(kept),
+        ] {
+            simulation.apply(command);
+        }
+        let bodies = simulation.bodies();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!((bodies[1].id, bodies[1].pos), (1, kept.pos));
+    }
+
+    #[test]
+    fn resample_conserves_mass_and_momentum() {
+        let mut rng = fastrand::Rng::with_seed(3);
+        let bodies = random_bodies(200, 3)
+            .into_iter()
+            .map(|mut body| {
+                body.vel = Vec2::new(rng.f32(), rng.f32()) * 2.0 - Vec2::one();
+                body
+            })
+            .collect();
+        let mut simulation = simulation(bodies);
+        let (mass, momentum) = (simulation.total_mass(), simulation.total_momentum());
+
+        for target in [50, 400] {
+            simulation.resample(target);
+            assert_eq!(simulation.bodies().len(), target);
+            assert!((simulation.total_mass() - mass).abs() <= 1e-4 * mass);
+            assert!((simulation.total_momentum() - momentum).mag() <= 1e-3 * mass);
+        }
+
+        let mut ids: Vec<u64> = simulation.bodies().iter().map(|body| body.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 400);
+    }
+
+    #[test]
+    fn l4_point_is_stationary_in_the_rotating_frame() {
+        // A 1000 : 10 pair 100 apart, pinned where it sits in the rotating frame
+        let (m1, m2, distance) = (1000.0_f32, 10.0_f32, 100.0_f32);
+        let total = m1 + m2;
+        let omega = (total / distance.powi(3)).sqrt();
+        let x1 = -distance * m2 / total;
+        let l4 = Vec2::new(x1 + 0.5 * distance, distance * 3.0_f32.sqrt() * 0.5);
+
+        let mut simulation = simulation(vec![
+            Body::new(Vec2::new(x1, 0.0), Vec2::zero(), m1, 1.0),
+            Body::new(Vec2::new(x1 + distance, 0.0), Vec2::zero(), m2, 1.0),
+            Body::new(l4, Vec2::zero(), 1e-6, 0.1),
+        ]);
+        simulation.set_fixed(0, true);
+        simulation.set_fixed(1, true);
+        simulation.softening = SofteningMode::Global(0.0);
+        simulation.method = ForceMethod::Direct;
+
+        simulation.attract();
+        let gravity = simulation.bodies()[2].acc.mag();
+        simulation.rotating_frame = Some(omega);
+        simulation.attract();
+        let net = simulation.bodies()[2].acc.mag();
+        assert!(net < 1e-2 * gravity, "{} left of {}", net, gravity);
+
+        // And it stays close over a whole period
+        let steps = (std::f32::consts::TAU / omega / simulation.dt) as usize;
+        for _ in 0..steps {
+            simulation.step();
+        }
+        let drift = (simulation.bodies()[2].pos - l4).mag();
+        assert!(drift < 5.0, "drifted {} from L4", drift);
+    }
+
+    #[test]
+    fn radius_softening_keeps_overlapping_pairs_finite() {
+        let mut previous = f32::INFINITY;
+        for radius in [1.0_f32, 2.0, 4.0] {
+            // Unit masses a distance 1 apart, so they overlap for every radius
+            let expected = 1.0 / (1.0 + (2.0 * radius).powi(2));
+            for method in [ForceMethod::BarnesHut, ForceMethod::Direct] {
+                let mut simulation = simulation(vec![
+                    Body::new(Vec2::zero(), Vec2::zero(), 1.0, radius),
+                    Body::new(Vec2::new(1.0, 0.0), Vec2::zero(), 1.0, radius),
+                ]);
+                simulation.softening = SofteningMode::PerBodyRadius;
+                simulation.method = method;
+                simulation.attract();
+
+                let acc = simulation.bodies()[0].acc;
+                assert!(acc.x.is_finite() && acc.y == 0.0);
+                assert!(
+                    (acc.x - expected).abs() <= 1e-5 * expected,
+                    "{:?}: {}",
+                    method,
+                    acc.x
+                );
+            }
+            assert!(expected < previous);
+            previous = expected;
+        }
+    }
+
+    #[test]
+    fn direct_and_barnes_hut_agree_as_theta_vanishes() {
+        let mut simulation = simulation(random_bodies(300, 6));
+        simulation.method = ForceMethod::Direct;
+        simulation.attract();
+        let direct = accelerations(&simulation);
+
+        simulation.method = ForceMethod::BarnesHut;
+        simulation.quadtree.t_sq = 0.0;
+        simulation.attract();
+        for (tree, direct) in accelerations(&simulation).iter().zip(&direct) {
+            assert!(
+                (*tree - *direct).mag() <= 1e-4 * direct.mag(),
+                "{:?} != {:?}",
+                tree,
+                direct
+            );
+        }
+    }
+
+    #[test]
+    fn two_body_orbit_conserves_angular_momentum() {
+        // Equal masses 20 apart, somewhat slower than circular so the orbit is eccentric
+        let speed = 0.8 * (10.0_f32 / 401.0).sqrt();
+        let mut simulation = simulation(vec![
+            Body::new(Vec2::new(-10.0, 0.0), Vec2::new(0.0, -speed), 1.0, 1.0),
+            Body::new(Vec2::new(10.0, 0.0), Vec2::new(0.0, speed), 1.0, 1.0),
+        ]);
+        simulation.attract();
+
+        let start = simulation.total_angular_momentum();
+        assert!((start - 20.0 * speed).abs() <= 1e-5 * start);
+        for _ in 0..1000 {
+            simulation.step();
+            let drift = (simulation.total_angular_momentum() - start).abs();
+            assert!(
+                drift <= 1e-4 * start,
+                "drifted by {} at frame {}",
+                drift,
+                simulation.frame
+            );
+        }
+    }
+
+    #[test]
+    fn mass_softening_keeps_heavy_bodies_sharp() {
+        // Fraction of the unsoftened `m / d²` a light particle at distance 0.5 feels
+        let sharpness = |mass: f32, method: ForceMethod| {
+            let mut simulation = simulation(vec![
+                Body::new(Vec2::zero(), Vec2::zero(), mass, 1.0),
+                Body::new(Vec2::new(0.5, 0.0), Vec2::zero(), 1e-3, 0.1),
+            ]);
+            simulation.softening = SofteningMode::PerBodyMass(2.0);
+            simulation.method = method;
+            simulation.attract();
+            -simulation.bodies()[1].acc.x / (mass / 0.25)
+        };
+
+        for method in [ForceMethod::BarnesHut, ForceMethod::Direct] {
+            let heavy = sharpness(1000.0, method);
+            let light = sharpness(1.0, method);
+            assert!(heavy > 0.8, "{:?}: heavy body {}", method, heavy);
+            assert!(light < 0.1, "{:?}: light body {}", method, light);
+        }
+    }
+
+    #[test]
+    fn instability_flags_energy_jumps_and_non_finite_bodies() {
+        let mut simulation = simulation(random_bodies(10, 7));
+        simulation.attract();
+        assert_eq!(simulation.instability(-10.0, -9.5, 0.1), None);
+        assert_eq!(
+            simulation.instability(-10.0, -5.0, 0.1),
+            Some(Instability::EnergyJump(0.5))
+        );
+
+        simulation.bodies_mut()[4].vel.y = f32::NAN;
+        let id = simulation.bodies()[4].id;
+        // A non-finite body is reported even without an energy change
+        assert_eq!(
+            simulation.instability(-10.0, -10.0, 0.1),
+            Some(Instability::NonFinite(id))
+        );
+    }
+
+    #[test]
+    fn min_interaction_distance_clamps_close_pairs() {
+        for method in [ForceMethod::BarnesHut, ForceMethod::Direct] {
+            for (distance, expected) in [(0.1, 1.0 / 4.0), (5.0, 1.0 / 25.0)] {
+                let mut simulation = simulation(vec![
+                    Body::new(Vec2::zero(), Vec2::zero(), 1.0, 0.01),
+                    Body::new(Vec2::new(distance, 0.0), Vec2::zero(), 1.0, 0.01),
+                ]);
+                simulation.softening = SofteningMode::Global(0.0);
+                simulation.min_interaction_distance = 2.0;
+                simulation.method = method;
+                simulation.attract();
+
+                let acc = simulation.bodies()[0].acc.x;
+                assert!(
+                    (acc - expected).abs() <= 1e-5 * expected,
+                    "{:?} at {}: {}",
+                    method,
+                    distance,
+                    acc
+                );
+            }
+        }
+    }
+
+    /// Every body's id and the bits of its position, velocity and acceleration.
+    fn state(simulation: &Simulation) -> Vec<(u64, [u32; 6])> {
+        simulation
+            .bodies()
+            .iter()
+            .map(|body| {
+                let (pos, vel, acc) = (body.pos, body.vel, body.acc);
+                (
+                    body.id,
+                    [pos.x, pos.y, vel.x, vel.y, acc.x, acc.y].map(f32::to_bits),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rewind_restores_an_earlier_exact_state() {
+        let mut simulation = simulation(random_bodies(100, 8));
+        simulation.history = Rewind::new(3, 10);
+        simulation.attract();
+
+        let mut snapshots = Vec::new();
+        for _ in 0..25 {
+            simulation.step();
+            if simulation.frame % 10 == 0 {
+                snapshots.push((simulation.frame, simulation.time, state(&simulation)));
+            }
+        }
+
+        for (frame, time, bodies) in snapshots.iter().rev() {
+            assert!(simulation.rewind());
+            assert_eq!((simulation.frame, simulation.time), (*frame, *time));
+            assert_eq!(state(&simulation), *bodies);
+        }
+
+        // Running on from the restored state retraces the same steps
+        for _ in 0..10 {
+            simulation.step();
+        }
+        assert_eq!(state(&simulation), snapshots[1].2);
+    }
+
+    #[test]
+    fn plain_steps_conserve_mass_and_count() {
+        let mut simulation = simulation(random_bodies(200, 9));
+        simulation.attract();
+        let (count, mass) = (simulation.bodies().len(), simulation.total_mass());
+        for _ in 0..20 {
+            simulation.step();
+        }
+        assert_eq!(simulation.bodies().len(), count);
+        assert_eq!(simulation.total_mass(), mass);
+        #[cfg(debug_assertions)]
+        simulation.check_conservation((count, mass), 0, (0, 0.0));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "changed the total mass")]
+    fn conservation_check_catches_lost_mass() {
+        let mut simulation = simulation(random_bodies(20, 10));
+        let (count, mass) = (simulation.bodies().len(), simulation.total_mass());
+        simulation.bodies_mut()[3].mass *= 0.5;
+        simulation.check_conservation((count, mass), 0, (0, 0.0));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "bodies with 0 merged or accreted and 0 culled")]
+    fn conservation_check_catches_lost_bodies() {
+        let mut simulation = simulation(random_bodies(20, 10));
+        let (count, mass) = (simulation.bodies().len(), simulation.total_mass());
+        simulation.apply(SimCommand::Despawn(3));
+        simulation.check_conservation((count, mass), 0, (0, 0.0));
+    }
+
+    #[test]
+    fn added_batches_get_unique_ids_and_join_the_tree() {
+        let mut simulation = simulation(random_bodies(100, 11));
+        simulation.attract();
+        simulation.step();
+
+        let cluster: Vec<Body> = random_bodies(50, 12)
+            .into_iter()
+            .map(|mut body| {
+                body.pos = body.pos * 0.2 + Vec2::new(300.0, 0.0);
+                body.vel = Vec2::new(-5.0, 0.0);
+                body
+            })
+            .collect();
+        let ids = simulation.add_bodies(cluster);
+        assert_eq!(ids, 100..150);
+        assert_eq!(simulation.next_body_id(), 150);
+        assert_eq!(simulation.bodies().len(), 150);
+
+        let spawned = simulation.add_body(Body::new(Vec2::zero(), Vec2::zero(), 1.0, 1.0));
+        assert_eq!(spawned, 150);
+        let mut all: Vec<u64> = simulation.bodies().iter().map(|body| body.id).collect();
+        all.sort_unstable();
+        all.dedup();
+        assert_eq!(all.len(), simulation.bodies().len());
+
+        simulation.step();
+        let quadtree = simulation.quadtree();
+        assert_eq!(
+            quadtree.check(simulation.bodies(), |body| body.mass),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn anchored_sinks_pull_exactly_at_any_theta() {
+        let mut bodies = random_bodies(300, 13);
+        let mut sink = Body::new(Vec2::zero(), Vec2::zero(), 1e5, 5.0);
+        sink.is_sink = true;
+        bodies.push(sink);
+        let mut rng = fastrand::Rng::with_seed(13);
+        for _ in 0..10 {
+            let a = rng.f32() * std::f32::consts::TAU;
+            let pos = Vec2::new(a.cos(), a.sin()) * (3.0 + 2.0 * rng.f32());
+            bodies.push(Body::new(pos, Vec2::zero(), 1e-3, 0.1));
+        }
+        let mut simulation = simulation(bodies);
+        simulation.direct_anchors = true;
+        let exact = simulation.direct_accelerations();
+        let light: f32 = simulation
+            .bodies()
+            .iter()
+            .filter(|body| !body.is_sink)
+            .map(|body| body.mass)
+            .sum();
+
+        for theta in [0.3_f32, 1.0, 2.0] {
+            simulation.quadtree.t_sq = theta * theta;
+            simulation.attract();
+            assert!((simulation.quadtree().total_mass() - light).abs() <= 1e-4 * light);
+            for (body, exact) in simulation.bodies().iter().zip(&exact).skip(301) {
+                let error = (body.acc - *exact).mag() / exact.mag();
+                assert!(error < 1e-3, "theta {}: off by {}", theta, error);
+            }
+        }
+    }
+
+    #[test]
+    fn soft_wall_turns_bodies_around_smoothly() {
+        let mut simulation = simulation(vec![Body::new(
+            Vec2::new(40.0, 0.0),
+            Vec2::new(5.0, 0.0),
+            1.0,
+            1.0,
+        )]);
+        simulation.gravity_enabled = false;
+        simulation.soft_wall = Some(SoftWall {
+            half_size: 50.0,
+            stiffness: 1.0,
+        });
+        simulation.attract();
+
+        let (mut previous, mut deepest) = (5.0_f32, 0.0_f32);
+        for _ in 0..300 {
+            simulation.step();
+            let body = simulation.bodies()[0];
+            // Only ever slowed down, and never by a sudden jump
+            assert!(body.vel.x <= previous);
+            assert!(previous - body.vel.x < 0.5);
+            previous = body.vel.x;
+            deepest = deepest.max(body.pos.x - 50.0);
+        }
+
+        let body = simulation.bodies()[0];
+        assert!(body.pos.x < 50.0 && body.vel.x < 0.0);
+        // Spring energy turns back into the speed it came in with
+        assert!((body.vel.x + 5.0).abs() < 0.25, "left at {}", body.vel.x);
+        assert!(deepest > 4.0 && deepest < 5.5, "went {} deep", deepest);
+    }
+
+    #[test]
+    fn scaling_masses_scales_total_mass_and_radii() {
+        let mut bodies = random_bodies(100, 14);
+        // Sized like the central sink, not by its mass
+        bodies[0].radius = 25.0;
+        let mut simulation = simulation(bodies);
+        let before: Vec<(f32, f32)> = simulation
+            .bodies()
+            .iter()
+            .map(|b| (b.mass, b.radius))
+            .collect();
+        let total = simulation.total_mass();
+
+        simulation.scale_all_masses(8.0);
+        assert!((simulation.total_mass() - 8.0 * total).abs() <= 1e-5 * 8.0 * total);
+        for (body, (mass, radius)) in simulation.bodies().iter().zip(&before) {
+            assert_eq!(body.mass, 8.0 * mass);
+            // Radii become the cube root of the mass, as the generators make them
+            assert_eq!(body.radius, body.mass.cbrt());
+            if body.id != 0 {
+                assert!((body.radius - 2.0 * radius).abs() <= 1e-5 * radius);
+            }
+        }
+        assert!(simulation.bodies()[0].radius < before[0].1);
+    }
+
+    #[test]
+    fn step_hook_can_stop_a_run() {
+        let mut simulation =
+            simulation(vec![Body::new(Vec2::zero(), Vec2::new(2.0, 0.0), 1.0, 1.0)]);
+        simulation.gravity_enabled = false;
+
+        let mut steps = 0;
+        while !simulation.step_with(|simulation| {
+            assert_eq!(simulation.frame, steps + 1);
+            simulation.bodies()[0].pos.x > 10.0
+        }) {
+            steps += 1;
+            assert!(steps < 1000, "the body never got away");
+        }
+        // It moves 0.1 per step, up to round-off in the position
+        assert!((99..=101).contains(&steps), "took {} steps", steps);
+    }
+
+    #[test]
+    fn elastic_head_on_collision_swaps_velocities() {
+        let pair = |v1: f32, v2: f32, restitution: f32| {
+            let mut simulation = simulation(vec![
+                Body::new(Vec2::new(-0.9, 0.0), Vec2::new(v1, 0.0), 1.0, 1.0),
+                Body::new(Vec2::new(0.9, 0.0), Vec2::new(v2, 0.0), 1.0, 1.0),
+            ]);
+            simulation.restitution = Some(restitution);
+            simulation.collide();
+            let [a, b] = [0, 1].map(|i| simulation.bodies()[i]);
+            assert!((b.pos - a.pos).mag() >= 2.0 - 1e-4, "still overlapping");
+            (a.vel, b.vel)
+        };
+        let close = |a: Vec2, b: Vec2| (a - b).mag() <= 1e-5;
+
+        for (v1, v2) in [(1.0, -1.0), (3.0, -1.0), (2.0, 0.0)] {
+            let (a, b) = pair(v1, v2, 1.0);
+            assert!(
+                close(a, Vec2::new(v2, 0.0)) && close(b, Vec2::new(v1, 0.0)),
+                "{:?} {:?}",
+                a,
+                b
+            );
+        }
+        // Without restitution both move on with their common velocity
+        let (a, b) = pair(3.0, -1.0, 0.0);
+        assert!(close(a, Vec2::new(1.0, 0.0)) && close(b, Vec2::new(1.0, 0.0)));
+    }
+
+    /// Relative energy error after a light body swings closely past a pinned
+    /// mass of 100 and gets far away again, over the same 8 time units.
+    fn close_pass_energy_error(dt: f32, setup: impl Fn(&mut Simulation)) -> f32 {
+        let mut simulation = simulation(vec![
+            Body::new(Vec2::zero(), Vec2::zero(), 100.0, 0.1),
+            Body::new(Vec2::new(-20.0, 1.0), Vec2::new(5.0, 0.0), 1.0, 0.1),
+        ]);
+        simulation.set_fixed(0, true);
+        simulation.dt = dt;
+        setup(&mut simulation);
+        simulation.attract();
+
+        let start = simulation.total_energy();
+        for _ in 0..(8.0 / dt).round() as usize {
+            simulation.step();
+        }
+        ((simulation.total_energy() - start) / start).abs()
+    }
+
+    #[test]
+    fn block_timesteps_keep_close_passes_accurate() {
+        let coarse = close_pass_energy_error(0.05, |_| {});
+        let fine = close_pass_energy_error(0.05 / 32.0, |_| {});
+        let blocks = close_pass_energy_error(0.05, |simulation| {
+            simulation.block_timesteps = BlockTimesteps {
+                enabled: true,
+                bins: 6,
+                eta: 0.05,
+            };
+        });
+        assert!(
+            blocks < 0.5 * coarse,
+            "blocks {} against coarse {}",
+            blocks,
+            coarse
+        );
+        assert!(
+            blocks < 5.0 * fine + 1e-3,
+            "blocks {} against fine {}",
+            blocks,
+            fine
+        );
+    }
+
+    #[test]
+    fn kick_changes_only_the_kicked_velocity() {
+        let mut rng = fastrand::Rng::with_seed(17);
+        let bodies = random_bodies(20, 17)
+            .into_iter()
+            .map(|mut body| {
+                body.vel = Vec2::new(rng.f32(), rng.f32());
+                body
+            })
+            .collect();
+        let mut simulation = simulation(bodies);
+        simulation.set_fixed(5, true);
+        let before: Vec<Body> = simulation.bodies().to_vec();
+
+        let delta_v = Vec2::new(1.5, -0.25);
+        simulation.apply(SimCommand::Kick(3, delta_v));
+        simulation.apply(SimCommand::Kick(5, delta_v));
+        for (body, old) in simulation.bodies().iter().zip(&before) {
+            let expected = if body.id == 3 {
+                old.vel + delta_v
+            } else {
+                old.vel
+            };
+            assert_eq!(body.vel, expected, "body {}", body.id);
+            assert_eq!((body.pos, body.mass), (old.pos, old.mass));
+        }
+    }
+
+    #[test]
+    fn tight_pair_is_bound_and_escaper_is_not() {
+        let mut simulation = simulation(vec![
+            Body::new(Vec2::new(-1.0, 0.0), Vec2::new(0.0, -0.5), 10.0, 0.5),
+            Body::new(Vec2::new(1.0, 0.0), Vec2::new(0.0, 0.5), 10.0, 0.5),
+            Body::new(Vec2::new(200.0, 0.0), Vec2::new(50.0, 0.0), 1.0, 0.5),
+        ]);
+        simulation.attract();
+
+        let energies = simulation.binding_energies();
+        assert!(energies[0] < 0.0 && energies[1] < 0.0, "{:?}", energies);
+        assert!(energies[2] > 0.0);
+        assert_eq!(simulation.unbound_count(), 1);
+        let (most, least) = simulation.binding_extremes().unwrap();
+        assert!(most < 2);
+        assert_eq!(least, 2);
+    }
+
+    #[test]
+    fn emitters_add_bodies_at_their_rate() {
+        let emitter = |x: f32, rate: f32| Emitter {
+            pos: Vec2::new(x, 0.0),
+            rate,
+            velocity: Vec2::new(0.0, 3.0),
+            ..Default::default()
+        };
+        let run = |max_bodies: usize| {
+            let mut simulation = simulation(Vec::new());
+            simulation.gravity_enabled = false;
+            simulation.emitters = vec![emitter(-50.0, 10.0), emitter(50.0, 4.0)];
+            simulation.max_bodies = max_bodies;
+            // 5 time units in steps of 0.05
+            for _ in 0..100 {
+                simulation.step();
+            }
+            simulation
+        };
+
+        let simulation = run(usize::MAX);
+        let from = |x: f32| {
+            simulation
+                .bodies()
+                .iter()
+                .filter(|body| body.pos.x.signum() == x.signum())
+                .count()
+        };
+        assert!(
+            (49..=51).contains(&from(-1.0)),
+            "{} from the first",
+            from(-1.0)
+        );
+        assert!(
+            (19..=21).contains(&from(1.0)),
+            "{} from the second",
+            from(1.0)
+        );
+        for body in simulation.bodies() {
+            // Launched within `spread` of the emitter velocity
+            assert!((body.vel - Vec2::new(0.0, 3.0)).mag() <= 1.0 + 1e-5);
+        }
+
+        assert_eq!(run(30).bodies().len(), 30);
+    }
+
+    #[test]
+    fn major_axis_follows_the_elongation() {
+        use std::f32::consts::PI;
+
+        for angle in [0.0_f32, 0.5, 1.2, -0.8] {
+            let mut rng = fastrand::Rng::with_seed(19);
+            let (sin, cos) = angle.sin_cos();
+            let bodies = (0..2000)
+                .map(|_| {
+                    // A 100 by 20 rectangle rotated by `angle`
+                    let (x, y) = ((rng.f32() - 0.5) * 100.0, (rng.f32() - 0.5) * 20.0);
+                    let pos =
+                        Vec2::new(x * cos - y * sin, x * sin + y * cos) + Vec2::new(30.0, -10.0);
+                    Body::new(pos, Vec2::zero(), 1.0, 1.0)
+                })
+                .collect();
+            let simulation = simulation(bodies);
+
+            let shape = simulation.shape().unwrap();
+            let off = shape.angle - angle;
+            let off = off - PI * (off / PI).round();
+            assert!(
+                off.abs() < 0.05,
+                "major axis at {} for {}",
+                shape.angle,
+                angle
+            );
+            assert!(
+                (shape.axis_ratio() - 0.2).abs() < 0.03,
+                "axis ratio {}",
+                shape.axis_ratio()
+            );
+        }
+
+        let tensor = simulation(random_bodies(10, 20)).inertia_tensor();
+        assert_eq!(tensor[0][1], tensor[1][0]);
+    }
+
+    #[test]
+    fn running_backward_retraces_a_two_body_orbit() {
+        let speed = 0.8 * (10.0_f32 / 401.0).sqrt();
+        let start = [
+            Body::new(Vec2::new(-10.0, 0.0), Vec2::new(0.0, -speed), 1.0, 1.0),
+            Body::new(Vec2::new(10.0, 0.0), Vec2::new(0.0, speed), 1.0, 1.0),
+        ];
+        let mut simulation = simulation(start.to_vec());
+        simulation.attract();
+
+        for _ in 0..400 {
+            simulation.step();
+        }
+        let moved = (simulation.bodies()[0].pos - start[0].pos).mag();
+        assert!(moved > 1.0, "only moved {}", moved);
+
+        simulation.time_direction = -1.0;
+        for _ in 0..400 {
+            simulation.step();
+        }
+        assert!(simulation.time.abs() < 1e-3);
+        for (body, start) in simulation.bodies().iter().zip(&start) {
+            assert!(
+                (body.pos - start.pos).mag() < 0.05,
+                "ended at {:?}",
+                body.pos
+            );
+            assert!(
+                (body.vel - start.vel).mag() < 0.01 * speed.max(1.0),
+                "ended with {:?}",
+                body.vel
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_domain_handles_escapers_per_policy() {
+        let domain = Quad {
+            center: Vec2::zero(),
+            size: 300.0,
+        };
+        for policy in [DomainPolicy::Clamp, DomainPolicy::Cull] {
+            let mut simulation = simulation(random_bodies(100, 21));
+            simulation.domain = Some(domain);
+            simulation.domain_policy = policy;
+            simulation.bodies_mut()[0].pos = Vec2::new(400.0, 30.0);
+            simulation.attract();
+            simulation.step();
+
+            let quadtree = simulation.quadtree();
+            assert_eq!(quadtree.bounds(), Some(domain));
+            match policy {
+                DomainPolicy::Clamp => {
+                    assert_eq!(simulation.bodies().len(), 100);
+                    // The escaper sits in the edge leaf its clamped position falls in
+                    let clamped: Vec<Body> = simulation
+                        .bodies()
+                        .iter()
+                        .map(|body| Body {
+                            pos: domain.clamp(body.pos),
+                            ..*body
+                        })
+                        .collect();
+                    assert_eq!(quadtree.check(&clamped, |body| body.mass), Ok(()));
+                    assert!(!domain.contains(simulation.bodies()[0].pos));
+                }
+                DomainPolicy::Cull => {
+                    assert_eq!(simulation.bodies().len(), 99);
+                    assert!(simulation.bodies().iter().all(|body| body.id != 0));
+                    assert_eq!(
+                        quadtree.check(simulation.bodies(), |body| body.mass),
+                        Ok(())
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn new_takes_the_tree_settings() {
+        let settings = Settings {
+            body_count: 10,
+            opening_criterion: OpeningCriterion::ComCorrected,
+            multipole_order: MultipoleOrder::Quadrupole,
+            min_node_size: 2.0,
+            ..Default::default()
+        };
+        let quadtree = Simulation::new(&settings).quadtree;
+        assert_eq!(quadtree.opening_criterion, OpeningCriterion::ComCorrected);
+        assert_eq!(quadtree.multipole_order, MultipoleOrder::Quadrupole);
+        assert_eq!(quadtree.min_node_size, 2.0);
+    }
+
+    #[test]
+    fn pinned_com_stays_at_the_origin() {
+        let com = |simulation: &Simulation| {
+            let weighted = simulation
+                .bodies()
+                .iter()
+                .fold(Vec2::zero(), |sum, b| sum + b.pos * b.mass);
+            weighted / simulation.total_mass()
+        };
+        let run = |pin_com: bool| {
+            let mut rng = fastrand::Rng::with_seed(22);
+            let bodies = random_bodies(200, 22)
+                .into_iter()
+                .map(|mut body| {
+                    // A net drift of (0.5, 0) on top of random motion
+                    body.vel = Vec2::new(rng.f32() - 0.5 + 0.5, rng.f32() - 0.5);
+                    body
+                })
+                .collect();
+            let mut simulation = simulation(bodies);
+            simulation.pin_com = pin_com;
+            simulation.attract();
+            let start = com(&simulation);
+            for _ in 0..200 {
+                simulation.step();
+                if pin_com {
+                    assert!(
+                        com(&simulation).mag() < 1e-2,
+                        "com at {:?}",
+                        com(&simulation)
+                    );
+                }
+            }
+            (simulation, start)
+        };
+
+        let (pinned, _) = run(true);
+        assert!(pinned.total_momentum().mag() < 1e-3 * pinned.total_mass());
+        // Unpinned it drifts 0.5 a time unit for 10 time units
+        let (free, start) = run(false);
+        assert!((com(&free) - start).mag() > 4.0);
+    }
+
+    #[test]
+    fn cancelling_the_net_force_conserves_momentum() {
+        // Returns the momentum gained and the momentum scale, Σ m|v|
+        let run = |enforce: bool| {
+            let mut simulation = simulation(random_bodies(200, 5));
+            simulation.method = ForceMethod::BarnesHut;
+            simulation.enforce_momentum_conservation = enforce;
+            simulation.attract();
+            for _ in 0..200 {
+                simulation.step();
+            }
+            let scale: f32 = simulation
+                .bodies
+                .iter()
+                .map(|body| body.mass * body.vel.mag())
+                .sum();
+            (simulation.total_momentum().mag(), scale)
+        };
+
+        let (conserved, scale) = run(true);
+        assert!(conserved < 1e-5 * scale, "{conserved} of {scale}");
+        let (leaked, _) = run(false);
+        assert!(leaked > 10.0 * conserved, "{leaked} vs {conserved}");
+    }
+
+    #[test]
+    fn near_field_substeps_keep_close_passes_accurate() {
+        // Both bodies are in each other's near field, so substepping it
+        // matches a smaller global step without rebuilding the tree as often
+        let coarse = close_pass_energy_error(0.05, |_| {});
+        let fine = close_pass_energy_error(0.05 / 8.0, |_| {});
+        let substepped = close_pass_energy_error(0.05, |simulation| {
+            simulation.near_field_substeps = 8;
+        });
+        assert!(
+            substepped < 0.5 * coarse,
+            "substepped {} against coarse {}",
+            substepped,
+            coarse
+        );
+        assert!(
+            substepped < 5.0 * fine + 1e-3,
+            "substepped {} against fine {}",
+            substepped,
+            fine
+        );
+    }
+
+    #[test]
+    fn only_pairs_past_the_threshold_merge() {
+        let collide = |m1: f32, m2: f32| {
+            let mut simulation = simulation(vec![
+                Body::new(Vec2::new(-0.5, 0.0), Vec2::new(1.0, 0.0), m1, 1.0),
+                Body::new(Vec2::new(0.5, 0.0), Vec2::new(-2.0, 0.5), m2, 1.0),
+            ]);
+            simulation.merge_threshold = Some(MergeThreshold {
+                mass: 5.0,
+                radius: 10.0,
+            });
+            let momentum = simulation.total_momentum();
+            let merged = simulation.collide();
+            (merged, simulation, momentum)
+        };
+
+        let (merged, light, _) = collide(1.0, 2.0);
+        assert_eq!(merged, 0);
+        assert_eq!(light.bodies().len(), 2);
+
+        let (merged, heavy, momentum) = collide(10.0, 1.0);
+        assert_eq!(merged, 1);
+        assert_eq!(heavy.bodies().len(), 1);
+        assert_eq!(heavy.total_mass(), 11.0);
+        assert!((heavy.total_momentum() - momentum).mag() <= 1e-5);
+    }
+
+    #[test]
+    fn sinks_absorb_bodies_within_the_capture_radius() {
+        let mut sink = Body::new(Vec2::zero(), Vec2::new(0.5, 0.0), 10.0, 1.0);
+        sink.is_sink = true;
+        let mut simulation = simulation(vec![
+            sink,
+            Body::new(Vec2::new(1.5, 0.0), Vec2::new(0.0, 2.0), 1.0, 1.0),
+            Body::new(Vec2::new(5.0, 0.0), Vec2::new(0.0, -1.0), 1.0, 1.0),
+        ]);
+        simulation.capture_radius = 2.0;
+        let (mass, momentum) = (simulation.total_mass(), simulation.total_momentum());
+
+        assert_eq!(simulation.accrete(), 1);
+        let ids: Vec<u64> = simulation.bodies().iter().map(|body| body.id).collect();
+        assert_eq!(ids, [0, 2]);
+        assert_eq!(simulation.bodies()[0].mass, 11.0);
+        assert_eq!(simulation.total_mass(), mass);
+        assert!((simulation.total_momentum() - momentum).mag() <= 1e-5);
+        assert_eq!(simulation.accreted_mass, 1.0);
+    }
+
+    #[test]
+    fn stepping_a_clone_leaves_the_original_alone() {
+        let mut original = simulation(random_bodies(50, 50));
+        original.attract();
+        let before = state(&original);
+
+        let mut fork = original.clone();
+        for _ in 0..10 {
+            fork.step();
+        }
+        assert_ne!(state(&fork), before);
+        assert_eq!(state(&original), before);
+        assert_eq!(original.frame, 0);
+
+        // And the copy was exact, so the original catches up with it
+        for _ in 0..10 {
+            original.step();
+        }
+        assert_eq!(state(&original), state(&fork));
+    }
+
+    #[test]
+    fn adaptive_dt_shrinks_for_a_close_heavy_pair() {
+        let mut simulation = simulation(random_bodies(50, 17));
+        simulation.adaptive_timestep.enabled = true;
+        let AdaptiveTimestep { dt_min, dt_max, .. } = simulation.adaptive_timestep;
+        simulation.attract();
+        let quiet = simulation.adaptive_dt();
+
+        simulation.add_body(Body::new(Vec2::new(-0.5, 0.0), Vec2::zero(), 100.0, 0.1));
+        simulation.add_body(Body::new(Vec2::new(0.5, 0.0), Vec2::zero(), 100.0, 0.1));
+        simulation.attract();
+        let close = simulation.adaptive_dt();
+
+        assert!(close < quiet, "{} against {}", close, quiet);
+        for dt in [quiet, close] {
+            assert!((dt_min..=dt_max).contains(&dt), "{}", dt);
+        }
+    }
+
+    #[test]
+    fn max_speed_caps_fast_bodies_in_their_direction() {
+        let mut simulation = simulation(vec![
+            Body::new(Vec2::zero(), Vec2::new(3000.0, -4000.0), 1.0, 1.0),
+            Body::new(Vec2::new(50.0, 0.0), Vec2::new(0.0, 1.0), 1.0, 1.0),
+        ]);
+        simulation.gravity_enabled = false;
+        simulation.max_speed = Some(10.0);
+        simulation.attract();
+        simulation.iterate();
+
+        let [fast, slow] = [0, 1].map(|i| simulation.bodies()[i].vel);
+        assert!((fast.mag() - 10.0).abs() <= 1e-4, "{:?}", fast);
+        assert!((fast.normalized() - Vec2::new(0.6, -0.8)).mag() <= 1e-5);
+        assert_eq!(slow, Vec2::new(0.0, 1.0));
+    }
+
+    #[test]
+    fn no_acceleration_with_gravity_off() {
+        let mut simulation = simulation(vec![
+            Body::new(Vec2::zero(), Vec2::zero(), 10.0, 1.0),
+            Body::new(Vec2::new(2.0, 0.0), Vec2::zero(), 10.0, 1.0),
+        ]);
+        simulation.attract();
+        assert!(accelerations(&simulation).iter().all(|acc| acc.mag() > 0.0));
+
+        // Also clears what the last attract left behind
+        simulation.gravity_enabled = false;
+        simulation.attract();
+        assert_eq!(accelerations(&simulation), [Vec2::zero(); 2]);
+    }
+
+    #[test]
+    fn circular_binary_is_virialized() {
+        // Equal masses a distance d apart orbit at v² = m / 2d, where
+        // 2T = m² / d = |U|
+        let (m, d) = (3.0f32, 10.0f32);
+        let v = (m / (2.0 * d)).sqrt();
+        let binary = |speed: f32| {
+            let mut simulation = simulation(vec![
+                Body::new(Vec2::new(-0.5 * d, 0.0), Vec2::new(0.0, -speed), m, 0.1),
+                Body::new(Vec2::new(0.5 * d, 0.0), Vec2::new(0.0, speed), m, 0.1),
+            ]);
+            simulation.softening = SofteningMode::Global(0.0);
+            simulation.attract();
+            simulation.virial_ratio()
+        };
+
+        let ratio = binary(v);
+        assert!((ratio - 1.0).abs() <= 1e-4, "ratio {}", ratio);
+        let ratio = binary(2.0 * v);
+        assert!((ratio - 4.0).abs() <= 1e-3, "ratio {}", ratio);
+    }
+
+    #[test]
+    fn population_mixing_spans_segregated_to_overlapped() {
+        let tagged = |bodies: Vec<Body>, population: u8, shift: f32| -> Vec<Body> {
+            bodies
+                .into_iter()
+                .map(|mut body| {
+                    body.pos.x = 0.2 * body.pos.x + shift;
+                    body.population = population;
+                    body
+                })
+                .collect()
+        };
+
+        // Far enough apart that no grid cell holds both
+        let mut bodies = tagged(random_bodies(100, 73), 0, -80.0);
+        bodies.extend(tagged(random_bodies(100, 74), 1, 80.0));
+        assert_eq!(simulation(bodies).population_mixing(), 0.0);
+
+        // Each cell holds as much of one as of the other
+        let mut bodies = tagged(random_bodies(100, 73), 0, 0.0);
+        bodies.extend(tagged(random_bodies(100, 73), 1, 0.0));
+        let mixing = simulation(bodies).population_mixing();
+        assert!((mixing - 1.0).abs() <= 1e-5, "mixing {}", mixing);
+    }
+
+    #[test]
+    fn clearing_then_stepping_does_not_panic() {
+        let mut simulation = simulation(random_bodies(50, 58));
+        simulation.restitution = Some(0.5);
+        simulation.merge_threshold = Some(MergeThreshold {
+            mass: 1.2,
+            radius: 10.0,
+        });
+        simulation.capture_radius = 2.0;
+        simulation.attract();
+        simulation.step();
+
+        simulation.apply(SimCommand::Reset(Vec::new()));
+        simulation.attract();
+        for _ in 0..5 {
+            simulation.step();
+        }
+        assert!(simulation.bodies().is_empty());
+        assert_eq!(simulation.total_energy(), 0.0);
+        assert_eq!(simulation.population_mixing(), 0.0);
+        assert!(simulation.shape().is_none());
+
+        // And the empty scene can be built up again
+        simulation.apply(SimCommand::Spawn(Body::new(
+            Vec2::zero(),
+            Vec2::new(1.0, 0.0),
+            1.0,
+            1.0,
+        )));
+        simulation.step();
+        assert_eq!(simulation.bodies().len(), 1);
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+const GRAVITATIONAL_CONSTANT: f64 = 6.674_30e-11;
+const SOLAR_MASS_KG: f64 = 1.988_47e30;
+const YEAR_S: f64 = 365.25 * 86400.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LengthUnit {
+    Parsec,
+    Au,
+    Meter,
+}
+
+impl LengthUnit {
+    pub fn meters(self) -> f64 {
+        match self {
+            LengthUnit::Parsec => 3.085_677_581e16,
+            LengthUnit::Au => 1.495_978_707e11,
+            LengthUnit::Meter => 1.0,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            LengthUnit::Parsec => "pc",
+            LengthUnit::Au => "AU",
+            LengthUnit::Meter => "m",
+        }
+    }
+}
+
+/// Physical size of the simulation units, only used to display values. The
+/// physics keeps G = 1, which fixes the time unit from the other two.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Units {
+    pub length_unit: LengthUnit,
+    /// `length_unit`s per simulation length.
+    pub length: f64,
+    /// Solar masses per simulation mass.
+    pub mass: f64,
+}
+
+impl Units {
+    /// Years per simulation time, `sqrt(L³ / (G M))`.
+    pub fn time_scale(&self) -> f64 {
+        let length = self.length * self.length_unit.meters();
+        let mass = self.mass * SOLAR_MASS_KG;
+        (length.powi(3) / (GRAVITATIONAL_CONSTANT * mass)).sqrt() / YEAR_S
+    }
+
+    pub fn length(&self, length: f32) -> f64 {
+        length as f64 * self.length
+    }
+
+    pub fn mass(&self, mass: f32) -> f64 {
+        mass as f64 * self.mass
+    }
+
+    /// Simulation time in years.
+    pub fn time(&self, time: f32) -> f64 {
+        time as f64 * self.time_scale()
+    }
+
+    pub fn from_length(&self, length: f64) -> f32 {
+        (length / self.length) as f32
+    }
+
+    pub fn from_mass(&self, mass: f64) -> f32 {
+        (mass / self.mass) as f32
+    }
+
+    pub fn from_time(&self, years: f64) -> f32 {
+        (years / self.time_scale()) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn earth_orbit_takes_a_year() {
+        let units = Units {
+            length_unit: LengthUnit::Au,
+            length: 1.0,
+            mass: 1.0,
+        };
+        // A circular orbit at radius 1 around mass 1 has period 2π with G = 1
+        let year = units.time(std::f32::consts::TAU);
+        assert!((year - 1.0).abs() < 1e-3, "the orbit takes {} years", year);
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let units = Units {
+            length_unit: LengthUnit::Parsec,
+            length: 0.01,
+            mass: 4e6,
+        };
+        let close = |a: f32, b: f32| (a - b).abs() <= 1e-6 * b.abs();
+        for value in [0.5_f32, 3.0, 1234.5] {
+            assert!(close(units.from_length(units.length(value)), value));
+            assert!(close(units.from_mass(units.mass(value)), value));
+            assert!(close(units.from_time(units.time(value)), value));
+        }
+        assert_eq!(units.length(2.0), 0.02);
+    }
+}
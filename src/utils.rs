@@ -1,15 +1,153 @@
+use std::{fs, io, path::Path};
+
 use crate::body::Body;
+use serde::{Deserialize, Serialize};
 use ultraviolet::Vec2;
 
-pub fn uniform_disc(n: usize) -> Vec<Body> {
-    fastrand::seed(0);
+/// Options of `uniform_disc` beyond the body count and seed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscOptions {
+    /// Whether the central mass is pinned in place.
+    pub pin_center: bool,
+    pub rotation: Rotation,
+    /// Random velocity added to each body as a fraction of its circular
+    /// speed, hotter discs spread out instead of staying thin.
+    pub dispersion: f32,
+    /// Largest random offset added to each position, see `jitter`.
+    pub jitter: f32,
+    pub warm_start: WarmStart,
+}
+
+impl Default for DiscOptions {
+    fn default() -> Self {
+        Self {
+            pin_center: true,
+            rotation: Rotation::Clockwise,
+            dispersion: 0.0,
+            jitter: 0.0,
+            warm_start: WarmStart::default(),
+        }
+    }
+}
+
+/// Where a generator's central mass starts, at rest at the origin by
+/// default. Off the origin it's velocity-scaled like every other body
+/// before being given `velocity`, so the disc no longer starts exactly
+/// symmetric about it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WarmStart {
+    pub offset: Vec2,
+    pub velocity: Vec2,
+}
+
+impl WarmStart {
+    /// Gives the central mass, the only sink a generator places, its
+    /// starting velocity once the rest have been scaled. A pinned one stays
+    /// at rest.
+    fn apply(&self, bodies: &mut [Body]) {
+        if let Some(center) = bodies.iter_mut().find(|body| body.is_sink) {
+            if !center.fixed {
+                center.vel = self.velocity;
+            }
+        }
+    }
+}
+
+/// Sense a disc orbits in, as seen on screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rotation {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Minimum, mean and median of a set of values.
+#[derive(Clone, Copy, Debug)]
+pub struct Summary {
+    pub min: f32,
+    pub mean: f32,
+    pub median: f32,
+}
+
+impl Summary {
+    /// Sorts `values` in place, returns `None` if there are none.
+    pub fn new(values: &mut [f32]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+
+        values.sort_by(f32::total_cmp);
+        let n = values.len();
+        let median = if n % 2 == 0 {
+            (values[n / 2 - 1] + values[n / 2]) * 0.5
+        } else {
+            values[n / 2]
+        };
+
+        Some(Self {
+            min: values[0],
+            mean: values.iter().sum::<f32>() / n as f32,
+            median,
+        })
+    }
+}
+
+/// Running sum of force contributions, optionally accumulated in `f64` to
+/// cut the round-off of adding many small terms to a large one, as near a
+/// heavy mass. Only the sum is wider, each term is still computed in `f32`.
+#[derive(Clone, Copy, Debug)]
+pub struct FieldSum {
+    precise: bool,
+    sum: Vec2,
+    x: f64,
+    y: f64,
+}
+
+impl FieldSum {
+    pub fn new(precise: bool) -> Self {
+        Self {
+            precise,
+            sum: Vec2::zero(),
+            x: 0.0,
+            y: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, term: Vec2) {
+        if self.precise {
+            self.x += term.x as f64;
+            self.y += term.y as f64;
+        } else {
+            self.sum += term;
+        }
+    }
+
+    pub fn total(&self) -> Vec2 {
+        if self.precise {
+            Vec2::new(self.x as f32, self.y as f32)
+        } else {
+            self.sum
+        }
+    }
+}
+
+pub fn uniform_disc(n: usize, seed: u64, options: &DiscOptions) -> Vec<Body> {
+    fastrand::seed(seed);
     let inner_radius = 25.0;
     let outer_radius = (n as f32).sqrt() * 5.0;
 
     let mut bodies: Vec<Body> = Vec::with_capacity(n);
 
     let m = 1e6;
-    let center = Body::new(Vec2::zero(), Vec2::zero(), m as f32, inner_radius);
+    let mut center = Body::new(
+        options.warm_start.offset,
+        Vec2::zero(),
+        m as f32,
+        inner_radius,
+    );
+    center.is_sink = true;
+    center.fixed = options.pin_center;
     bodies.push(center);
 
     while bodies.len() < n {
@@ -35,7 +173,695 @@ pub fn uniform_disc(n: usize) -> Vec<Body> {
 
         let v = (mass / bodies[i].pos.mag()).sqrt();
         bodies[i].vel *= v;
+        if options.rotation == Rotation::CounterClockwise {
+            bodies[i].vel = -bodies[i].vel;
+        }
+        if options.dispersion > 0.0 {
+            let kick = Vec2::new(fastrand::f32(), fastrand::f32()) * 2.0 - Vec2::one();
+            bodies[i].vel += kick * v * options.dispersion;
+        }
+    }
+
+    options.warm_start.apply(&mut bodies);
+    jitter(&mut bodies, options.jitter, seed);
+    recenter_and_zero_momentum(&mut bodies);
+    for (i, body) in bodies.iter_mut().enumerate() {
+        body.id = i as u64;
+    }
+
+    bodies
+}
+
+/// A clockwise disc like `uniform_disc` squashed along y to `axis_ratio`
+/// times its extent along x, for studying bars. Positions and velocities
+/// on circular orbits are squashed alike, so each body starts on an
+/// ellipse with the period of its circular orbit, which is close to but
+/// not exactly an equilibrium. With `central_mass` the first body is a
+/// sink of that mass placed by `warm_start`, whose offset is squashed
+/// along with the rest.
+pub fn elliptical_disc(
+    n: usize,
+    axis_ratio: f32,
+    seed: u64,
+    central_mass: Option<f32>,
+    warm_start: &WarmStart,
+) -> Vec<Body> {
+    fastrand::seed(seed);
+    let inner_radius = if central_mass.is_some() { 25.0 } else { 0.0 };
+    let outer_radius = (n as f32).sqrt() * 5.0;
+
+    let mut bodies: Vec<Body> = Vec::with_capacity(n);
+
+    if let Some(mass) = central_mass {
+        let mut center = Body::new(warm_start.offset, Vec2::zero(), mass, inner_radius);
+        center.is_sink = true;
+        bodies.push(center);
+    }
+
+    while bodies.len() < n {
+        let a = fastrand::f32() * std::f32::consts::TAU;
+        let (sin, cos) = a.sin_cos();
+        let t = inner_radius / outer_radius;
+        let r = fastrand::f32() * (1.0 - t * t) + t * t;
+        let pos = Vec2::new(cos, sin) * outer_radius * r.sqrt();
+        let vel = Vec2::new(sin, -cos);
+        let mass = 1.0f32;
+
+        bodies.push(Body::new(pos, vel, mass, mass.cbrt()));
+    }
+
+    bodies.sort_by(|a, b| a.pos.mag_sq().total_cmp(&b.pos.mag_sq()));
+    let mut mass = 0.0;
+    for body in &mut bodies {
+        mass += body.mass;
+        if body.pos == Vec2::zero() {
+            continue;
+        }
+        body.vel *= (mass / body.pos.mag()).sqrt();
+        body.pos.y *= axis_ratio;
+        body.vel.y *= axis_ratio;
+    }
+
+    warm_start.apply(&mut bodies);
+    recenter_and_zero_momentum(&mut bodies);
+    for (i, body) in bodies.iter_mut().enumerate() {
+        body.id = i as u64;
+    }
+
+    bodies
+}
+
+/// Moves every free body by a random offset of up to `amplitude`, drawn
+/// uniformly from a disc and reproducible for `seed`. Breaks the exact
+/// symmetries a generator can produce, which would otherwise make e.g. a
+/// cold collapse pile every body onto the origin at once.
+pub fn jitter(bodies: &mut [Body], amplitude: f32, seed: u64) {
+    if amplitude <= 0.0 {
+        return;
+    }
+
+    let mut rng = fastrand::Rng::with_seed(seed);
+    for body in bodies.iter_mut().filter(|body| !body.fixed) {
+        let (sin, cos) = (rng.f32() * std::f32::consts::TAU).sin_cos();
+        body.pos += Vec2::new(cos, sin) * (amplitude * rng.f32().sqrt());
+    }
+}
+
+/// Bodies scattered uniformly over the square `[-half_size, half_size]²`
+/// at rest, which collapses under its own gravity. With `central_mass`
+/// the first body is a sink of that mass at the origin.
+pub fn uniform_box(n: usize, half_size: f32, seed: u64, central_mass: Option<f32>) -> Vec<Body> {
+    fastrand::seed(seed);
+
+    let mut bodies: Vec<Body> = Vec::with_capacity(n);
+
+    if let Some(mass) = central_mass {
+        let mut center = Body::new(Vec2::zero(), Vec2::zero(), mass, mass.cbrt());
+        center.is_sink = true;
+        bodies.push(center);
     }
 
+    while bodies.len() < n {
+        let x = (fastrand::f32() * 2.0 - 1.0) * half_size;
+        let y = (fastrand::f32() * 2.0 - 1.0) * half_size;
+        let mass = 1.0f32;
+        let radius = mass.cbrt();
+
+        bodies.push(Body::new(Vec2::new(x, y), Vec2::zero(), mass, radius));
+    }
+
+    recenter_and_zero_momentum(&mut bodies);
+    for (i, body) in bodies.iter_mut().enumerate() {
+        body.id = i as u64;
+    }
+
+    bodies
+}
+
+/// `n` bodies at rest scattered over `[-100, 100]²`, reproducible for
+/// `seed`, the fixture the tests share.
+#[cfg(test)]
+pub(crate) fn random_bodies(n: usize, seed: u64) -> Vec<Body> {
+    let mut rng = fastrand::Rng::with_seed(seed);
+    (0..n)
+        .map(|_| {
+            let pos = Vec2::new(rng.f32(), rng.f32()) * 200.0 - Vec2::broadcast(100.0);
+            let mass = 0.5 + rng.f32();
+            Body::new(pos, Vec2::zero(), mass, mass.cbrt())
+        })
+        .collect()
+}
+
+/// Reorders `bodies` randomly but reproducibly for `seed`, to compare
+/// against the generated or a spatially sorted order at the same physics.
+pub fn shuffle_bodies(bodies: &mut [Body], seed: u64) {
+    fastrand::seed(seed);
+    fastrand::shuffle(bodies);
+}
+
+/// Exact softened gravitational field of `bodies` at `pos`, the O(n)
+/// reference the tree approximates. Bodies exactly at `pos` are skipped.
+pub fn direct_field(bodies: &[Body], pos: Vec2, e_sq: f32) -> Vec2 {
     bodies
+        .iter()
+        .filter(|body| body.pos != pos)
+        .fold(Vec2::zero(), |field, body| {
+            let d = body.pos - pos;
+            let d_sq = d.mag_sq();
+            field + d * (body.mass / ((d_sq + e_sq) * d_sq.sqrt()))
+        })
+}
+
+/// Moves the center of mass to the origin and removes the net momentum.
+/// Pinned bodies stay at rest, the momentum is taken out of the others.
+pub fn recenter_and_zero_momentum(bodies: &mut [Body]) {
+    let mut mass = 0.0;
+    let mut pos = Vec2::zero();
+    let mut free_mass = 0.0;
+    let mut momentum = Vec2::zero();
+    for body in bodies.iter() {
+        mass += body.mass;
+        pos += body.pos * body.mass;
+        if !body.fixed {
+            free_mass += body.mass;
+            momentum += body.vel * body.mass;
+        }
+    }
+
+    let com = if mass != 0.0 {
+        pos / mass
+    } else {
+        Vec2::zero()
+    };
+    let vel = if free_mass != 0.0 {
+        momentum / free_mass
+    } else {
+        Vec2::zero()
+    };
+    for body in bodies.iter_mut() {
+        body.pos -= com;
+        if !body.fixed {
+            body.vel -= vel;
+        }
+    }
+}
+
+/// Replaces the velocities with a Maxwell-Boltzmann distribution at
+/// `temperature` in units where Boltzmann's constant is 1, so each
+/// component is Gaussian with standard deviation `sqrt(T / m)`. Pinned
+/// bodies stay at rest. Also recenters and removes the net momentum.
+pub fn thermalize(bodies: &mut [Body], temperature: f32, seed: u64) {
+    fastrand::seed(seed);
+    for body in bodies.iter_mut().filter(|body| !body.fixed) {
+        // Box-Muller gives two independent standard normals
+        let u = 1.0 - fastrand::f32();
+        let a = fastrand::f32() * std::f32::consts::TAU;
+        let (sin, cos) = a.sin_cos();
+        let normal = Vec2::new(cos, sin) * (-2.0 * u.ln()).sqrt();
+        body.vel = normal * (temperature / body.mass).sqrt();
+    }
+
+    recenter_and_zero_momentum(bodies);
+}
+
+/// Kinetic temperature `Σ m v² / 2N` of the free bodies, with two degrees
+/// of freedom each. The inverse of `thermalize` up to sampling noise.
+pub fn temperature(bodies: &[Body]) -> f32 {
+    let (sum, n) = bodies
+        .iter()
+        .filter(|body| !body.fixed)
+        .fold((0.0, 0), |(sum, n), body| {
+            (sum + body.mass * body.vel.mag_sq(), n + 1)
+        });
+    if n == 0 {
+        return 0.0;
+    }
+    sum / (2 * n) as f32
+}
+
+/// Floats per gas, dark matter and star particle of a Tipsy file.
+const TIPSY_FLOATS: [usize; 3] = [12, 9, 11];
+
+/// Reads the particles of a Tipsy snapshot: gas, then dark matter, then
+/// stars, each starting with its mass, position and velocity. The 3D
+/// positions and velocities are projected onto the xy plane by dropping z,
+/// the masses are kept and radii follow as `mass.cbrt()` like the
+/// generated bodies. Both the big endian standard files and native little
+/// endian ones are accepted, with or without the 4 padding bytes after the
+/// 28 byte header. Files that don't match their header are an error.
+pub fn load_tipsy(path: impl AsRef<Path>) -> io::Result<Vec<Body>> {
+    let data = fs::read(path)?;
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let word = |offset: usize, big: bool| -> Option<[u8; 4]> {
+        let mut bytes: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+        if big {
+            bytes.reverse();
+        }
+        Some(bytes)
+    };
+    let count = |offset, big| word(offset, big).map(|bytes| u32::from_le_bytes(bytes) as usize);
+
+    // The header is a double time, then the total, dimension, gas, dark and
+    // star counts, whose sum and dimension tell the byte order
+    let big = [true, false]
+        .into_iter()
+        .find(|&big| {
+            let counts: Option<Vec<usize>> = (0..5).map(|i| count(8 + 4 * i, big)).collect();
+            counts.is_some_and(|c| {
+                let total = c[2].checked_add(c[3]).and_then(|n| n.checked_add(c[4]));
+                (1..=3).contains(&c[1]) && total == Some(c[0])
+            })
+        })
+        .ok_or_else(|| invalid("not a Tipsy header"))?;
+    let counts: Vec<usize> = (2..5).map(|i| count(8 + 4 * i, big).unwrap()).collect();
+
+    // Checked, as the counts can overflow a 32-bit usize
+    let body_bytes = counts
+        .iter()
+        .zip(TIPSY_FLOATS)
+        .try_fold(0usize, |sum, (&n, floats)| {
+            n.checked_mul(floats * 4)?.checked_add(sum)
+        });
+    let mut offset = match body_bytes.and_then(|bytes| data.len().checked_sub(bytes)) {
+        Some(28) => 28,
+        Some(32) => 32,
+        _ => return Err(invalid("Tipsy file size doesn't match its header")),
+    };
+
+    let float = |offset: usize| f32::from_le_bytes(word(offset, big).unwrap());
+    let mut bodies = Vec::with_capacity(counts.iter().sum());
+    for (&n, floats) in counts.iter().zip(TIPSY_FLOATS) {
+        for _ in 0..n {
+            let [mass, x, y, _, vx, vy] = [0, 1, 2, 3, 4, 5].map(|i| float(offset + 4 * i));
+            if mass < 0.0 || !mass.is_finite() {
+                return Err(invalid("Tipsy particle with an invalid mass"));
+            }
+            let mut body = Body::new(Vec2::new(x, y), Vec2::new(vx, vy), mass, mass.cbrt());
+            body.id = bodies.len() as u64;
+            bodies.push(body);
+            offset += floats * 4;
+        }
+    }
+
+    Ok(bodies)
+}
+
+/// One body of the files `save_json` writes.
+#[derive(Serialize, Deserialize)]
+struct JsonBody {
+    id: u64,
+    x: f32,
+    y: f32,
+    vx: f32,
+    vy: f32,
+    mass: f32,
+    radius: f32,
+    color: [u8; 4],
+}
+
+/// Writes `bodies` as a JSON array of `{id, x, y, vx, vy, mass, radius,
+/// color}` objects, meant to be read and edited by hand. Charges, sinks and
+/// pins aren't kept, unlike the binary formats.
+pub fn save_json(path: impl AsRef<Path>, bodies: &[Body]) -> io::Result<()> {
+    let records: Vec<JsonBody> = bodies
+        .iter()
+        .map(|body| JsonBody {
+            id: body.id,
+            x: body.pos.x,
+            y: body.pos.y,
+            vx: body.vel.x,
+            vy: body.vel.y,
+            mass: body.mass,
+            radius: body.radius,
+            color: body.color,
+        })
+        .collect();
+    let text = serde_json::to_string_pretty(&records)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    fs::write(path, text)
+}
+
+/// Reads bodies written by `save_json`. Malformed files are an error naming
+/// the path, line and column.
+pub fn load_json(path: impl AsRef<Path>) -> io::Result<Vec<Body>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    let records: Vec<JsonBody> = serde_json::from_str(&text).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), err),
+        )
+    })?;
+    Ok(records
+        .into_iter()
+        .map(|record| {
+            let pos = Vec2::new(record.x, record.y);
+            let vel = Vec2::new(record.vx, record.vy);
+            let mut body = Body::new(pos, vel, record.mass, record.radius);
+            body.id = record.id;
+            body.color = record.color;
+            body
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quadtree::Quad;
+
+    #[test]
+    fn uniform_disc_is_reproducible_per_seed() {
+        let options = DiscOptions::default();
+        let positions = |seed| -> Vec<Vec2> {
+            uniform_disc(200, seed, &options)
+                .iter()
+                .map(|body| body.pos)
+                .collect()
+        };
+        assert_eq!(positions(1), positions(1));
+        assert_ne!(positions(1), positions(2));
+    }
+
+    #[test]
+    fn uniform_box_fills_the_box_at_rest() {
+        let half_size = 50.0;
+        let bodies = uniform_box(1000, half_size, 11, None);
+        assert_eq!(bodies.len(), 1000);
+
+        // Recentering shifts the whole box by the sample's centre of mass,
+        // a fraction of a percent of its size for this many bodies
+        assert!(Quad::new_containing(&bodies).size <= 2.0 * half_size);
+        for body in &bodies {
+            let (x, y) = (body.pos.x.abs(), body.pos.y.abs());
+            assert!(x <= 1.05 * half_size && y <= 1.05 * half_size);
+            assert!(body.vel.mag() <= 1e-6, "{:?}", body.vel);
+        }
+    }
+
+    #[test]
+    fn recentering_zeroes_the_centroid_and_momentum() {
+        let mut bodies = vec![
+            Body::new(Vec2::new(10.0, 4.0), Vec2::new(1.0, 0.5), 3.0, 1.0),
+            Body::new(Vec2::new(12.0, -1.0), Vec2::new(-0.25, 2.0), 1.0, 1.0),
+            Body::new(Vec2::new(7.0, 9.0), Vec2::new(0.5, 0.0), 0.5, 1.0),
+        ];
+        let separation = bodies[1].pos - bodies[0].pos;
+        recenter_and_zero_momentum(&mut bodies);
+
+        let (mut mass, mut pos, mut momentum) = (0.0, Vec2::zero(), Vec2::zero());
+        for body in &bodies {
+            mass += body.mass;
+            pos += body.pos * body.mass;
+            momentum += body.vel * body.mass;
+        }
+        assert!((pos / mass).mag() <= 1e-5, "centroid {:?}", pos / mass);
+        assert!(momentum.mag() <= 1e-5, "momentum {:?}", momentum);
+        // The bodies keep their places relative to each other
+        assert!((bodies[1].pos - bodies[0].pos - separation).mag() <= 1e-5);
+    }
+
+    #[test]
+    fn thermalize_hits_the_requested_temperature() {
+        let mut bodies = uniform_box(20000, 100.0, 4, None);
+        thermalize(&mut bodies, 2.5, 9);
+
+        let measured = temperature(&bodies);
+        assert!(
+            (measured - 2.5).abs() <= 0.05 * 2.5,
+            "measured {}",
+            measured
+        );
+        // Each component carries half of it, whatever the mass
+        let n = bodies.len() as f32;
+        let x: f32 = bodies
+            .iter()
+            .map(|b| b.mass * b.vel.x * b.vel.x)
+            .sum::<f32>()
+            / n;
+        assert!((x - 2.5).abs() <= 0.05 * 2.5, "x component {}", x);
+
+        let momentum = bodies.iter().fold(Vec2::zero(), |p, b| p + b.vel * b.mass);
+        let mass: f32 = bodies.iter().map(|b| b.mass).sum();
+        assert!(momentum.mag() <= 1e-3 * mass);
+    }
+
+    #[test]
+    fn precise_field_sum_has_less_round_off() {
+        let mut rng = fastrand::Rng::with_seed(5);
+        let terms: Vec<Vec2> = (0..100_000)
+            .map(|_| Vec2::new(rng.f32(), 0.5 * rng.f32()))
+            .collect();
+        let exact = terms.iter().fold((0.0f64, 0.0f64), |(x, y), term| {
+            (x + term.x as f64, y + term.y as f64)
+        });
+
+        let error = |precise| {
+            let mut sum = FieldSum::new(precise);
+            for &term in &terms {
+                sum.add(term);
+            }
+            let total = sum.total();
+            (total.x as f64 - exact.0).abs() + (total.y as f64 - exact.1).abs()
+        };
+        let (fast, precise) = (error(false), error(true));
+        assert!(precise <= 1e-6 * exact.0, "f64 sum is off by {}", precise);
+        assert!(
+            fast > 10.0 * precise,
+            "f32 {} against f64 {}",
+            fast,
+            precise
+        );
+    }
+
+    #[test]
+    fn disc_rotation_sense_and_dispersion() {
+        let disc = |rotation, dispersion| {
+            let options = DiscOptions {
+                rotation,
+                dispersion,
+                ..Default::default()
+            };
+            uniform_disc(2000, 3, &options)
+        };
+        let spin = |bodies: &[Body]| -> f32 {
+            bodies
+                .iter()
+                .map(|body| body.mass * body.pos.wedge(body.vel).xy)
+                .sum()
+        };
+        // Mean fraction of each orbiting body's speed that points radially
+        let radial = |bodies: &[Body]| -> f32 {
+            let orbiting: Vec<&Body> = bodies.iter().filter(|body| !body.is_sink).collect();
+            orbiting
+                .iter()
+                .map(|body| body.vel.dot(body.pos.normalized()).abs() / body.vel.mag())
+                .sum::<f32>()
+                / orbiting.len() as f32
+        };
+
+        let clockwise = disc(Rotation::Clockwise, 0.0);
+        let counter = disc(Rotation::CounterClockwise, 0.0);
+        assert!(spin(&clockwise) < 0.0 && spin(&counter) > 0.0);
+        // Zeroing the net momentum leaves a cold disc only slightly radial
+        assert!(radial(&clockwise) < 0.05);
+
+        let hot = disc(Rotation::Clockwise, 0.5);
+        assert!(
+            radial(&hot) > 0.15,
+            "hot disc is only {} radial",
+            radial(&hot)
+        );
+        assert!(spin(&hot) < 0.0);
+    }
+
+    #[test]
+    fn shuffle_is_reproducible_and_keeps_every_body() {
+        let bodies = uniform_box(300, 50.0, 2, None);
+        let ids = |bodies: &[Body]| -> Vec<u64> { bodies.iter().map(|body| body.id).collect() };
+        let shuffled = |seed| {
+            let mut copy = bodies.clone();
+            shuffle_bodies(&mut copy, seed);
+            copy
+        };
+
+        let (a, b, c) = (shuffled(7), shuffled(7), shuffled(8));
+        assert_eq!(ids(&a), ids(&b));
+        assert_ne!(ids(&a), ids(&c));
+        assert_ne!(ids(&a), ids(&bodies));
+
+        let mut sorted = a.clone();
+        sorted.sort_by_key(|body| body.id);
+        for (body, original) in sorted.iter().zip(&bodies) {
+            assert_eq!(
+                (body.id, body.pos, body.vel),
+                (original.id, original.pos, original.vel)
+            );
+        }
+    }
+
+    /// A Tipsy file with one dark matter and one star particle.
+    fn tipsy_file(big: bool, padded: bool) -> Vec<u8> {
+        let order = |mut bytes: [u8; 4]| {
+            if big {
+                bytes.reverse();
+            }
+            bytes
+        };
+        let mut data = Vec::new();
+        data.extend([0; 8]);
+        for count in [2u32, 3, 0, 1, 1] {
+            data.extend(order(count.to_le_bytes()));
+        }
+        if padded {
+            data.extend([0; 4]);
+        }
+        let dark = [2.0f32, 1.0, -3.0, 7.0, 0.5, 0.25, 9.0, 0.1, 0.0];
+        let star = [8.0f32, -4.0, 6.0, 1.0, -1.0, 2.0, 3.0, 0.0, 0.0, 0.1, 0.0];
+        for value in dark.into_iter().chain(star) {
+            data.extend(order(value.to_le_bytes()));
+        }
+        data
+    }
+
+    #[test]
+    fn load_tipsy_reads_a_handcrafted_file() {
+        let path = std::env::temp_dir().join("barnes-hut-tipsy-test.bin");
+        for (big, padded) in [(true, true), (false, false)] {
+            fs::write(&path, tipsy_file(big, padded)).unwrap();
+            let bodies = load_tipsy(&path).unwrap();
+            assert_eq!(bodies.len(), 2);
+            let (dark, star) = (&bodies[0], &bodies[1]);
+            assert_eq!(
+                (dark.id, dark.mass, dark.pos, dark.vel),
+                (0, 2.0, Vec2::new(1.0, -3.0), Vec2::new(0.5, 0.25))
+            );
+            assert_eq!(
+                (star.id, star.mass, star.pos, star.vel),
+                (1, 8.0, Vec2::new(-4.0, 6.0), Vec2::new(-1.0, 2.0))
+            );
+            assert!((star.radius - 2.0).abs() < 1e-6);
+        }
+
+        let mut truncated = tipsy_file(true, true);
+        // Cut off mid-float, as 4 bytes less would just read as unpadded
+        truncated.truncate(truncated.len() - 2);
+        fs::write(&path, truncated).unwrap();
+        assert!(load_tipsy(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn json_export_round_trips() {
+        let mut bodies = uniform_box(50, 20.0, 6, Some(100.0));
+        for (i, body) in bodies.iter_mut().enumerate() {
+            body.vel = Vec2::new(i as f32 * 0.1, -1.0 / (i + 1) as f32);
+            body.color = [i as u8, 0x80, 0xff - i as u8, 0xff];
+        }
+        let path = std::env::temp_dir().join("barnes-hut-json-test.json");
+        save_json(&path, &bodies).unwrap();
+        let loaded = load_json(&path).unwrap();
+        assert_eq!(loaded.len(), bodies.len());
+        for (a, b) in loaded.iter().zip(&bodies) {
+            assert_eq!((a.id, a.pos, a.vel), (b.id, b.pos, b.vel));
+            assert_eq!((a.mass, a.radius, a.color), (b.mass, b.radius, b.color));
+        }
+
+        fs::write(&path, "[{\"id\": 0, \"x\": 1.0").unwrap();
+        let err = load_json(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("barnes-hut-json-test.json"));
+    }
+
+    #[test]
+    fn jitter_is_bounded_and_reproducible() {
+        let mut original = uniform_box(500, 50.0, 1, None);
+        original[0].fixed = true;
+        let jittered = |amplitude, seed| {
+            let mut bodies = original.clone();
+            jitter(&mut bodies, amplitude, seed);
+            bodies.iter().map(|body| body.pos).collect::<Vec<Vec2>>()
+        };
+        let positions: Vec<Vec2> = original.iter().map(|body| body.pos).collect();
+
+        assert_eq!(jittered(0.0, 3), positions);
+        let a = jittered(0.5, 3);
+        assert_eq!(a, jittered(0.5, 3));
+        assert_ne!(a, jittered(0.5, 4));
+        assert_eq!(a[0], positions[0], "pinned bodies stay put");
+        let offsets: Vec<f32> = a
+            .iter()
+            .zip(&positions)
+            .map(|(a, b)| (*a - *b).mag())
+            .collect();
+        assert!(offsets.iter().all(|&offset| offset <= 0.5 * (1.0 + 1e-5)));
+        assert!(offsets[1..].iter().any(|&offset| offset > 0.25));
+    }
+
+    #[test]
+    fn elliptical_disc_has_the_requested_axis_ratio() {
+        let disc = |seed| elliptical_disc(5000, 0.4, seed, Some(100.0), &WarmStart::default());
+        // √(Σy² / Σx²) over the disc, leaving out the central mass
+        let bodies = disc(6);
+        let (xx, yy) = bodies
+            .iter()
+            .filter(|body| !body.is_sink)
+            .fold((0.0, 0.0), |(xx, yy), body| {
+                (xx + body.pos.x * body.pos.x, yy + body.pos.y * body.pos.y)
+            });
+        let ratio = (yy / xx).sqrt();
+        assert!((ratio - 0.4).abs() < 0.02, "axis ratio {}", ratio);
+
+        let positions =
+            |bodies: Vec<Body>| -> Vec<Vec2> { bodies.iter().map(|body| body.pos).collect() };
+        assert_eq!(positions(disc(6)), positions(bodies));
+        assert_ne!(positions(disc(7)), positions(disc(6)));
+    }
+
+    #[test]
+    fn warm_start_moves_only_an_unpinned_center() {
+        let disc = |pin_center, velocity| {
+            let options = DiscOptions {
+                pin_center,
+                warm_start: WarmStart {
+                    offset: Vec2::new(3.0, -2.0),
+                    velocity,
+                },
+                ..Default::default()
+            };
+            uniform_disc(500, 8, &options)
+        };
+        let velocity = Vec2::new(0.5, 0.2);
+
+        let pinned = disc(true, velocity);
+        let center = pinned.iter().find(|body| body.is_sink).unwrap();
+        assert_eq!(center.vel, Vec2::zero());
+
+        // Zeroing the momentum shifts every velocity alike, so the centre
+        // moves by `velocity` against the rest of the disc
+        let relative = |bodies: &[Body]| {
+            let center = bodies.iter().find(|body| body.is_sink).unwrap();
+            let other = bodies.iter().find(|body| !body.is_sink).unwrap();
+            center.vel - other.vel
+        };
+        let gained = relative(&disc(false, velocity)) - relative(&disc(false, Vec2::zero()));
+        assert!((gained - velocity).mag() < 1e-3, "{:?}", gained);
+    }
+
+    #[test]
+    fn load_tipsy_rejects_counts_past_the_file() {
+        // 2^28 gas particles take 3 * 2^32 bytes, which a 32-bit usize would
+        // wrap to none at all
+        let gas = 1u32 << 28;
+        let mut data = vec![0; 8];
+        for count in [gas, 3, gas, 0, 0] {
+            data.extend(count.to_be_bytes());
+        }
+        let path = std::env::temp_dir().join("barnes-hut-tipsy-overflow-test.bin");
+        fs::write(&path, data).unwrap();
+        assert!(load_tipsy(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
 }
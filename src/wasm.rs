@@ -0,0 +1,37 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{Settings, Simulation};
+
+/// Minimal browser bindings, rendering is left to the page.
+#[wasm_bindgen]
+pub struct WasmSimulation {
+    simulation: Simulation,
+}
+
+#[wasm_bindgen]
+impl WasmSimulation {
+    #[wasm_bindgen(constructor)]
+    pub fn new(body_count: usize, seed: u64) -> Self {
+        let settings = Settings {
+            body_count,
+            seed,
+            ..Default::default()
+        };
+        Self {
+            simulation: Simulation::new(&settings),
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.simulation.step();
+    }
+
+    /// Positions of all bodies as interleaved `x, y` pairs.
+    pub fn positions(&self) -> Vec<f32> {
+        self.simulation
+            .bodies()
+            .iter()
+            .flat_map(|body| [body.pos.x, body.pos.y])
+            .collect()
+    }
+}